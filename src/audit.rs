@@ -22,7 +22,7 @@ use tempfile::NamedTempFile;
 use crate::constants::{render_core_standing_orders, CORE_STANDING_ORDERS_VERSION, STANDING_ORDERS_AUDIT_PROMPT_TEMPLATE};
 use crate::llm::LlmToolChain;
 use crate::logger::Logger;
-use crate::runner::{log_message, log_warning, stream_outputs};
+use crate::runner::{log_message, log_warning, invoke_streamed};
 
 pub enum AuditTarget {
     File {
@@ -114,8 +114,7 @@ pub fn run_standing_orders_audit(
         .replace("{orders_file}", &orders_file_path)
         .replace("{orders_current}", &orders_current_path);
 
-    let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt_body, logger)?;
-    stream_outputs("audit", &stdout, &stderr, logger);
+    let (stdout, _stderr) = invoke_streamed("audit", &prompt_body, tool_chain, logger)?;
 
     let mut rendered = stdout;
     if !rendered.ends_with('\n') {
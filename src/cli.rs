@@ -15,8 +15,14 @@
 use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
-use crate::constants::{DEFAULT_COMPLETION_TOKEN, DEFAULT_CONTROLLER_PROMPT};
+use crate::constants::{
+    DEFAULT_COMPLETION_TOKEN, DEFAULT_CONTROLLER_PROMPT, DEFAULT_GATE_TIMEOUT_SECONDS,
+    DEFAULT_IDLE_TIMEOUT_SECONDS, DEFAULT_PROBE_TIMEOUT_SECONDS, DEFAULT_SESSION_MAX_TURNS,
+    DEFAULT_TIMEOUT_SECONDS,
+};
+use crate::llm::ToolStrategy;
 use crate::prompts;
+use crate::stall::StallAction;
 
 #[derive(Parser)]
 #[command(name = "afkcode")]
@@ -77,6 +83,14 @@ pub enum Commands {
         #[arg(long, default_value_t = 15)]
         sleep_seconds: u64,
 
+        /// Kill an LLM invocation after this many seconds (0 disables)
+        #[arg(long, default_value_t = DEFAULT_TIMEOUT_SECONDS)]
+        timeout_seconds: u64,
+
+        /// Kill an LLM invocation after this many seconds without output (0 disables)
+        #[arg(long, default_value_t = DEFAULT_IDLE_TIMEOUT_SECONDS)]
+        idle_timeout_seconds: u64,
+
         /// Loop mode (worker-only default, or controller/worker alternation)
         #[arg(long, value_enum, default_value_t = RunMode::Worker)]
         mode: RunMode,
@@ -113,7 +127,8 @@ pub enum Commands {
         #[arg(long)]
         codex_model: Option<String>,
 
-        /// Number of parallel LLM instances
+        /// Number of parallel LLM instances. They share one working tree, so
+        /// with more than one, commits aren't attributed to turns
         #[arg(long, default_value_t = 1)]
         num_instances: usize,
 
@@ -141,7 +156,7 @@ pub enum Commands {
         #[arg(long)]
         verifier_prompt: Option<PathBuf>,
 
-        /// Tools for verifier (defaults to --tools). Uses default models, ignoring --claude-model etc.
+        /// Tools for the verifier (defaults to the [roles.verifier] tools, then --tools)
         #[arg(long)]
         verifier_tools: Option<String>,
 
@@ -152,6 +167,86 @@ pub enum Commands {
         /// Maximum number of verify/work spirals (default: 5)
         #[arg(long, default_value_t = 5)]
         max_spirals: usize,
+
+        /// Stop once reported or estimated spend reaches this many USD
+        #[arg(long)]
+        max_cost: Option<f64>,
+
+        /// Stop once this many tokens have been used
+        #[arg(long)]
+        max_tokens: Option<u64>,
+
+        /// Stop after this many worker turns (across all instances)
+        #[arg(long)]
+        max_iterations: Option<usize>,
+
+        /// Stop starting turns after this time: a duration (8h, 1h30m),
+        /// HH:MM, "YYYY-MM-DD HH:MM" or an RFC 3339 timestamp
+        #[arg(long)]
+        deadline: Option<String>,
+
+        /// Only start turns within these daily windows, pausing in between
+        /// (e.g. "22:00-06:00" or "00:00-07:00,19:00-23:59")
+        #[arg(long)]
+        active_hours: Option<String>,
+
+        /// Escalate after this many worker turns in a row with no new commit,
+        /// checklist change or diff change (0 disables)
+        #[arg(long, default_value_t = 0)]
+        stall_threshold: usize,
+
+        /// Escalation steps for a stalled worker, one per threshold
+        /// (default: switch-tool,nudge,groom,block,stop)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        stall_ladder: Option<Vec<StallAction>>,
+
+        /// Commit changes a turn leaves uncommitted, naming the assigned item
+        /// and iteration. Single instance only: ignored with --num-instances
+        /// above 1
+        #[arg(long)]
+        auto_commit: bool,
+
+        /// Shell command run after every worker turn (e.g. "cargo test
+        /// --workspace"); while it fails, its output leads the next prompt and
+        /// the run cannot complete
+        #[arg(long)]
+        gate_command: Option<String>,
+
+        /// Wall-clock limit for one run of the gate command (0 disables)
+        #[arg(long, default_value_t = DEFAULT_GATE_TIMEOUT_SECONDS)]
+        gate_timeout_seconds: u64,
+
+        /// Run Claude, Codex and Gemini in their JSON event-stream modes and
+        /// check stop tokens and rate limits against the final message only
+        #[arg(long)]
+        structured_output: bool,
+
+        /// Continue the previous agent session on each worker turn instead
+        /// of starting a new one (Claude, Codex, Gemini; implies --structured-output)
+        #[arg(long)]
+        resume_sessions: bool,
+
+        /// Worker turns per resumed session before starting fresh (0 for no limit)
+        #[arg(long, default_value_t = DEFAULT_SESSION_MAX_TURNS)]
+        session_max_turns: usize,
+
+        /// Record every prompt/response exchange to this JSONL cassette
+        #[arg(long)]
+        record_cassette: Option<PathBuf>,
+
+        /// Answer every prompt from a recorded cassette instead of running
+        /// the tools (single instance only)
+        #[arg(long)]
+        replay_cassette: Option<PathBuf>,
+
+        /// How each invocation picks its starting tool from --tools
+        #[arg(long, value_enum, default_value_t = ToolStrategy::Priority)]
+        tool_strategy: ToolStrategy,
+
+        /// Give each parallel instance a different primary tool (instance 0
+        /// starts with the first tool, instance 1 with the second, ...)
+        #[arg(long)]
+        spread_instances: bool,
     },
 
     /// Initialize a new bare checklist with standing orders
@@ -272,4 +367,38 @@ pub enum Commands {
         #[arg(long)]
         codex_model: Option<String>,
     },
+
+    /// Check tools, checklist, git and wake lock before an unattended run
+    Doctor {
+        /// Checklist file to validate
+        checklist: Option<PathBuf>,
+
+        /// Directory of AGENTS.md files to validate instead of a single checklist
+        #[arg(long, conflicts_with = "checklist")]
+        checklist_dir: Option<PathBuf>,
+
+        /// Comma-separated list of LLM tools to check
+        #[arg(long, default_value = "gemini,codex,claude")]
+        tools: String,
+
+        /// Model to use for Gemini CLI
+        #[arg(long)]
+        gemini_model: Option<String>,
+
+        /// Model to use for Claude CLI
+        #[arg(long)]
+        claude_model: Option<String>,
+
+        /// Model to use for Codex CLI
+        #[arg(long)]
+        codex_model: Option<String>,
+
+        /// Don't send each tool a test prompt (skips the auth and model checks)
+        #[arg(long)]
+        no_probe: bool,
+
+        /// Wall-clock limit for each tool's test prompt in seconds
+        #[arg(long, default_value_t = DEFAULT_PROBE_TIMEOUT_SECONDS)]
+        probe_timeout: u64,
+    },
 }
@@ -15,45 +15,93 @@
 use anyhow::{Context, Result};
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::time::Duration;
 
 use crate::cli::RunMode;
 use crate::constants::{render_core_standing_orders, DEFAULT_COMPLETION_TOKEN};
-use crate::llm::{LlmToolChain, ModelConfig};
+use crate::doctor::{self, DoctorOptions, Status};
+use crate::gate::Gate;
+use crate::git::CommitLedger;
+use crate::limits::RunLimits;
+use crate::llm::{LlmToolChain, ModelConfig, Role, UsageBudget, UsageLedger};
 use crate::logger::Logger;
 use crate::parallel::{self, ParallelConfig};
-use crate::runner::{run_controller_worker_loop, run_worker_loop, RunConfig};
+use crate::runner::{self, run_controller_worker_loop, run_worker_loop, RunConfig, SessionPolicy};
+use crate::stall::StallPolicy;
 use crate::wakelock::WakeLock;
 
-pub fn cmd_run(
-    checklist: PathBuf,
-    controller_prompt: String,
-    worker_prompt: String,
-    completion_token: String,
-    sleep_seconds: u64,
-    mode: RunMode,
-    skip_audit: bool,
-    audit_orders_path: Option<PathBuf>,
-    commit_audit: bool,
-    tools: String,
-    log_file: String,
-    model_config: ModelConfig,
-    shutdown_flag: Arc<AtomicBool>,
-    num_instances: usize,
-    warmup_delay: u64,
-    gimme_enabled: bool,
-    gimme_base_path: PathBuf,
-    items_per_instance: usize,
-    multi_checklist_mode: bool,
-    verify_enabled: bool,
-    verifier_prompt: Option<PathBuf>,
-    verifier_tools: Option<String>,
-    spiral_enabled: bool,
-    max_spirals: usize,
-) -> Result<()> {
+/// Settings for `run`, merged from the command line, the config file and the
+/// defaults
+pub struct RunOptions {
+    pub checklist: PathBuf,
+    pub controller_prompt: String,
+    pub worker_prompt: String,
+    pub completion_token: String,
+    pub sleep_seconds: u64,
+    pub mode: RunMode,
+    pub skip_audit: bool,
+    pub audit_orders_path: Option<PathBuf>,
+    pub commit_audit: bool,
+    pub tools: String,
+    pub log_file: String,
+    pub model_config: ModelConfig,
+    pub shutdown_flag: Arc<AtomicBool>,
+    pub num_instances: usize,
+    pub warmup_delay: u64,
+    pub gimme_enabled: bool,
+    pub gimme_base_path: PathBuf,
+    pub items_per_instance: usize,
+    pub multi_checklist_mode: bool,
+    pub verify_enabled: bool,
+    pub verifier_prompt: Option<PathBuf>,
+    pub verifier_tools: Option<String>,
+    pub spiral_enabled: bool,
+    pub max_spirals: usize,
+    pub usage_budget: UsageBudget,
+    pub session_policy: SessionPolicy,
+    pub run_limits: RunLimits,
+    pub stall_policy: StallPolicy,
+    pub auto_commit: bool,
+    pub gate: Option<Gate>,
+}
+
+pub fn cmd_run(options: RunOptions) -> Result<()> {
+    let RunOptions {
+        checklist,
+        controller_prompt,
+        worker_prompt,
+        completion_token,
+        sleep_seconds,
+        mode,
+        skip_audit,
+        audit_orders_path,
+        commit_audit,
+        tools,
+        log_file,
+        model_config,
+        shutdown_flag,
+        num_instances,
+        warmup_delay,
+        gimme_enabled,
+        gimme_base_path,
+        items_per_instance,
+        multi_checklist_mode,
+        verify_enabled,
+        verifier_prompt,
+        verifier_tools,
+        spiral_enabled,
+        max_spirals,
+        usage_budget,
+        session_policy,
+        run_limits,
+        stall_policy,
+        auto_commit,
+        gate,
+    } = options;
+
     // Acquire wake lock to prevent system sleep during LLM execution.
     // Uses OS-native facilities that are automatically released when the process exits,
     // even if forcibly killed (SIGKILL), so the system can still sleep afterward.
@@ -99,6 +147,35 @@ pub fn cmd_run(
         .context("Checklist path contains invalid UTF-8")?
         .to_string();
 
+    // Instances share one working tree, so neither commits nor leftovers can
+    // be told apart by turn
+    let shared_worktree = num_instances > 1;
+    if shared_worktree {
+        if auto_commit {
+            eprintln!("Warning: --auto-commit only applies to single-instance runs; disabled.");
+        }
+        eprintln!(
+            "Warning: {} instances share one working tree; commits are not attributed to turns.",
+            num_instances
+        );
+    }
+
+    // The log, each instance's and the verifier's, and the rate-limit state
+    let mut own_paths = vec![
+        PathBuf::from(&log_file),
+        PathBuf::from(format!("{}.verifier", log_file)),
+    ];
+    own_paths.extend((0..num_instances).map(|id| PathBuf::from(format!("{}.{}", log_file, id))));
+    own_paths.extend(
+        model_config
+            .rate_limit_state
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf),
+    );
+
+    let limits = Arc::new(run_limits);
+    let commits = CommitLedger::default();
     let run_config = RunConfig {
         checklist: checklist.clone(),
         checklist_path_str,
@@ -117,11 +194,21 @@ pub fn cmd_run(
         } else {
             None
         },
+        session_policy,
+        limits: limits.clone(),
+        stall_policy,
+        auto_commit: auto_commit && !shared_worktree,
+        shared_worktree,
+        own_paths,
+        commits: commits.clone(),
+        gate,
     };
 
+    let usage = UsageLedger::new(usage_budget);
+
     // Use parallel runner if num_instances > 1 OR if verify is enabled
     // (single-instance with verify still uses the parallel infrastructure for spiral loop)
-    if num_instances > 1 || verify_enabled {
+    let result = if num_instances > 1 || verify_enabled {
         let parallel_config = ParallelConfig {
             num_instances,
             warmup_delay: Duration::from_secs(warmup_delay),
@@ -137,21 +224,30 @@ pub fn cmd_run(
             verifier_tools,
             spiral_enabled,
             max_spirals,
+            usage: usage.clone(),
         };
-        return parallel::run_parallel(parallel_config);
-    }
-
-    // Single instance mode (original behavior)
-    let mut tool_chain = LlmToolChain::with_models(&tools, &model_config)?;
-
-    match run_config.mode {
-        RunMode::Worker => run_worker_loop(&run_config, &mut tool_chain, &mut logger)?,
-        RunMode::Controller => {
-            run_controller_worker_loop(&run_config, &mut tool_chain, &mut logger)?
+        parallel::run_parallel(parallel_config)
+    } else {
+        // Single instance mode (original behavior)
+        let mut tool_chain =
+            LlmToolChain::with_models(&tools, &model_config)?.with_usage_ledger(usage.clone());
+
+        match run_config.mode {
+            RunMode::Worker => run_worker_loop(&run_config, &mut tool_chain, &mut logger),
+            RunMode::Controller => {
+                run_controller_worker_loop(&run_config, &mut tool_chain, &mut logger)
+            }
         }
+    };
+
+    // Report totals even when the loop ended with an error
+    runner::log_message(&mut logger, &limits.summary());
+    if let Some(summary) = commits.summary() {
+        runner::log_message(&mut logger, &summary);
     }
+    runner::log_message(&mut logger, &usage.summary());
 
-    Ok(())
+    result
 }
 
 pub fn cmd_init(checklist: PathBuf, title: Option<String>, examples: bool) -> Result<()> {
@@ -223,7 +319,9 @@ Output ONLY the checklist content in Markdown format, nothing else.
     println!("Generating checklist...");
     let mut tool_chain = LlmToolChain::with_models(&tools, &model_config)?;
     let mut logger = None;
-    let (stdout, _stderr) = tool_chain.invoke_with_fallback(&generation_prompt, &mut logger)?;
+    let (stdout, _stderr) = tool_chain
+        .for_role(Role::Generate)
+        .invoke_with_fallback(&generation_prompt, &mut logger)?;
 
     if stdout.trim().is_empty() {
         anyhow::bail!("LLM returned empty response");
@@ -342,7 +440,9 @@ Do not include explanations, headers, or any other text. Just the checkbox items
     println!("Generating items...");
     let mut tool_chain = LlmToolChain::with_models(&tools, &model_config)?;
     let mut logger = None;
-    let (stdout, _stderr) = tool_chain.invoke_with_fallback(&batch_prompt, &mut logger)?;
+    let (stdout, _stderr) = tool_chain
+        .for_role(Role::Generate)
+        .invoke_with_fallback(&batch_prompt, &mut logger)?;
 
     let content = fs::read_to_string(&checklist)?;
     let mut new_content = content;
@@ -440,7 +540,9 @@ Update the checklist according to the instruction above. Output the COMPLETE upd
     println!("Updating checklist...");
     let mut tool_chain = LlmToolChain::with_models(&tools, &model_config)?;
     let mut logger = None;
-    let (stdout, _stderr) = tool_chain.invoke_with_fallback(&update_prompt, &mut logger)?;
+    let (stdout, _stderr) = tool_chain
+        .for_role(Role::Update)
+        .invoke_with_fallback(&update_prompt, &mut logger)?;
 
     // Verify standing orders are preserved
     let mut updated_content = stdout;
@@ -495,3 +597,17 @@ Update the checklist according to the instruction above. Output the COMPLETE upd
 
     Ok(())
 }
+
+pub fn cmd_doctor(options: DoctorOptions) -> Result<()> {
+    let checks = doctor::run_checks(&options);
+    print!("{}", doctor::render_table(&checks));
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} doctor check(s) failed", failed);
+    }
+    Ok(())
+}
@@ -14,9 +14,13 @@
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+use crate::llm::{EventFormat, PromptDelivery, ReasoningEffort};
+use crate::stall::StallAction;
+
 /// Configuration file structure
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Config {
@@ -26,6 +30,77 @@ pub struct Config {
     /// Sleep duration between LLM calls (in seconds)
     pub sleep_seconds: Option<u64>,
 
+    /// Wall-clock limit for a single LLM invocation in seconds (0 disables)
+    pub timeout_seconds: Option<u64>,
+
+    /// Kill an LLM invocation after this many seconds without output
+    /// (default: 1800, 0 disables)
+    pub idle_timeout_seconds: Option<u64>,
+
+    /// Retries on the same tool after a transient failure (empty output,
+    /// non-zero exit, timeout) before falling back (default: 2)
+    pub max_retries: Option<u32>,
+
+    /// Delay before the first retry in seconds, doubled for each one after (default: 5)
+    pub retry_delay_seconds: Option<u64>,
+
+    /// Run Claude, Codex and Gemini with JSON event-stream output
+    pub structured_output: Option<bool>,
+
+    /// Continue the previous agent session on each worker turn
+    pub resume_sessions: Option<bool>,
+
+    /// Worker turns per session before starting a fresh one (0 for no limit)
+    pub session_max_turns: Option<usize>,
+
+    /// Start a fresh session once the context reaches this many tokens
+    pub session_max_context_tokens: Option<u64>,
+
+    /// Stop the run once reported/estimated spend reaches this many USD
+    pub max_cost: Option<f64>,
+
+    /// Stop the run once this many tokens have been used
+    pub max_tokens: Option<u64>,
+
+    /// Stop the run after this many worker turns (across all instances)
+    pub max_iterations: Option<usize>,
+
+    /// Stop starting turns after this time: a duration from startup (8h,
+    /// 1h30m), HH:MM, "YYYY-MM-DD HH:MM" or an RFC 3339 timestamp
+    pub deadline: Option<String>,
+
+    /// Daily windows in which turns may start (e.g. "22:00-06:00"); the run
+    /// pauses outside them
+    pub active_hours: Option<String>,
+
+    /// Worker turns in a row without progress before escalating (0 disables)
+    pub stall_threshold: Option<usize>,
+
+    /// Escalation steps for a stalled worker: switch-tool, nudge, groom,
+    /// block and stop
+    pub stall_ladder: Option<Vec<StallAction>>,
+
+    /// Commit changes a turn leaves uncommitted. Ignored with more than one
+    /// instance, whose commits aren't attributed to turns.
+    pub auto_commit: Option<bool>,
+
+    /// Shell command run after every worker turn that must pass before the
+    /// run can complete (e.g. "cargo test --workspace")
+    pub gate_command: Option<String>,
+
+    /// Wall-clock limit for one run of the gate command (0 disables)
+    pub gate_timeout_seconds: Option<u64>,
+
+    /// Record every prompt/response exchange to this JSONL cassette
+    pub record_cassette: Option<PathBuf>,
+
+    /// How each invocation picks its starting tool: priority (default),
+    /// round-robin, weighted, or least-recently-rate-limited
+    pub tool_strategy: Option<String>,
+
+    /// Give each parallel instance a different primary tool
+    pub spread_instances: Option<bool>,
+
     /// Controller prompt template
     pub controller_prompt: Option<String>,
 
@@ -54,10 +129,21 @@ pub struct Config {
     pub gemini_model: Option<String>,
     pub claude_model: Option<String>,
     pub codex_model: Option<String>,
+    pub aider_model: Option<String>,
+    pub opencode_model: Option<String>,
+    pub cursor_model: Option<String>,
     
     /// Warp Agent API configuration
     pub warp_api_key: Option<String>,
     pub warp_model: Option<String>,
+    pub warp_api_base: Option<String>,
+    /// Seconds between Warp Agent task status polls (default: 5)
+    pub warp_poll_seconds: Option<u64>,
+
+    /// OpenAI-compatible chat-completions backend configuration
+    pub openai_base_url: Option<String>,
+    pub openai_model: Option<String>,
+    pub openai_api_key: Option<String>,
 
     /// Number of parallel LLM instances (default: 1)
     pub num_instances: Option<usize>,
@@ -73,6 +159,142 @@ pub struct Config {
 
     /// Number of work items each instance should check out (default: 1)
     pub gimme_items_per_instance: Option<usize>,
+
+    /// Per-tool settings keyed by tool name (`[tool.<name>]` tables)
+    #[serde(default)]
+    pub tool: HashMap<String, ToolConfig>,
+
+    /// Per-role tool routing keyed by role name (`[roles.<role>]` tables)
+    #[serde(default)]
+    pub roles: HashMap<String, RoleConfig>,
+}
+
+/// Tools and models for one role, read from a `[roles.<role>]` table.
+/// Roles without a table use the run's `tools` and models.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RoleConfig {
+    /// Tools for this role (comma-separated); defaults to the run's `tools`
+    pub tools: Option<String>,
+
+    /// Model per tool name for this role, overriding `<tool>_model` and the
+    /// tool table's `model`
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+
+    /// Reasoning effort for this role's prompts (minimal, low, medium or
+    /// high), overriding the tool's `reasoning_effort`
+    pub reasoning_effort: Option<ReasoningEffort>,
+}
+
+/// Settings for a single tool, read from a `[tool.<name>]` table.
+///
+/// A table with a `command` declares a user-defined CLI agent that can be
+/// listed in `tools` like any built-in. A custom definition whose name matches
+/// a built-in (e.g. `claude`) replaces that built-in. A table with a
+/// `replay_script` instead declares a scripted tool for dry runs.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolConfig {
+    /// Executable to run
+    pub command: Option<String>,
+
+    /// Argument template. `{prompt}` (argv mode), `{prompt_file}` (file mode)
+    /// and `{model}` are substituted; prompt placeholders are appended if absent.
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// How the prompt is handed to the tool: stdin (default), argv, or file
+    pub prompt_mode: Option<PromptDelivery>,
+
+    /// Model to request from the tool
+    pub model: Option<String>,
+
+    /// Flag used to pass the model (e.g. "--model")
+    pub model_flag: Option<String>,
+
+    /// Case-insensitive substrings in the output that indicate a rate limit
+    #[serde(default)]
+    pub rate_limit_patterns: Vec<String>,
+
+    /// Overrides the global `timeout_seconds` for this tool
+    pub timeout_seconds: Option<u64>,
+
+    /// Overrides the global `idle_timeout_seconds` for this tool
+    pub idle_timeout_seconds: Option<u64>,
+
+    /// Overrides the global `max_retries` for this tool
+    pub max_retries: Option<u32>,
+
+    /// Overrides the global `retry_delay_seconds` for this tool
+    pub retry_delay_seconds: Option<u64>,
+
+    /// Longest delay between retries of this tool (default: 60)
+    pub retry_max_delay_seconds: Option<u64>,
+
+    /// Reasoning effort (minimal, low, medium or high) for roles that don't
+    /// set their own; the tool's default when unset
+    pub reasoning_effort: Option<ReasoningEffort>,
+
+    /// Arguments a custom tool is given for a reasoning effort; `{effort}`
+    /// is substituted (e.g. `["--effort", "{effort}"]`)
+    #[serde(default)]
+    pub reasoning_args: Vec<String>,
+
+    /// Overrides the global `structured_output` for a built-in tool
+    pub structured_output: Option<bool>,
+
+    /// Event schema a custom tool prints (claude, codex or gemini); its
+    /// final message is then used for stop tokens and rate-limit checks
+    pub event_format: Option<EventFormat>,
+
+    /// Arguments that resume a previous session; `{session_id}` is substituted
+    #[serde(default)]
+    pub resume_args: Vec<String>,
+
+    /// USD per million input tokens, to estimate cost when the tool doesn't report it
+    pub input_price: Option<f64>,
+
+    /// USD per million output tokens
+    pub output_price: Option<f64>,
+
+    /// Relative share of invocations started with this tool under the
+    /// weighted strategy (default 1; 0 makes it a fallback only)
+    pub weight: Option<u32>,
+
+    /// Script of canned responses; a table with this and no `command`
+    /// declares a `replay` tool
+    pub replay_script: Option<PathBuf>,
+
+    /// Environment variables set for the tool's process (e.g.
+    /// `CLAUDE_CONFIG_DIR`); `{instance_id}` is substituted in values
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Working directory for the tool's process (default: afkcode's own);
+    /// `{instance_id}` is substituted
+    pub cwd: Option<PathBuf>,
+
+    /// Arguments appended to the tool's own (e.g. `--add-dir` for claude or
+    /// `--profile` for codex); `{instance_id}` is substituted
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+
+    /// Overrides for particular parallel instances, keyed by instance id
+    #[serde(default)]
+    pub instances: HashMap<String, ToolInstanceConfig>,
+}
+
+/// Process settings for one parallel instance of a tool, read from a
+/// `[tool.<name>.instances.<id>]` table. Variables are merged over the tool's
+/// `env`, `cwd` replaces the tool's, and `extra_args` are appended after its own.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolInstanceConfig {
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    pub cwd: Option<PathBuf>,
+
+    #[serde(default)]
+    pub extra_args: Vec<String>,
 }
 
 impl Config {
@@ -40,8 +40,59 @@ pub const CORE_STANDING_ORDERS_TEMPLATE: &str = r#"# STANDING ORDERS - DO NOT DE
    - If unsure which mode you're in, do NOT emit the token. Completing one task does NOT mean emitting the token.
 "#;
 
+/// Default wall-clock limit for one LLM invocation (0 disables). Off by
+/// default so long agent turns aren't cut short.
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 0;
+
+/// Default no-output limit for one LLM invocation (0 disables), so a hung
+/// agent is killed. Generous because several CLIs print nothing until the
+/// turn is finished.
+pub const DEFAULT_IDLE_TIMEOUT_SECONDS: u64 = 1800;
+
+/// Default wall-clock limit for one run of the gate command (0 disables)
+pub const DEFAULT_GATE_TIMEOUT_SECONDS: u64 = 1800;
+
+/// Lines of a failed gate's output shown to the next worker turn
+pub const GATE_TAIL_LINES: usize = 40;
+
+/// How long a rate-limited tool is squelched when it gave no reset hint
+pub const DEFAULT_RATE_LIMIT_SECONDS: u64 = 300;
+
+/// Retries on the same tool after a transient failure, before falling back
+pub const DEFAULT_MAX_RETRIES: u32 = 2;
+
+/// Delay before the first retry; doubled (with jitter) for each one after
+pub const DEFAULT_RETRY_DELAY_SECONDS: u64 = 5;
+
+/// Upper bound on the delay between retries
+pub const DEFAULT_RETRY_MAX_DELAY_SECONDS: u64 = 60;
+
+/// Worker turns per agent session before starting a fresh one (0 disables)
+pub const DEFAULT_SESSION_MAX_TURNS: usize = 10;
+
+/// Context size at which a resumed session is abandoned for a fresh one
+/// (0 disables)
+pub const DEFAULT_SESSION_MAX_CONTEXT_TOKENS: u64 = 150_000;
+
+/// Rate-limit squelch state, relative to the working directory
+pub const RATE_LIMIT_STATE_FILE: &str = ".afkcode/ratelimits.json";
+
+/// How long `afkcode doctor` waits for each tool's test prompt
+pub const DEFAULT_PROBE_TIMEOUT_SECONDS: u64 = 120;
+
+/// Default interval between Warp Agent task status polls
+pub const DEFAULT_WARP_POLL_SECONDS: u64 = 5;
+
+/// How long a Warp Agent task is polled when the tool has no wall-clock limit
+pub const DEFAULT_WARP_MAX_SECONDS: u64 = 3600;
+
+/// Time running tools get to exit after SIGTERM on a forced exit, before SIGKILL
+pub const SHUTDOWN_GRACE_SECONDS: u64 = 10;
+
 pub const WARP_AGENT_API_BASE: &str = "https://app.warp.dev/api/v1";
 
+pub const OPENAI_API_BASE: &str = "https://api.openai.com/v1";
+
 pub const STANDING_ORDERS_AUDIT_PROMPT_TEMPLATE: &str = r#"You are aligning the repository's Standing Orders for afkcode's worker-only mode.
 
 Goals:
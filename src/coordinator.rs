@@ -40,6 +40,12 @@ pub enum SubprocessResult {
     StopConfirmed,
     /// Shutdown due to external signal (Ctrl+C or coordinator).
     Shutdown,
+    /// Stopped because the run's cost or token budget was used up.
+    BudgetExceeded,
+    /// Stopped at the run's iteration cap or deadline.
+    LimitReached,
+    /// Stopped after making no progress through the stall escalation ladder.
+    Stalled,
     /// Error during execution.
     Error(String),
 }
@@ -0,0 +1,497 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Preflight checks for `afkcode doctor`.
+//!
+//! Everything an unattended run depends on is checked up front: each
+//! configured tool (installed, version, authenticated, model accepted, API
+//! key present), including those only used by routed roles, the checklist,
+//! the git repository and the wake lock.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::checklist::scan_all_checklists;
+use crate::gimme::{parser, MarkerType};
+use crate::llm::{InvokeTimeouts, LlmTool, LlmToolKind, ModelConfig, ReasoningEffort, Role};
+use crate::wakelock::WakeLock;
+
+/// Sent to each tool to check that it is authenticated and accepts its model
+const PROBE_PROMPT: &str = "Reply with the single word OK. Do not run any commands or edit any files.";
+
+/// How long `--version` may take
+const VERSION_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Tool errors that blame the model rather than the credentials
+static MODEL_ERROR_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(model\b.{0,60}\b(not found|does not exist|not exist|invalid|unknown|not supported|unsupported|not available|no access)|\b(invalid|unknown|unsupported) model)").unwrap()
+});
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Worth knowing, but a run would still work
+    Warn,
+    Fail,
+    /// Not checked
+    Skip,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+            Status::Skip => "SKIP",
+        }
+    }
+}
+
+/// One row of the doctor table
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked: a tool name, "checklist", "git" or "system"
+    pub subject: String,
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(subject: &str, name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            subject: subject.to_string(),
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// What `afkcode doctor` should check
+pub struct DoctorOptions {
+    pub tools: String,
+    pub model_config: ModelConfig,
+    pub checklist: Option<PathBuf>,
+    pub checklist_dir: Option<PathBuf>,
+    /// Send each tool a tiny prompt to check authentication and the model
+    pub probe: bool,
+    pub probe_timeout: Duration,
+}
+
+/// Run every check, in table order
+pub fn run_checks(options: &DoctorOptions) -> Vec<Check> {
+    let mut checks = Vec::new();
+    for name in options
+        .tools
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        check_tool(name, name, &options.model_config, options, &mut checks);
+    }
+    let mut roles: Vec<Role> = options.model_config.roles.keys().copied().collect();
+    roles.sort();
+    for role in roles {
+        check_role(role, options, &mut checks);
+    }
+    checks.push(check_checklist(
+        options.checklist.as_deref(),
+        options.checklist_dir.as_deref(),
+    ));
+    checks.extend(check_git());
+    checks.push(check_wake_lock());
+    checks
+}
+
+/// Check the tools a `[roles.<role>]` table adds, or gives another model
+fn check_role(role: Role, options: &DoctorOptions, checks: &mut Vec<Check>) {
+    let role_config = match options.model_config.for_role(role) {
+        Ok(Some(config)) => config,
+        Ok(None) => return,
+        Err(e) => {
+            let subject = format!("[roles.{}]", role);
+            checks.push(Check::new(&subject, "config", Status::Fail, format!("{:#}", e)));
+            return;
+        }
+    };
+    let models = &options.model_config.roles[&role].models;
+    let split = |tools: &str| -> Vec<String> {
+        tools
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let default_tools = split(&options.tools);
+    for name in split(options.model_config.get_tools_for_role(role, &options.tools)) {
+        if default_tools.contains(&name) && !models.contains_key(&name) {
+            continue;
+        }
+        let subject = format!("{} ({})", name, role);
+        check_tool(&name, &subject, &role_config, options, checks);
+    }
+}
+
+/// Check one tool, resolved with `model_config`, reporting it as `subject`
+fn check_tool(
+    name: &str,
+    subject: &str,
+    model_config: &ModelConfig,
+    options: &DoctorOptions,
+    checks: &mut Vec<Check>,
+) {
+    let tool = match LlmTool::resolve(name, model_config) {
+        Ok(tool) => tool,
+        Err(e) => {
+            checks.push(Check::new(subject, "config", Status::Fail, format!("{:#}", e)));
+            return;
+        }
+    };
+
+    match tool.kind() {
+        LlmToolKind::Replay => {
+            checks.push(Check::new(subject, "script", Status::Pass, "scripted responses load"));
+            return;
+        }
+        LlmToolKind::WarpAgent => {
+            if tool.api_key().is_none() {
+                checks.push(Check::new(
+                    subject,
+                    "api key",
+                    Status::Fail,
+                    "missing: set warp_api_key or WARP_API_KEY",
+                ));
+                return;
+            }
+            checks.push(Check::new(subject, "api key", Status::Pass, "set"));
+        }
+        LlmToolKind::OpenAi => {
+            let (status, detail) = match tool.api_key() {
+                Some(_) => (Status::Pass, "set"),
+                None => (Status::Warn, "not set (fine for local servers)"),
+            };
+            checks.push(Check::new(subject, "api key", status, detail));
+        }
+        _ => {}
+    }
+
+    if let Some(program) = tool.executable() {
+        match find_on_path(program) {
+            Some(path) => checks.push(Check::new(subject, "installed", Status::Pass, path.display().to_string())),
+            None => {
+                checks.push(Check::new(
+                    subject,
+                    "installed",
+                    Status::Fail,
+                    format!("{} not found on PATH", program),
+                ));
+                return;
+            }
+        }
+        checks.push(match tool.version(VERSION_TIMEOUT) {
+            Ok(version) => Check::new(subject, "version", Status::Pass, version),
+            Err(e) => Check::new(subject, "version", Status::Warn, format!("{:#}", e)),
+        });
+    }
+
+    let model = tool.model().unwrap_or("tool default").to_string();
+    if !options.probe {
+        checks.push(Check::new(subject, "auth", Status::Skip, "not probed (--no-probe)"));
+        checks.push(Check::new(subject, "model", Status::Skip, format!("{} (not probed)", model)));
+        return;
+    }
+
+    let probe = tool
+        .clone()
+        .with_timeouts(InvokeTimeouts {
+            wall_clock: Some(options.probe_timeout),
+            idle: None,
+        })
+        .with_default_reasoning_effort(Some(ReasoningEffort::Minimal));
+    let started = Instant::now();
+    let failure = match probe.invoke(PROBE_PROMPT) {
+        Err(e) => format!("{:#}", e),
+        Ok(output) => {
+            if let Some(limit) = probe.rate_limit(&output) {
+                checks.push(Check::new(
+                    subject,
+                    "auth",
+                    Status::Warn,
+                    match limit.retry_after {
+                        Some(wait) => format!("rate limited; resets in {}s", wait.as_secs()),
+                        None => "rate limited right now".to_string(),
+                    },
+                ));
+                checks.push(Check::new(subject, "model", Status::Skip, format!("{} (not probed)", model)));
+                return;
+            }
+            match output.exit_code {
+                Some(0) | None => {
+                    let elapsed = started.elapsed().as_secs_f64();
+                    checks.push(Check::new(
+                        subject,
+                        "auth",
+                        Status::Pass,
+                        format!("answered a test prompt in {:.1}s", elapsed),
+                    ));
+                    checks.push(Check::new(subject, "model", Status::Pass, model));
+                    return;
+                }
+                Some(code) => format!(
+                    "exited with {}: {}",
+                    code,
+                    first_line(&format!("{}\n{}", output.stderr, output.stdout))
+                ),
+            }
+        }
+    };
+
+    if MODEL_ERROR_PATTERN.is_match(&failure) {
+        checks.push(Check::new(subject, "auth", Status::Skip, "model rejected before auth could be confirmed"));
+        checks.push(Check::new(subject, "model", Status::Fail, format!("{}: {}", model, failure)));
+    } else {
+        checks.push(Check::new(subject, "auth", Status::Fail, failure));
+        checks.push(Check::new(subject, "model", Status::Skip, format!("{} (not probed)", model)));
+    }
+}
+
+fn check_checklist(checklist: Option<&Path>, checklist_dir: Option<&Path>) -> Check {
+    const SUBJECT: &str = "checklist";
+    if let Some(dir) = checklist_dir {
+        return match scan_all_checklists(dir) {
+            Ok(scan) if scan.total_files() == 0 => Check::new(
+                SUBJECT,
+                "parses",
+                Status::Fail,
+                format!("no AGENTS.md files under {}", dir.display()),
+            ),
+            Ok(scan) => Check::new(SUBJECT, "parses", Status::Pass, scan.summary()),
+            Err(e) => Check::new(SUBJECT, "parses", Status::Fail, format!("{:#}", e)),
+        };
+    }
+    let Some(path) = checklist else {
+        return Check::new(SUBJECT, "parses", Status::Skip, "no checklist given");
+    };
+    if !path.exists() {
+        return Check::new(
+            SUBJECT,
+            "parses",
+            Status::Warn,
+            format!("{} does not exist yet (run will create it)", path.display()),
+        );
+    }
+    match parser::parse_file(path) {
+        Ok(items) => {
+            let incomplete = items
+                .iter()
+                .filter(|item| MarkerType::from_marker(&item.marker).is_incomplete())
+                .count();
+            Check::new(
+                SUBJECT,
+                "parses",
+                Status::Pass,
+                format!("{} items, {} incomplete", items.len(), incomplete),
+            )
+        }
+        Err(e) => Check::new(
+            SUBJECT,
+            "parses",
+            Status::Fail,
+            format!("{}: {:#}", path.display(), e),
+        ),
+    }
+}
+
+/// Checks on the git repository in the working directory
+fn check_git() -> Vec<Check> {
+    const SUBJECT: &str = "git";
+    let toplevel = match git(&["rev-parse", "--show-toplevel"]) {
+        Ok(toplevel) => toplevel,
+        Err(e) => return vec![Check::new(SUBJECT, "repository", Status::Fail, e)],
+    };
+    let mut checks = vec![Check::new(SUBJECT, "repository", Status::Pass, toplevel)];
+
+    checks.push(match git(&["status", "--porcelain"]) {
+        Ok(status) if status.is_empty() => Check::new(SUBJECT, "clean", Status::Pass, "no uncommitted changes"),
+        Ok(status) => Check::new(
+            SUBJECT,
+            "clean",
+            Status::Fail,
+            format!("{} uncommitted change(s)", status.lines().count()),
+        ),
+        Err(e) => Check::new(SUBJECT, "clean", Status::Fail, e),
+    });
+
+    // Agents and the audit commit as this identity
+    checks.push(match git(&["var", "GIT_COMMITTER_IDENT"]) {
+        Ok(ident) => {
+            // Drop the trailing timestamp and timezone
+            let who = ident.rsplitn(3, ' ').last().unwrap_or(&ident).to_string();
+            Check::new(SUBJECT, "identity", Status::Pass, who)
+        }
+        Err(_) => Check::new(
+            SUBJECT,
+            "identity",
+            Status::Fail,
+            "user.name/user.email not configured; commits will fail",
+        ),
+    });
+    checks
+}
+
+/// Run git in the working directory, returning trimmed stdout
+fn git(args: &[&str]) -> std::result::Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(first_line(&String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn check_wake_lock() -> Check {
+    match WakeLock::acquire() {
+        Ok(_guard) => Check::new("system", "wake lock", Status::Pass, "can inhibit sleep"),
+        // Runs continue without one, so this is only a warning
+        Err(e) => Check::new("system", "wake lock", Status::Warn, format!("{:#}", e)),
+    }
+}
+
+/// Locate `program` the way spawning it would: paths are used as given,
+/// bare names are searched for on PATH
+fn find_on_path(program: &str) -> Option<PathBuf> {
+    let candidate = Path::new(program);
+    if candidate.components().count() > 1 {
+        return is_executable(candidate).then(|| candidate.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+/// First non-empty line of `text`, shortened for the table
+fn first_line(text: &str) -> String {
+    const MAX_CHARS: usize = 120;
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("no output");
+    if line.chars().count() > MAX_CHARS {
+        format!("{}...", line.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Format the checks as an aligned table followed by a summary line
+pub fn render_table(checks: &[Check]) -> String {
+    let label = |check: &Check| format!("{} {}", check.subject, check.name);
+    let width = checks
+        .iter()
+        .map(|check| label(check).len())
+        .max()
+        .unwrap_or(0)
+        .max("CHECK".len());
+
+    let mut table = format!("{:<width$}  {:<6}  DETAIL\n", "CHECK", "STATUS");
+    for check in checks {
+        table.push_str(&format!(
+            "{:<width$}  {:<6}  {}\n",
+            label(check),
+            check.status.label(),
+            check.detail
+        ));
+    }
+    let count = |status: Status| checks.iter().filter(|check| check.status == status).count();
+    table.push_str(&format!(
+        "\n{} passed, {} warning(s), {} failed, {} skipped\n",
+        count(Status::Pass),
+        count(Status::Warn),
+        count(Status::Fail),
+        count(Status::Skip)
+    ));
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render_table_aligns_columns() {
+        let checks = vec![
+            Check::new("claude", "installed", Status::Pass, "/usr/bin/claude"),
+            Check::new("git", "clean", Status::Fail, "2 uncommitted change(s)"),
+        ];
+        let table = render_table(&checks);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines[0], "CHECK             STATUS  DETAIL");
+        assert_eq!(lines[1], "claude installed  PASS    /usr/bin/claude");
+        assert_eq!(lines[2], "git clean         FAIL    2 uncommitted change(s)");
+        assert!(table.ends_with("1 passed, 0 warning(s), 1 failed, 0 skipped\n"));
+    }
+
+    #[test]
+    fn test_model_errors_are_told_apart_from_auth_errors() {
+        assert!(MODEL_ERROR_PATTERN.is_match("Error: model 'gpt-9' not found"));
+        assert!(MODEL_ERROR_PATTERN.is_match("API Error: invalid model: opus-9"));
+        assert!(!MODEL_ERROR_PATTERN.is_match("Invalid API key. Please run /login"));
+    }
+
+    #[test]
+    fn test_checklist_check() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("checklist.md");
+        assert_eq!(check_checklist(Some(&path), None).status, Status::Warn);
+
+        fs::write(&path, "# Tasks\n- [ ] One\n- [x] Two\n- [~] Three\n").unwrap();
+        let check = check_checklist(Some(&path), None);
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.detail, "3 items, 2 incomplete");
+
+        assert_eq!(check_checklist(None, Some(dir.path())).status, Status::Fail);
+        assert_eq!(check_checklist(None, None).status, Status::Skip);
+    }
+
+    #[test]
+    fn test_find_on_path_accepts_explicit_paths() {
+        assert!(find_on_path("/definitely/not/a/tool").is_none());
+        assert!(find_on_path("afkcode-no-such-tool").is_none());
+    }
+}
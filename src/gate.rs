@@ -0,0 +1,147 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Build/test gate.
+//!
+//! A `gate_command` (e.g. `cargo test --workspace`) runs after every worker
+//! turn. When it fails, the tail of its output leads the next turn's prompt,
+//! and neither a stop token nor scanner completion ends the run until it
+//! passes, so "complete" means the project builds and its tests pass.
+
+use std::collections::VecDeque;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::constants::GATE_TAIL_LINES;
+use crate::llm::{self, OutputStream};
+
+/// The command every worker turn has to leave passing
+#[derive(Debug, Clone)]
+pub struct Gate {
+    command: String,
+    timeout: Option<Duration>,
+}
+
+/// Why the gate didn't pass, with the end of its output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GateFailure {
+    pub summary: String,
+    pub tail: String,
+}
+
+/// The gate's result for the tree as the last turn left it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum GateStatus {
+    /// Not run since the last turn
+    #[default]
+    Unknown,
+    Passed,
+    Failed(GateFailure),
+}
+
+impl Gate {
+    /// A gate running `command` in the shell, killed after `timeout_seconds`
+    /// (0 for no limit)
+    pub fn new(command: String, timeout_seconds: u64) -> Self {
+        Self {
+            command,
+            timeout: (timeout_seconds > 0).then(|| Duration::from_secs(timeout_seconds)),
+        }
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Run the command in the working directory; `Ok` if it exited 0
+    pub fn run(&self) -> Result<Duration, GateFailure> {
+        let mut cmd = shell_command(&self.command);
+        let mut tail = VecDeque::with_capacity(GATE_TAIL_LINES);
+        let mut sink = |_stream: OutputStream, line: &str| {
+            if tail.len() == GATE_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line.to_string());
+        };
+        let started = Instant::now();
+        let result = llm::run_command(&mut cmd, Some(&mut sink), self.timeout);
+        let elapsed = started.elapsed();
+
+        let summary = match result {
+            Ok(output) if output.exit_code == Some(0) => return Ok(elapsed),
+            Ok(output) => match output.exit_code {
+                Some(code) => format!("exited with status {}", code),
+                None => "was killed by a signal".to_string(),
+            },
+            Err(_) if self.timeout.is_some_and(|timeout| elapsed >= timeout) => {
+                format!("timed out after {}s", elapsed.as_secs())
+            }
+            Err(e) => format!("could not be run: {:#}", e),
+        };
+        Err(GateFailure {
+            summary,
+            tail: Vec::from(tail).join("\n"),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate_passes_and_fails_with_output_tail() {
+        assert!(Gate::new("true".to_string(), 0).run().is_ok());
+
+        let script = format!(
+            "for i in $(seq 1 {}); do echo line $i; done; exit 3",
+            GATE_TAIL_LINES + 5
+        );
+        let failure = Gate::new(script, 0).run().unwrap_err();
+        assert_eq!(failure.summary, "exited with status 3");
+        let lines: Vec<&str> = failure.tail.lines().collect();
+        assert_eq!(lines.len(), GATE_TAIL_LINES);
+        assert_eq!(lines[0], "line 6");
+        assert_eq!(lines.last(), Some(&"line 45"));
+
+        // stderr is captured too
+        let failure = Gate::new("echo boom >&2; false".to_string(), 0)
+            .run()
+            .unwrap_err();
+        assert_eq!(failure.tail, "boom");
+    }
+
+    #[test]
+    fn test_gate_timeout() {
+        let failure = Gate::new("echo started; sleep 5".to_string(), 1)
+            .run()
+            .unwrap_err();
+        assert_eq!(failure.summary, "timed out after 1s");
+        assert_eq!(failure.tail, "started");
+    }
+}
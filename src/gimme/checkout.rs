@@ -16,8 +16,10 @@
 
 use anyhow::{Context, Result};
 use fs2::FileExt;
+use once_cell::sync::Lazy;
 use std::fs::{File, OpenOptions};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use super::marker;
@@ -128,6 +130,44 @@ pub fn checkout(request: CheckoutRequest, subprocess_id: usize) -> Result<Checko
     // Lock is released here when _lock is dropped
 }
 
+/// Items checked out by this process that are still being worked on
+static OUTSTANDING: Lazy<Mutex<Vec<ChecklistItem>>> = Lazy::new(Mutex::default);
+
+/// Remember checked-out items, so they can be restored if afkcode is forced
+/// to exit before their instance finishes.
+pub fn track_outstanding(items: &[ChecklistItem]) {
+    OUTSTANDING.lock().unwrap().extend_from_slice(items);
+}
+
+/// Forget items once their instance has finished with them.
+pub fn release_outstanding(items: &[ChecklistItem]) {
+    OUTSTANDING
+        .lock()
+        .unwrap()
+        .retain(|held| {
+            !items.iter().any(|item| {
+                item.file == held.file
+                    && item.line == held.line
+                    && item.checkout_id == held.checkout_id
+            })
+        });
+}
+
+/// Restore every outstanding item to `[ ]`. Returns how many were restored.
+pub fn restore_outstanding() -> usize {
+    let items = std::mem::take(&mut *OUTSTANDING.lock().unwrap());
+    items
+        .iter()
+        .filter(|item| match marker::restore_item(item) {
+            Ok(restored) => restored,
+            Err(e) => {
+                eprintln!("Warning: Failed to restore work item {}: {}", item.content, e);
+                false
+            }
+        })
+        .count()
+}
+
 /// Build a prompt section describing the checked-out work items.
 ///
 /// This is injected into the worker prompt so the LLM knows what to work on.
@@ -221,6 +261,33 @@ mod tests {
         assert!(content.contains("[ip:"));
     }
 
+    #[test]
+    fn test_restore_outstanding_checkouts() {
+        let dir = TempDir::new().unwrap();
+        let path = create_test_file(
+            dir.path(),
+            "AGENTS.md",
+            "- [ip:aaaa] Task one\n- [ip:bbbb] Task two\n",
+        );
+        let item = |line: usize, id: &str| ChecklistItem {
+            file: path.clone(),
+            line,
+            marker: format!("[ip:{}]", id),
+            content: format!("Task {}", line),
+            sub_items: vec![],
+            checkout_id: Some(id.to_string()),
+        };
+        let finished = vec![item(1, "aaaa")];
+        track_outstanding(&finished);
+        track_outstanding(&[item(2, "bbbb")]);
+        release_outstanding(&finished);
+
+        assert_eq!(restore_outstanding(), 1);
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "- [ip:aaaa] Task one\n- [ ] Task two\n");
+        assert_eq!(restore_outstanding(), 0);
+    }
+
     #[test]
     fn test_checkout_no_matching_items() {
         let dir = TempDir::new().unwrap();
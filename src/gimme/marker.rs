@@ -137,6 +137,29 @@ pub fn restore_item(item: &ChecklistItem) -> Result<bool> {
     Ok(true)
 }
 
+/// Mark a checked-out item `[BLOCKED: reason]`.
+///
+/// Like `restore_item`, finds the item by its checkout ID. Returns `false` if
+/// the item is no longer in progress.
+pub fn mark_blocked(item: &ChecklistItem, reason: &str) -> Result<bool> {
+    let Some(checkout_id) = &item.checkout_id else {
+        return Ok(false);
+    };
+
+    let pattern = format!("[ip:{}]", checkout_id);
+    let content = fs::read_to_string(&item.file)
+        .with_context(|| format!("Failed to read {}", item.file.display()))?;
+    if !content.contains(&pattern) {
+        return Ok(false);
+    }
+
+    // A `]` in the reason would end the marker early
+    let marker = format!("[BLOCKED: {}]", reason.replace(']', ")"));
+    atomic_write(&item.file, &content.replace(&pattern, &marker))?;
+
+    Ok(true)
+}
+
 /// Reset all orphaned in-progress markers to incomplete `[ ]`.
 ///
 /// Scans all AGENTS.md files under the given base path and resets any
@@ -369,6 +392,36 @@ mod tests {
         assert!(!restored);
     }
 
+    #[test]
+    fn test_mark_blocked() {
+        let dir = TempDir::new().unwrap();
+        let content = "- [ip:a3f7] Task one\n- [ ] Task two\n";
+        let path = create_test_file(dir.path(), "AGENTS.md", content);
+
+        let item = ChecklistItem {
+            file: path.clone(),
+            line: 1,
+            marker: "[ip:a3f7]".to_string(),
+            content: "Task one".to_string(),
+            sub_items: vec![],
+            checkout_id: Some("a3f7".to_string()),
+        };
+
+        assert!(mark_blocked(&item, "no progress [x3]").unwrap());
+        let new_content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            new_content,
+            "- [BLOCKED: no progress [x3)] Task one\n- [ ] Task two\n"
+        );
+        assert_eq!(
+            MarkerType::from_marker(&parser::parse_file(&path).unwrap()[0].marker),
+            MarkerType::Blocked
+        );
+
+        // Already blocked
+        assert!(!mark_blocked(&item, "again").unwrap());
+    }
+
     #[test]
     fn test_validate_items_success() {
         let dir = TempDir::new().unwrap();
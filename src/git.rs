@@ -0,0 +1,363 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Git bookkeeping.
+//!
+//! The Standing Orders tell agents to commit their work; this checks that they
+//! did. A [`TurnStart`] records HEAD and the content of every uncommitted path
+//! before a turn, so the commits the turn made and the paths it changed can be
+//! listed after it, and a [`CommitLedger`] keeps those commits for the whole
+//! run so every change traces back to the turn that made it.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Run git in the working directory, returning stdout if it succeeded
+pub fn output(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Current HEAD, or `None` before the first commit or outside a repository
+pub fn head() -> Option<String> {
+    output(&["rev-parse", "--verify", "--quiet", "HEAD"]).map(|head| head.trim().to_string())
+}
+
+/// Uncommitted paths, relative to the repository root, with the blob hash of
+/// their content (`None` once deleted)
+pub type UncommittedChanges = BTreeMap<String, Option<String>>;
+
+/// git at the repository root, where porcelain paths are relative to, taking
+/// paths literally
+fn git_at(root: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.current_dir(root).arg("--literal-pathspecs");
+    command
+}
+
+/// Every uncommitted change, untracked files included, with its content
+fn uncommitted_changes(root: &Path) -> UncommittedChanges {
+    let Ok(status) = git_at(root)
+        .args(["status", "--porcelain", "-z", "--untracked-files=all"])
+        .output()
+    else {
+        return UncommittedChanges::new();
+    };
+    let status = String::from_utf8_lossy(&status.stdout);
+    let mut entries = status.split('\0');
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next() {
+        let Some((code, path)) = entry.get(..2).zip(entry.get(3..)) else {
+            continue;
+        };
+        // A rename or copy is followed by its source, which the staged
+        // change already accounts for
+        if code.contains(['R', 'C']) {
+            entries.next();
+        }
+        paths.push(path.to_string());
+    }
+
+    let files: Vec<&String> = paths.iter().filter(|path| root.join(path).is_file()).collect();
+    let hashes: BTreeMap<&String, String> =
+        files.iter().copied().zip(hash_files(root, &files)).collect();
+    paths
+        .iter()
+        .map(|path| (path.clone(), hashes.get(path).cloned()))
+        .collect()
+}
+
+/// `path` relative to the repository `root`, with `/` separators, or `None`
+/// if it lies outside it. `path` need not exist yet.
+fn relative_to(root: &Path, path: &Path) -> Option<String> {
+    let path = std::path::absolute(path).ok()?;
+    // Resolve symlinks as `git rev-parse` did for the root
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent.canonicalize().ok()?.join(name),
+        _ => path,
+    };
+    let root = root.canonicalize().ok()?;
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Whether the repository path `path` is `own` or lies under it
+fn is_within(path: &str, own: &str) -> bool {
+    path.strip_prefix(own)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Blob hashes of `files`, in order
+fn hash_files(root: &Path, files: &[&String]) -> Vec<String> {
+    if files.is_empty() {
+        return Vec::new();
+    }
+    let Ok(mut child) = git_at(root)
+        .args(["hash-object", "--stdin-paths"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return Vec::new();
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let paths: String = files.iter().map(|path| format!("{}\n", path)).collect();
+        let _ = stdin.write_all(paths.as_bytes());
+    }
+    child
+        .wait_with_output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A commit made during a turn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub sha: String,
+    pub subject: String,
+    /// Made by afkcode from changes the turn left uncommitted
+    pub auto: bool,
+}
+
+impl Commit {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(7)]
+    }
+}
+
+/// Commits reachable from HEAD but not from `since`, oldest first
+fn commits_since(since: Option<&str>) -> Vec<Commit> {
+    let range = match since {
+        Some(since) => format!("{}..HEAD", since),
+        None => "HEAD".to_string(),
+    };
+    output(&["log", "--reverse", "--format=%H%x09%s", &range])
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(sha, subject)| Commit {
+            sha: sha.to_string(),
+            subject: subject.to_string(),
+            auto: false,
+        })
+        .collect()
+}
+
+/// What a turn did to the repository
+#[derive(Debug, Clone, Default)]
+pub struct TurnChanges {
+    pub commits: Vec<Commit>,
+    /// Paths it created or changed and left uncommitted
+    pub left_behind: UncommittedChanges,
+}
+
+/// Where HEAD and the working tree were when a turn started
+#[derive(Debug, Clone)]
+pub struct TurnStart {
+    root: PathBuf,
+    head: Option<String>,
+    uncommitted: UncommittedChanges,
+    /// afkcode's own files and directories, relative to `root`
+    own_paths: Vec<String>,
+}
+
+impl TurnStart {
+    /// Record HEAD and the content of every uncommitted path, or `None`
+    /// outside a git repository. `own_paths` (afkcode's log and state) are
+    /// never counted as a turn's changes.
+    pub fn record(own_paths: &[PathBuf]) -> Option<Self> {
+        let root = PathBuf::from(output(&["rev-parse", "--show-toplevel"])?.trim());
+        Some(Self {
+            head: head(),
+            uncommitted: uncommitted_changes(&root),
+            own_paths: own_paths
+                .iter()
+                .filter_map(|path| relative_to(&root, path))
+                .collect(),
+            root,
+        })
+    }
+
+    /// Uncommitted paths the turn created or changed. A path that was
+    /// already dirty counts only if its content differs from the start.
+    pub fn left_behind(&self) -> UncommittedChanges {
+        uncommitted_changes(&self.root)
+            .into_iter()
+            .filter(|(path, content)| self.uncommitted.get(path) != Some(content))
+            .filter(|(path, _)| !self.own_paths.iter().any(|own| is_within(path, own)))
+            .collect()
+    }
+
+    /// Commits made since the turn started
+    pub fn commits(&self) -> Vec<Commit> {
+        let head = head();
+        if head.is_none() || head == self.head {
+            return Vec::new();
+        }
+        commits_since(self.head.as_deref())
+    }
+
+    /// Commit `changes` (from [`left_behind`](Self::left_behind)) and
+    /// nothing else with `message`. Other staged or dirty paths stay as they
+    /// are; a path that was dirty before the turn is committed whole.
+    pub fn commit(&self, message: &str, changes: &UncommittedChanges) -> Result<Commit> {
+        let existing: Vec<&String> = changes
+            .iter()
+            .filter(|(_, content)| content.is_some())
+            .map(|(path, _)| path)
+            .collect();
+        if !existing.is_empty() {
+            let status = git_at(&self.root)
+                .args(["add", "-A", "--"])
+                .args(&existing)
+                .status()
+                .context("Failed to run git add")?;
+            if !status.success() {
+                bail!("git add returned {}", status);
+            }
+        }
+        // With paths, only those are committed, deletions included
+        let status = git_at(&self.root)
+            .args(["commit", "--quiet", "-m", message, "--"])
+            .args(changes.keys())
+            .status()
+            .context("Failed to run git commit")?;
+        if !status.success() {
+            bail!("git commit returned {}", status);
+        }
+        let sha = head().context("No HEAD after committing")?;
+        Ok(Commit {
+            sha,
+            subject: message.lines().next().unwrap_or_default().to_string(),
+            auto: true,
+        })
+    }
+}
+
+/// Commits made by one turn
+#[derive(Debug, Clone)]
+struct TurnCommits {
+    instance: Option<usize>,
+    iteration: usize,
+    commits: Vec<Commit>,
+}
+
+/// Commits of every turn in the run, shared by parallel instances
+#[derive(Debug, Clone, Default)]
+pub struct CommitLedger {
+    turns: Arc<Mutex<Vec<TurnCommits>>>,
+}
+
+impl CommitLedger {
+    pub fn record(&self, instance: Option<usize>, iteration: usize, commits: &[Commit]) {
+        self.turns.lock().unwrap().push(TurnCommits {
+            instance,
+            iteration,
+            commits: commits.to_vec(),
+        });
+    }
+
+    /// Commits per turn, or `None` if no turn ran in a git repository
+    pub fn summary(&self) -> Option<String> {
+        let turns = self.turns.lock().unwrap();
+        if turns.is_empty() {
+            return None;
+        }
+        let total: usize = turns.iter().map(|turn| turn.commits.len()).sum();
+        let mut lines = vec![format!(
+            "Commits: {} over {} turn(s)",
+            total,
+            turns.len()
+        )];
+        for turn in turns.iter().filter(|turn| !turn.commits.is_empty()) {
+            let label = match turn.instance {
+                Some(instance) => format!("instance {} iteration {}", instance, turn.iteration),
+                None => format!("iteration {}", turn.iteration),
+            };
+            lines.push(format!("  {}: {}", label, format_commits(&turn.commits)));
+        }
+        Some(lines.join("\n"))
+    }
+}
+
+/// `a1b2c3d Subject; e4f5a6b Subject (auto)`
+pub fn format_commits(commits: &[Commit]) -> String {
+    commits
+        .iter()
+        .map(|commit| {
+            let auto = if commit.auto { " (auto)" } else { "" };
+            format!("{} {}{}", commit.short_sha(), commit.subject, auto)
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(sha: &str, subject: &str, auto: bool) -> Commit {
+        Commit {
+            sha: sha.to_string(),
+            subject: subject.to_string(),
+            auto,
+        }
+    }
+
+    #[test]
+    fn test_ledger_summary() {
+        let ledger = CommitLedger::default();
+        assert_eq!(ledger.summary(), None);
+
+        ledger.record(None, 1, &[commit("a1b2c3d4e5f6", "Add parser", false)]);
+        ledger.record(None, 2, &[]);
+        ledger.clone().record(
+            Some(1),
+            3,
+            &[
+                commit("0123456789ab", "Fix tests", false),
+                commit("fedcba987654", "afkcode iteration 3: Task", true),
+            ],
+        );
+
+        assert_eq!(
+            ledger.summary().unwrap(),
+            "Commits: 3 over 3 turn(s)\n  \
+             iteration 1: a1b2c3d Add parser\n  \
+             instance 1 iteration 3: 0123456 Fix tests; fedcba9 afkcode iteration 3: Task (auto)"
+        );
+    }
+
+    #[test]
+    fn test_is_within() {
+        assert!(is_within("afkcode.log", "afkcode.log"));
+        assert!(is_within(".afkcode/ratelimits.json", ".afkcode"));
+        assert!(!is_within("afkcode.log.0", "afkcode.log"));
+        assert!(!is_within(".afkcode.toml", ".afkcode"));
+    }
+}
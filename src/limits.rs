@@ -0,0 +1,362 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Run limits.
+//!
+//! A run can be capped at a number of worker turns and a wall-clock deadline,
+//! and confined to active hours. All are checked between turns, so a turn in
+//! progress always finishes. Like the usage budget, [`RunLimits`] is shared by
+//! every parallel instance: the iteration cap counts worker turns across all
+//! of them. Controller, confirmation and grooming turns don't count.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveDateTime, NaiveTime, TimeZone};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Daily windows in which turns may start, e.g. `22:00-06:00,12:00-13:00`.
+/// A window whose end is before its start runs past midnight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveHours {
+    windows: Vec<(NaiveTime, NaiveTime)>,
+}
+
+impl ActiveHours {
+    /// Whether `time` falls in one of the windows
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        self.windows.iter().any(|&(start, end)| {
+            if start < end {
+                start <= time && time < end
+            } else {
+                // Wraps midnight; equal ends mean the whole day
+                time >= start || time < end
+            }
+        })
+    }
+
+    /// When the next window opens, or `None` if `now` is already inside one
+    pub fn next_open(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.contains(now.time()) {
+            return None;
+        }
+        self.windows
+            .iter()
+            .filter_map(|&(start, _)| {
+                let today = now.date_naive().and_time(start);
+                let opens = if start > now.time() {
+                    today
+                } else {
+                    today + ChronoDuration::days(1)
+                };
+                local_time(opens)
+            })
+            .min()
+    }
+}
+
+impl FromStr for ActiveHours {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let windows = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|window| !window.is_empty())
+            .map(|window| {
+                let (start, end) = window
+                    .split_once('-')
+                    .ok_or_else(|| anyhow!("Expected HH:MM-HH:MM, got '{}'", window))?;
+                Ok((parse_clock(start)?, parse_clock(end)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if windows.is_empty() {
+            bail!("No active hours given");
+        }
+        Ok(Self { windows })
+    }
+}
+
+impl fmt::Display for ActiveHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let windows: Vec<String> = self
+            .windows
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start.format("%H:%M"), end.format("%H:%M")))
+            .collect();
+        f.write_str(&windows.join(","))
+    }
+}
+
+fn parse_clock(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .with_context(|| format!("Invalid time '{}', expected HH:MM", value.trim()))
+}
+
+/// Resolve a naive local time, taking the earlier reading when a clock change
+/// makes it ambiguous and skipping ahead an hour when it makes it not exist
+fn local_time(naive: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| Local.from_local_datetime(&(naive + ChronoDuration::hours(1))).earliest())
+}
+
+/// Parse a deadline given as a duration from `now` (`8h`, `1h30m`, `90m`,
+/// `2d`), a time of day (`06:00`, the next occurrence), a local date and time
+/// (`2025-06-01 06:00`), or an RFC 3339 timestamp.
+pub fn parse_deadline(value: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let value = value.trim();
+    if let Some(duration) = parse_duration(value) {
+        return Ok(now + duration);
+    }
+    if let Ok(time) = NaiveTime::parse_from_str(value, "%H:%M") {
+        let today = now.date_naive().and_time(time);
+        let next = if time > now.time() {
+            today
+        } else {
+            today + ChronoDuration::days(1)
+        };
+        return local_time(next).ok_or_else(|| anyhow!("Invalid local time '{}'", value));
+    }
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Local));
+    }
+    for format in ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return local_time(naive).ok_or_else(|| anyhow!("Invalid local time '{}'", value));
+        }
+    }
+    bail!(
+        "Invalid deadline '{}': expected a duration (8h, 1h30m), HH:MM, \
+         'YYYY-MM-DD HH:MM' or an RFC 3339 timestamp",
+        value
+    )
+}
+
+/// `1h30m`-style durations; units are d, h, m and s
+fn parse_duration(value: &str) -> Option<ChronoDuration> {
+    let mut total = ChronoDuration::zero();
+    let mut digits = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let amount: i64 = digits.parse().ok()?;
+        digits.clear();
+        total += match c.to_ascii_lowercase() {
+            'd' => ChronoDuration::days(amount),
+            'h' => ChronoDuration::hours(amount),
+            'm' => ChronoDuration::minutes(amount),
+            's' => ChronoDuration::seconds(amount),
+            _ => return None,
+        };
+    }
+    // A bare number or trailing digits have no unit
+    (digits.is_empty() && !value.is_empty()).then_some(total)
+}
+
+/// A claimed worker turn. It counts as finished once dropped, so a turn that
+/// fails still does.
+#[must_use]
+#[derive(Debug)]
+pub struct IterationClaim<'a> {
+    limits: &'a RunLimits,
+}
+
+impl IterationClaim<'_> {
+    /// Record that the turn has finished
+    pub fn finish(self) {}
+}
+
+impl Drop for IterationClaim<'_> {
+    fn drop(&mut self) {
+        self.limits.finished.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Iteration cap, deadline and active hours for a run
+#[derive(Debug)]
+pub struct RunLimits {
+    max_iterations: Option<usize>,
+    deadline: Option<DateTime<Local>>,
+    active_hours: Option<ActiveHours>,
+    started: Instant,
+    /// Worker turns claimed, including ones still running
+    claimed: AtomicUsize,
+    /// Worker turns finished
+    finished: AtomicUsize,
+    /// Why the run stopped, once a limit has been hit
+    stopped_by: Mutex<Option<String>>,
+}
+
+impl RunLimits {
+    pub fn new(
+        max_iterations: Option<usize>,
+        deadline: Option<DateTime<Local>>,
+        active_hours: Option<ActiveHours>,
+    ) -> Self {
+        Self {
+            max_iterations,
+            deadline,
+            active_hours,
+            started: Instant::now(),
+            claimed: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+            stopped_by: Mutex::new(None),
+        }
+    }
+
+    pub fn active_hours(&self) -> Option<&ActiveHours> {
+        self.active_hours.as_ref()
+    }
+
+    /// The limit that has been reached, if any. The iteration cap counts as
+    /// reached once the last allowed worker turn has finished.
+    pub fn exceeded(&self) -> Option<String> {
+        let reason = if self.deadline.is_some_and(|deadline| Local::now() >= deadline) {
+            format!("deadline {} reached", self.deadline_label())
+        } else if self
+            .max_iterations
+            .is_some_and(|max| self.finished.load(Ordering::SeqCst) >= max)
+        {
+            format!("{} iteration(s) completed", self.max_iterations.unwrap_or_default())
+        } else {
+            return None;
+        };
+        self.stopped_by
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| reason.clone());
+        Some(reason)
+    }
+
+    /// Claim a worker turn, or the reason no more may start
+    pub fn start_iteration(&self) -> Result<IterationClaim<'_>, String> {
+        if let Some(reason) = self.exceeded() {
+            return Err(reason);
+        }
+        let claimed = self.claimed.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |claimed| {
+            match self.max_iterations {
+                Some(max) if claimed >= max => None,
+                _ => Some(claimed + 1),
+            }
+        });
+        match claimed {
+            Ok(_) => Ok(IterationClaim { limits: self }),
+            // Other instances are running the last turns
+            Err(max) => Err(format!("all {} iteration(s) started", max)),
+        }
+    }
+
+    /// When turns may start again, if `now` is outside the active hours
+    pub fn paused_until(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.active_hours.as_ref()?.next_open(now)
+    }
+
+    fn deadline_label(&self) -> String {
+        self.deadline
+            .map(|deadline| deadline.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default()
+    }
+
+    /// One line on how long the run took and which limit, if any, ended it
+    pub fn summary(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs();
+        let mut summary = format!(
+            "Run: {} iteration(s) in {}h{:02}m{:02}s",
+            self.finished.load(Ordering::SeqCst),
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60
+        );
+        if let Some(reason) = self.stopped_by.lock().unwrap().as_ref() {
+            summary.push_str(&format!("; stopped: {}", reason));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 6, 2, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_deadline() {
+        let now = at(20, 0);
+        assert_eq!(parse_deadline("8h", now).unwrap(), now + ChronoDuration::hours(8));
+        assert_eq!(parse_deadline("1h30m", now).unwrap(), now + ChronoDuration::minutes(90));
+        assert_eq!(parse_deadline("21:15", now).unwrap(), at(21, 15));
+        // A time already past today means tomorrow
+        assert_eq!(
+            parse_deadline("06:00", now).unwrap(),
+            at(6, 0) + ChronoDuration::days(1)
+        );
+        assert_eq!(parse_deadline("2025-06-03 06:00", now).unwrap(), at(6, 0) + ChronoDuration::days(1));
+        assert_eq!(
+            parse_deadline("2025-06-02T20:00:00Z", now).unwrap(),
+            Local.from_utc_datetime(&NaiveDateTime::parse_from_str("2025-06-02 20:00", "%Y-%m-%d %H:%M").unwrap())
+        );
+        assert!(parse_deadline("90", now).is_err());
+        assert!(parse_deadline("soon", now).is_err());
+    }
+
+    #[test]
+    fn test_active_hours_windows() {
+        let hours: ActiveHours = "22:00-06:00, 12:00-13:00".parse().unwrap();
+        assert_eq!(hours.to_string(), "22:00-06:00,12:00-13:00");
+
+        assert_eq!(hours.next_open(at(23, 0)), None);
+        assert_eq!(hours.next_open(at(5, 59)), None);
+        assert_eq!(hours.next_open(at(12, 30)), None);
+        assert_eq!(hours.next_open(at(9, 0)), Some(at(12, 0)));
+        assert_eq!(hours.next_open(at(13, 0)), Some(at(22, 0)));
+
+        let mornings: ActiveHours = "06:00-09:00".parse().unwrap();
+        assert_eq!(mornings.next_open(at(10, 0)), Some(at(6, 0) + ChronoDuration::days(1)));
+
+        assert!("22:00".parse::<ActiveHours>().is_err());
+        assert!("25:00-06:00".parse::<ActiveHours>().is_err());
+    }
+
+    #[test]
+    fn test_iteration_cap() {
+        let limits = RunLimits::new(Some(2), None, None);
+        let first = limits.start_iteration().unwrap();
+        let second = limits.start_iteration().unwrap();
+        // Both turns are still running
+        assert_eq!(limits.exceeded(), None);
+        assert_eq!(limits.start_iteration().unwrap_err(), "all 2 iteration(s) started");
+        first.finish();
+        assert_eq!(limits.exceeded(), None);
+        // A turn that fails finishes as it unwinds
+        fn failing_turn(_claim: IterationClaim<'_>) -> Result<(), String> {
+            Err("tool failed".to_string())
+        }
+        assert!(failing_turn(second).is_err());
+        assert_eq!(limits.start_iteration().unwrap_err(), "2 iteration(s) completed");
+        assert!(limits.summary().starts_with("Run: 2 iteration(s) in 0h00m"));
+        assert!(limits.summary().ends_with("; stopped: 2 iteration(s) completed"));
+
+        let past = RunLimits::new(None, Some(Local::now() - ChronoDuration::minutes(1)), None);
+        assert!(past.start_iteration().unwrap_err().starts_with("deadline "));
+    }
+}
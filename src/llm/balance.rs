@@ -0,0 +1,131 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How a tool chain picks the tool to start each invocation with.
+//!
+//! Whatever the strategy, squelched tools are skipped and a rate limit or
+//! failure falls back through the remaining tools. With `priority` the
+//! fallback follows list order; the other strategies wrap around the list
+//! so every tool gets a turn as a fallback.
+
+use clap::ValueEnum;
+use std::time::SystemTime;
+
+/// Tool selection strategy for an [`LlmToolChain`](super::LlmToolChain)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ToolStrategy {
+    /// Always start with the first usable tool in the list
+    #[default]
+    Priority,
+    /// Start each invocation with the next tool in the list
+    RoundRobin,
+    /// Pick the starting tool at random, in proportion to its `weight`
+    Weighted,
+    /// Start with the tool whose last rate limit is longest ago (or never)
+    LeastRecentlyRateLimited,
+}
+
+impl std::str::FromStr for ToolStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "priority" => Ok(Self::Priority),
+            "round-robin" => Ok(Self::RoundRobin),
+            "weighted" => Ok(Self::Weighted),
+            "least-recently-rate-limited" => Ok(Self::LeastRecentlyRateLimited),
+            other => Err(format!("Invalid tool strategy: {}", other)),
+        }
+    }
+}
+
+impl ToolStrategy {
+    /// Whether fallback continues from the start of the list
+    pub(super) fn wraps(self) -> bool {
+        self != Self::Priority
+    }
+}
+
+/// Index of the first `available` tool at or after `cursor`, wrapping around
+pub(super) fn next_in_rotation(available: &[usize], len: usize, cursor: usize) -> Option<usize> {
+    (0..len)
+        .map(|offset| (cursor + offset) % len)
+        .find(|index| available.contains(index))
+}
+
+/// Pick from `available` in proportion to `weights`, using `roll` as the
+/// random number. `None` if every candidate has weight 0.
+pub(super) fn weighted_pick(available: &[usize], weights: &[u32], roll: u64) -> Option<usize> {
+    let total: u64 = available.iter().map(|&i| u64::from(weights[i])).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut target = roll % total;
+    for &index in available {
+        let weight = u64::from(weights[index]);
+        if target < weight {
+            return Some(index);
+        }
+        target -= weight;
+    }
+    None
+}
+
+/// The available tool rate limited longest ago; never-limited tools come
+/// first, ties go to list order
+pub(super) fn least_recently_limited(
+    available: &[usize],
+    last_limited: impl Fn(usize) -> Option<SystemTime>,
+) -> Option<usize> {
+    available.iter().copied().min_by_key(|&index| last_limited(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_strategy() {
+        assert_eq!("round_robin".parse(), Ok(ToolStrategy::RoundRobin));
+        assert_eq!(
+            "Least-Recently-Rate-Limited".parse(),
+            Ok(ToolStrategy::LeastRecentlyRateLimited)
+        );
+        assert!("random".parse::<ToolStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_next_in_rotation_skips_unavailable() {
+        assert_eq!(next_in_rotation(&[0, 2], 3, 1), Some(2));
+        assert_eq!(next_in_rotation(&[0, 2], 3, 3), Some(0));
+        assert_eq!(next_in_rotation(&[], 3, 0), None);
+    }
+
+    #[test]
+    fn test_weighted_pick() {
+        let weights = [3, 0, 1];
+        let picks: Vec<_> = (0..4).map(|roll| weighted_pick(&[0, 1, 2], &weights, roll)).collect();
+        assert_eq!(picks, [Some(0), Some(0), Some(0), Some(2)]);
+        assert_eq!(weighted_pick(&[1], &weights, 7), None);
+    }
+
+    #[test]
+    fn test_least_recently_limited() {
+        let now = SystemTime::now();
+        let limited = [Some(now), None, Some(now - Duration::from_secs(60))];
+        assert_eq!(least_recently_limited(&[0, 1, 2], |i| limited[i]), Some(1));
+        assert_eq!(least_recently_limited(&[0, 2], |i| limited[i]), Some(2));
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of prompt/response exchanges.
+//!
+//! With `--record-cassette` every tool invocation made through an
+//! [`LlmToolChain`](super::LlmToolChain), fallbacks included, is appended to a
+//! JSONL file: the prompt, which tool and model answered, the raw output, how
+//! long it took and how afkcode classified it. `--replay-cassette` turns the
+//! file back into scripted tools (see [`ReplayTool`](super::replay::ReplayTool))
+//! so the run can be reproduced without any agent.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::replay::ReplayTool;
+use super::retry::Classification;
+use super::{EventFormat, ReasoningEffort};
+
+/// One line of a cassette
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CassetteEntry {
+    /// Position in the cassette, starting at 1
+    pub seq: usize,
+    pub timestamp: String,
+    /// Parallel instance id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<usize>,
+    pub tool: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Reasoning effort the tool was asked for, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    pub prompt: String,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub classification: Classification,
+    /// Why the invocation failed, for `error` entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Event schema of `stdout`, if the tool ran in structured mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_format: Option<EventFormat>,
+}
+
+/// Appends exchanges to a cassette file. Clones share the file, so parallel
+/// instances record into one cassette in the order their invocations finish.
+#[derive(Debug, Clone)]
+pub struct CassetteRecorder {
+    inner: Arc<Mutex<Recorder>>,
+}
+
+#[derive(Debug)]
+struct Recorder {
+    path: PathBuf,
+    file: File,
+    next_seq: usize,
+}
+
+impl CassetteRecorder {
+    /// Start a new cassette at `path`, replacing any previous recording
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create cassette {}", path.display()))?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Recorder {
+                path: path.to_path_buf(),
+                file,
+                next_seq: 1,
+            })),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Recorder> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Append `entry`, numbering it. A failed write is reported but doesn't
+    /// stop the run.
+    pub fn record(&self, mut entry: CassetteEntry) {
+        let mut recorder = self.lock();
+        entry.seq = recorder.next_seq;
+        recorder.next_seq += 1;
+
+        let result = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                writeln!(recorder.file, "{}", line)?;
+                recorder.file.flush()?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            eprintln!(
+                "Warning: Failed to record exchange to {}: {}",
+                recorder.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// A recorded cassette loaded for replay
+#[derive(Debug)]
+pub struct Cassette {
+    pub path: PathBuf,
+    pub entries: Vec<CassetteEntry>,
+    /// One replay per tool, shared by every chain built from the cassette so
+    /// each recorded exchange is answered once
+    tools: Mutex<HashMap<String, Arc<ReplayTool>>>,
+}
+
+impl Cassette {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        let entries = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line).with_context(|| {
+                    format!("Invalid cassette entry at {}:{}", path.display(), index + 1)
+                })
+            })
+            .collect::<Result<Vec<CassetteEntry>>>()?;
+        if entries.is_empty() {
+            anyhow::bail!("Cassette {} has no recorded exchanges", path.display());
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+            tools: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The replay of `name`'s recorded exchanges
+    pub(super) fn replay_tool(&self, name: &str) -> Result<Arc<ReplayTool>> {
+        let mut tools = self.tools.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tool) = tools.get(name) {
+            return Ok(tool.clone());
+        }
+        let tool = Arc::new(ReplayTool::from_cassette(name, &self.path, &self.entries)?);
+        tools.insert(name.to_string(), tool.clone());
+        Ok(tool)
+    }
+
+    /// Tool names in the order they were first used, for rebuilding the chain
+    pub fn tool_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for entry in &self.entries {
+            if !names.contains(&entry.tool) {
+                names.push(entry.tool.clone());
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(tool: &str, classification: Classification) -> CassetteEntry {
+        CassetteEntry {
+            seq: 0,
+            timestamp: "2025-06-01T10:00:00+00:00".to_string(),
+            instance: None,
+            tool: tool.to_string(),
+            model: None,
+            reasoning_effort: None,
+            prompt: "do the thing".to_string(),
+            stdout: "done".to_string(),
+            stderr: String::new(),
+            exit_code: Some(0),
+            duration_ms: 1200,
+            classification,
+            error: None,
+            event_format: None,
+        }
+    }
+
+    #[test]
+    fn test_record_and_load_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("runs").join("cassette.jsonl");
+        let recorder = CassetteRecorder::create(&path).unwrap();
+        recorder.clone().record(entry("gemini", Classification::RateLimited));
+        recorder.record(entry("codex", Classification::Ok));
+        recorder.record(entry("gemini", Classification::Ok));
+
+        let cassette = Cassette::load(&path).unwrap();
+        let seqs: Vec<_> = cassette.entries.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [1, 2, 3]);
+        assert_eq!(cassette.entries[0].classification, Classification::RateLimited);
+        assert_eq!(cassette.tool_names(), ["gemini", "codex"]);
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(r#""classification":"rate_limited""#));
+    }
+
+    #[test]
+    fn test_load_reports_bad_line() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cassette.jsonl");
+        fs::write(&path, "{\"oops\": true}\n").unwrap();
+        let err = Cassette::load(&path).unwrap_err();
+        assert!(err.to_string().contains("cassette.jsonl:1"));
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reasoning effort.
+//!
+//! A level asked of a tool for one invocation. Each adapter maps it onto its
+//! own controls: Codex's `model_reasoning_effort`, Aider's
+//! `--reasoning-effort`, the `reasoning_effort` field of OpenAI-compatible
+//! requests, a thinking budget for Claude Code, and `reasoning_args` for
+//! custom tools. Tools without such a control ignore it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How hard a tool should think before answering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReasoningEffort::Minimal => "minimal",
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+
+    /// `MAX_THINKING_TOKENS` for Claude Code, matching its "think", "think
+    /// hard" and "ultrathink" budgets. `None` at minimal, where thinking is
+    /// turned off in the prompt instead.
+    pub(super) fn claude_thinking_tokens(self) -> Option<u32> {
+        match self {
+            ReasoningEffort::Minimal => None,
+            ReasoningEffort::Low => Some(4_000),
+            ReasoningEffort::Medium => Some(10_000),
+            ReasoningEffort::High => Some(31_999),
+        }
+    }
+}
+
+impl fmt::Display for ReasoningEffort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of machine-readable agent output.
+//!
+//! In structured mode Claude (`--output-format stream-json`), Codex
+//! (`exec --json`) and Gemini (`--output-format stream-json`) print one JSON
+//! event per line. [`EventParser`] turns such a stream into a
+//! [`StructuredResult`] and, for live output, into readable lines, so the
+//! console shows what the agent says and does rather than raw JSON.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::usage::{self, Usage};
+
+/// Longest tool-call detail shown in live output
+const MAX_DETAIL_CHARS: usize = 160;
+
+/// Which CLI's event schema a stream follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventFormat {
+    Claude,
+    Codex,
+    Gemini,
+}
+
+/// A tool the agent ran during its turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub name: String,
+    /// Command line, path or compact JSON input
+    pub input: String,
+}
+
+/// Typed summary of one structured invocation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StructuredResult {
+    /// The agent's last message to the user
+    pub final_message: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub errors: Vec<String>,
+    pub usage: Option<Usage>,
+    /// Id for resuming the conversation (Codex calls it a thread id)
+    pub session_id: Option<String>,
+    /// Prompt size of the last model call, i.e. how full the context is.
+    /// Only Claude reports usage per call.
+    pub context_tokens: Option<u64>,
+}
+
+/// Incremental parser for one event stream
+#[derive(Debug)]
+pub(super) struct EventParser {
+    format: EventFormat,
+    result: StructuredResult,
+    /// Streamed message deltas not yet complete (Gemini)
+    pending: String,
+    /// Sum of per-turn usage; a final result event replaces it
+    turn_usage: Option<Usage>,
+    final_usage: Option<Usage>,
+}
+
+impl EventParser {
+    pub(super) fn new(format: EventFormat) -> Self {
+        Self {
+            format,
+            result: StructuredResult::default(),
+            pending: String::new(),
+            turn_usage: None,
+            final_usage: None,
+        }
+    }
+
+    /// Parse a whole captured stream
+    pub(super) fn parse(format: EventFormat, stdout: &str) -> StructuredResult {
+        let mut parser = Self::new(format);
+        for line in stdout.lines() {
+            parser.push_line(line);
+        }
+        parser.finish().0
+    }
+
+    /// Consume one stdout line, returning what to show for it. Lines that
+    /// aren't JSON events are passed through unchanged.
+    pub(super) fn push_line(&mut self, line: &str) -> Vec<String> {
+        let trimmed = line.trim();
+        let event = trimmed
+            .starts_with('{')
+            .then(|| serde_json::from_str::<Value>(trimmed).ok())
+            .flatten();
+        let Some(event) = event else {
+            return vec![line.to_string()];
+        };
+
+        let mut display = Vec::new();
+        if !self.is_delta(&event) {
+            display.extend(self.flush_pending());
+        }
+        if self.result.session_id.is_none()
+            && let Some(id) = ["session_id", "thread_id"]
+                .iter()
+                .find_map(|key| event.get(*key).and_then(Value::as_str))
+        {
+            self.result.session_id = Some(id.to_string());
+        }
+
+        match self.format {
+            EventFormat::Claude => self.claude_event(&event, &mut display),
+            EventFormat::Codex => self.codex_event(&event, &mut display),
+            EventFormat::Gemini => self.gemini_event(&event, &mut display),
+        }
+        display
+    }
+
+    /// Finish the stream, returning the result and any remaining display lines
+    pub(super) fn finish(mut self) -> (StructuredResult, Vec<String>) {
+        let display = self.flush_pending();
+        self.result.usage = self.final_usage.or(self.turn_usage);
+        (self.result, display)
+    }
+
+    fn is_delta(&self, event: &Value) -> bool {
+        self.format == EventFormat::Gemini
+            && event_type(event) == "message"
+            && event.get("delta").and_then(Value::as_bool) == Some(true)
+    }
+
+    fn flush_pending(&mut self) -> Vec<String> {
+        if self.pending.trim().is_empty() {
+            self.pending.clear();
+            return Vec::new();
+        }
+        let message = std::mem::take(&mut self.pending);
+        let display = message.lines().map(str::to_string).collect();
+        self.result.final_message = message;
+        display
+    }
+
+    fn set_message(&mut self, text: &str, display: &mut Vec<String>) {
+        if text.trim().is_empty() {
+            return;
+        }
+        display.extend(text.lines().map(str::to_string));
+        self.result.final_message = text.to_string();
+    }
+
+    fn add_tool_call(&mut self, name: &str, input: String, display: &mut Vec<String>) {
+        display.push(format!("-> {}: {}", name, truncate(&input)));
+        self.result.tool_calls.push(ToolCall {
+            name: name.to_string(),
+            input,
+        });
+    }
+
+    fn add_error(&mut self, message: &str, display: &mut Vec<String>) {
+        display.push(format!("error: {}", message));
+        self.result.errors.push(message.to_string());
+    }
+
+    fn add_turn_usage(&mut self, usage: Option<Usage>) {
+        if let Some(usage) = usage {
+            *self.turn_usage.get_or_insert_with(Usage::default) += usage;
+        }
+    }
+
+    fn claude_event(&mut self, event: &Value, display: &mut Vec<String>) {
+        match event_type(event) {
+            "assistant" => {
+                let blocks = event
+                    .pointer("/message/content")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let mut text = Vec::new();
+                for block in blocks {
+                    match event_type(block) {
+                        "text" => text.extend(block.get("text").and_then(Value::as_str)),
+                        "tool_use" => {
+                            let name = str_field(block, "name").unwrap_or("tool");
+                            let input = block.get("input").map(compact).unwrap_or_default();
+                            self.add_tool_call(name, input, display);
+                        }
+                        _ => {}
+                    }
+                }
+                self.set_message(&text.join("\n"), display);
+                if let Some(usage) = event.get("message").and_then(usage::usage_from_value) {
+                    self.result.context_tokens = Some(usage.input_tokens);
+                }
+            }
+            "result" => {
+                self.final_usage = usage::usage_from_value(event);
+                let text = str_field(event, "result").unwrap_or_default();
+                if event.get("is_error").and_then(Value::as_bool) == Some(true) {
+                    let subtype = str_field(event, "subtype").unwrap_or("error");
+                    let message = if text.is_empty() { subtype } else { text };
+                    self.add_error(message, display);
+                } else if !text.trim().is_empty() {
+                    // Already shown as the last assistant message
+                    self.result.final_message = text.to_string();
+                }
+            }
+            "error" => {
+                let message = error_message(event).unwrap_or_else(|| compact(event));
+                self.add_error(&message, display);
+            }
+            _ => {}
+        }
+    }
+
+    fn codex_event(&mut self, event: &Value, display: &mut Vec<String>) {
+        match event_type(event) {
+            "item.completed" => {
+                let Some(item) = event.get("item") else {
+                    return;
+                };
+                match event_type(item) {
+                    "agent_message" => {
+                        self.set_message(str_field(item, "text").unwrap_or_default(), display)
+                    }
+                    "command_execution" => {
+                        let command = str_field(item, "command").unwrap_or_default();
+                        self.add_tool_call("command", command.to_string(), display);
+                    }
+                    "file_change" => {
+                        let paths: Vec<&str> = item
+                            .get("changes")
+                            .and_then(Value::as_array)
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|change| str_field(change, "path"))
+                            .collect();
+                        self.add_tool_call("file_change", paths.join(", "), display);
+                    }
+                    "mcp_tool_call" => {
+                        let name = format!(
+                            "{}.{}",
+                            str_field(item, "server").unwrap_or("mcp"),
+                            str_field(item, "tool").unwrap_or("tool")
+                        );
+                        let input = item.get("arguments").map(compact).unwrap_or_default();
+                        self.add_tool_call(&name, input, display);
+                    }
+                    "web_search" => {
+                        let query = str_field(item, "query").unwrap_or_default();
+                        self.add_tool_call("web_search", query.to_string(), display);
+                    }
+                    "error" => {
+                        let message = str_field(item, "message").unwrap_or("unknown error");
+                        self.add_error(message, display);
+                    }
+                    _ => {}
+                }
+            }
+            "turn.completed" => self.add_turn_usage(usage::usage_from_value(event)),
+            "turn.failed" | "error" => {
+                let message = error_message(event).unwrap_or_else(|| compact(event));
+                self.add_error(&message, display);
+            }
+            _ => {}
+        }
+    }
+
+    fn gemini_event(&mut self, event: &Value, display: &mut Vec<String>) {
+        match event_type(event) {
+            "message" if str_field(event, "role") == Some("assistant") => {
+                let content = str_field(event, "content").unwrap_or_default();
+                if self.is_delta(event) {
+                    self.pending.push_str(content);
+                } else {
+                    self.set_message(content, display);
+                }
+            }
+            "tool_use" => {
+                let name = str_field(event, "tool_name").unwrap_or("tool");
+                let input = event.get("parameters").map(compact).unwrap_or_default();
+                self.add_tool_call(name, input, display);
+            }
+            "error" => {
+                let message = error_message(event).unwrap_or_else(|| compact(event));
+                self.add_error(&message, display);
+            }
+            "result" => {
+                if let Some(stats) = event.get("stats") {
+                    self.final_usage = usage::usage_from_value(&json!({ "usage": stats }));
+                }
+                if str_field(event, "status") == Some("error") {
+                    let message = error_message(event).unwrap_or_else(|| "error".to_string());
+                    self.add_error(&message, display);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn event_type(value: &Value) -> &str {
+    str_field(value, "type").unwrap_or_default()
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Value::as_str)
+}
+
+/// The message of an error event: `message`, `error.message` or a string `error`
+fn error_message(event: &Value) -> Option<String> {
+    str_field(event, "message")
+        .or_else(|| event.pointer("/error/message").and_then(Value::as_str))
+        .or_else(|| str_field(event, "error"))
+        .map(str::to_string)
+}
+
+fn compact(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() <= MAX_DETAIL_CHARS && line.len() == text.len() {
+        return line.to_string();
+    }
+    let short: String = line.chars().take(MAX_DETAIL_CHARS).collect();
+    format!("{}...", short)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claude_stream() {
+        let stream = r#"{"type":"system","subtype":"init","session_id":"abc-123","model":"sonnet"}
+{"type":"assistant","message":{"content":[{"type":"text","text":"Let me check."},{"type":"tool_use","name":"Bash","input":{"command":"grep -r __ALL_TASKS_COMPLETE__ ."}}]}}
+{"type":"user","message":{"content":[{"type":"tool_result","content":"rate limit reached __ALL_TASKS_COMPLETE__"}]}}
+{"type":"assistant","message":{"content":[{"type":"text","text":"All done."}]}}
+{"type":"result","subtype":"success","is_error":false,"result":"All done.","session_id":"abc-123","total_cost_usd":0.02,"usage":{"input_tokens":10,"output_tokens":5}}"#;
+        let result = EventParser::parse(EventFormat::Claude, stream);
+        assert_eq!(result.final_message, "All done.");
+        assert_eq!(result.session_id.as_deref(), Some("abc-123"));
+        assert_eq!(result.tool_calls.len(), 1);
+        assert_eq!(result.tool_calls[0].name, "Bash");
+        assert!(result.errors.is_empty());
+        assert_eq!(result.usage.unwrap().cost_usd, Some(0.02));
+        assert_eq!(result.context_tokens, None);
+
+        let with_usage = r#"{"type":"assistant","message":{"content":[],"usage":{"input_tokens":3,"cache_read_input_tokens":90000,"output_tokens":7}}}"#;
+        let result = EventParser::parse(EventFormat::Claude, with_usage);
+        assert_eq!(result.context_tokens, Some(90003));
+    }
+
+    #[test]
+    fn test_claude_error_result() {
+        let stream = r#"{"type":"result","subtype":"success","is_error":true,"result":"Claude AI usage limit reached|1750000000"}"#;
+        let result = EventParser::parse(EventFormat::Claude, stream);
+        assert_eq!(result.errors, vec!["Claude AI usage limit reached|1750000000"]);
+        assert!(result.final_message.is_empty());
+    }
+
+    #[test]
+    fn test_codex_stream() {
+        let stream = r#"{"type":"thread.started","thread_id":"thread-9"}
+{"type":"item.completed","item":{"id":"item_0","type":"reasoning","text":"thinking"}}
+{"type":"item.completed","item":{"id":"item_1","type":"command_execution","command":"cargo test","aggregated_output":"ok","exit_code":0}}
+{"type":"item.completed","item":{"id":"item_2","type":"file_change","changes":[{"path":"src/lib.rs","kind":"update"}]}}
+{"type":"item.completed","item":{"id":"item_3","type":"agent_message","text":"Fixed it."}}
+{"type":"turn.completed","usage":{"input_tokens":100,"cached_input_tokens":50,"output_tokens":20}}"#;
+        let result = EventParser::parse(EventFormat::Codex, stream);
+        assert_eq!(result.final_message, "Fixed it.");
+        assert_eq!(result.session_id.as_deref(), Some("thread-9"));
+        let names: Vec<_> = result.tool_calls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["command", "file_change"]);
+        assert_eq!(result.tool_calls[1].input, "src/lib.rs");
+        assert_eq!(result.usage.unwrap().output_tokens, 20);
+
+        let failed = r#"{"type":"turn.failed","error":{"message":"stream error: 429 Too Many Requests"}}"#;
+        let result = EventParser::parse(EventFormat::Codex, failed);
+        assert_eq!(result.errors, vec!["stream error: 429 Too Many Requests"]);
+    }
+
+    #[test]
+    fn test_gemini_deltas_are_joined() {
+        let stream = r#"{"type":"init","session_id":"g-1","model":"gemini-2.5-pro"}
+{"type":"message","role":"user","content":"do it"}
+{"type":"message","role":"assistant","content":"Reading ","delta":true}
+{"type":"message","role":"assistant","content":"files.","delta":true}
+{"type":"tool_use","tool_name":"read_file","tool_id":"t1","parameters":{"path":"a.rs"}}
+{"type":"tool_result","tool_id":"t1","status":"success"}
+{"type":"message","role":"assistant","content":"Done","delta":true}
+{"type":"message","role":"assistant","content":".","delta":true}
+{"type":"result","status":"success","stats":{"total_tokens":30,"input_tokens":20,"output_tokens":10}}"#;
+        let mut parser = EventParser::new(EventFormat::Gemini);
+        let display: Vec<String> = stream.lines().flat_map(|l| parser.push_line(l)).collect();
+        let (result, rest) = parser.finish();
+        assert_eq!(display, ["Reading files.", r#"-> read_file: {"path":"a.rs"}"#, "Done."]);
+        assert!(rest.is_empty());
+        assert_eq!(result.final_message, "Done.");
+        assert_eq!(result.session_id.as_deref(), Some("g-1"));
+        assert_eq!(result.usage.unwrap().total_tokens, 30);
+    }
+
+    #[test]
+    fn test_plain_lines_pass_through() {
+        let mut parser = EventParser::new(EventFormat::Codex);
+        assert_eq!(parser.push_line("Reading prompt from stdin..."), ["Reading prompt from stdin..."]);
+        let (result, _) = parser.finish();
+        assert_eq!(result, StructuredResult::default());
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Process settings for CLI tools.
+//!
+//! A `[tool.<name>]` table can give the tool's process extra environment
+//! variables, a working directory and extra arguments, and override them for
+//! particular parallel instances. `{instance_id}` is substituted in each, so
+//! instances can run against isolated agent config, e.g. separate accounts:
+//!
+//! ```toml
+//! [tool.claude]
+//! env = { CLAUDE_CONFIG_DIR = "/home/me/.claude-{instance_id}" }
+//! ```
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::{ToolConfig, ToolInstanceConfig};
+
+/// Placeholder replaced by the parallel instance id (0 outside parallel runs)
+const INSTANCE_PLACEHOLDER: &str = "{instance_id}";
+
+/// Environment, working directory and extra arguments for a tool's process
+#[derive(Debug, Clone, Default)]
+pub(super) struct LaunchSettings {
+    /// Settings every instance gets
+    base: ToolInstanceConfig,
+    /// Overrides keyed by instance id
+    instances: BTreeMap<usize, ToolInstanceConfig>,
+}
+
+impl LaunchSettings {
+    pub(super) fn from_config(config: &ToolConfig) -> Result<Self> {
+        let instances = config
+            .instances
+            .iter()
+            .map(|(id, settings)| {
+                let id = id
+                    .trim()
+                    .parse::<usize>()
+                    .with_context(|| format!("Invalid instance id '{}' in instances", id))?;
+                Ok((id, settings.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            base: ToolInstanceConfig {
+                env: config.env.clone(),
+                cwd: config.cwd.clone(),
+                extra_args: config.extra_args.clone(),
+            },
+            instances,
+        })
+    }
+
+    /// Variables set for `instance`, instance overrides winning
+    pub(super) fn env(&self, instance: usize) -> BTreeMap<String, String> {
+        let mut env: BTreeMap<_, _> = self.base.env.clone().into_iter().collect();
+        if let Some(overrides) = self.instances.get(&instance) {
+            env.extend(overrides.env.clone());
+        }
+        env.into_iter()
+            .map(|(key, value)| (key, interpolate(&value, instance)))
+            .collect()
+    }
+
+    /// Working directory for `instance`, if not afkcode's own
+    pub(super) fn cwd(&self, instance: usize) -> Option<PathBuf> {
+        self.instances
+            .get(&instance)
+            .and_then(|overrides| overrides.cwd.as_ref())
+            .or(self.base.cwd.as_ref())
+            .map(|cwd| PathBuf::from(interpolate(&cwd.to_string_lossy(), instance)))
+    }
+
+    /// Arguments appended after the tool's own for `instance`
+    pub(super) fn extra_args(&self, instance: usize) -> Vec<String> {
+        let overrides = self.instances.get(&instance).map(|o| &o.extra_args);
+        self.base
+            .extra_args
+            .iter()
+            .chain(overrides.into_iter().flatten())
+            .map(|arg| interpolate(arg, instance))
+            .collect()
+    }
+
+    /// Apply the environment and working directory for `instance` to `cmd`.
+    /// A missing working directory is reported here rather than as a spawn
+    /// failure that looks like the CLI isn't installed.
+    pub(super) fn configure(&self, cmd: &mut Command, instance: usize) -> Result<()> {
+        cmd.envs(self.env(instance));
+        if let Some(cwd) = self.cwd(instance) {
+            if !cwd.is_dir() {
+                anyhow::bail!("Working directory {} does not exist", cwd.display());
+            }
+            cmd.current_dir(cwd);
+        }
+        Ok(())
+    }
+}
+
+fn interpolate(value: &str, instance: usize) -> String {
+    value.replace(INSTANCE_PLACEHOLDER, &instance.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn instance(env: &[(&str, &str)], cwd: Option<&str>, extra_args: &[&str]) -> ToolInstanceConfig {
+        ToolInstanceConfig {
+            env: env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            cwd: cwd.map(PathBuf::from),
+            extra_args: extra_args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_instance_overrides_and_interpolation() {
+        let base = instance(
+            &[("CLAUDE_CONFIG_DIR", "/cfg/claude-{instance_id}"), ("MODE", "shared")],
+            Some("/work/{instance_id}"),
+            &["--add-dir", "/shared"],
+        );
+        let config = ToolConfig {
+            env: base.env,
+            cwd: base.cwd,
+            extra_args: base.extra_args,
+            instances: HashMap::from([(
+                "1".to_string(),
+                instance(&[("MODE", "solo")], Some("/solo"), &["--profile", "p{instance_id}"]),
+            )]),
+            ..Default::default()
+        };
+        let launch = LaunchSettings::from_config(&config).unwrap();
+
+        assert_eq!(
+            launch.env(0),
+            BTreeMap::from([
+                ("CLAUDE_CONFIG_DIR".to_string(), "/cfg/claude-0".to_string()),
+                ("MODE".to_string(), "shared".to_string()),
+            ])
+        );
+        assert_eq!(launch.cwd(0), Some(PathBuf::from("/work/0")));
+        assert_eq!(launch.extra_args(0), vec!["--add-dir", "/shared"]);
+
+        assert_eq!(launch.env(1)["CLAUDE_CONFIG_DIR"], "/cfg/claude-1");
+        assert_eq!(launch.env(1)["MODE"], "solo");
+        assert_eq!(launch.cwd(1), Some(PathBuf::from("/solo")));
+        assert_eq!(
+            launch.extra_args(1),
+            vec!["--add-dir", "/shared", "--profile", "p1"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_instance_id() {
+        let config = ToolConfig {
+            instances: HashMap::from([("first".to_string(), ToolInstanceConfig::default())]),
+            ..Default::default()
+        };
+        assert!(LaunchSettings::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,2197 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod balance;
+mod cassette;
+mod effort;
+mod events;
+mod launch;
+mod openai;
+mod process;
+mod ratelimit;
+mod replay;
+mod retry;
+mod roles;
+mod squelch;
+mod usage;
+mod warp;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tempfile::NamedTempFile;
+
+use crate::config::{RoleConfig, ToolConfig};
+use crate::constants::{DEFAULT_RATE_LIMIT_SECONDS, DEFAULT_WARP_POLL_SECONDS};
+use crate::logger::Logger;
+use cassette::CassetteEntry;
+use events::EventParser;
+use launch::LaunchSettings;
+use replay::ReplayTool;
+use retry::{Classification, InvokeFailure};
+
+pub use balance::ToolStrategy;
+pub use cassette::{Cassette, CassetteRecorder};
+pub use effort::ReasoningEffort;
+pub use events::{EventFormat, StructuredResult};
+pub use process::{
+    run_command, terminate_running_tools, InvokeTimeouts, OutputSink, OutputStream,
+};
+pub use ratelimit::RateLimit;
+pub use retry::RetryPolicy;
+pub use roles::Role;
+pub use squelch::SquelchRegistry;
+pub use usage::{TokenPricing, Usage, UsageBudget, UsageLedger};
+
+/// How often the shutdown flag is checked while waiting out a retry delay or
+/// a remote task's poll interval
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// LLM tool kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmToolKind {
+    Gemini,
+    Codex,
+    Claude,
+    Aider,
+    OpenCode,
+    CursorAgent,
+    WarpAgent,
+    /// Any OpenAI-compatible `/v1/chat/completions` endpoint
+    OpenAi,
+    /// User-defined CLI agent declared in a `[tool.<name>]` config table
+    Custom,
+    /// Scripted responses read from a `replay_script`, for dry runs
+    Replay,
+}
+
+/// How the prompt is handed to a CLI tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptDelivery {
+    /// Written to the child's stdin
+    #[default]
+    Stdin,
+    /// Passed as a command-line argument
+    #[serde(alias = "arg")]
+    Argv,
+    /// Written to a temp file whose path is passed as an argument
+    File,
+}
+
+/// Everything a single tool invocation produced
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code of a CLI tool; `None` for HTTP backends or a signal death
+    pub exit_code: Option<i32>,
+    /// Tokens and cost, if the tool reported them
+    pub usage: Option<Usage>,
+    /// Parsed event stream, for tools run in structured mode
+    pub structured: Option<StructuredResult>,
+}
+
+impl ToolOutput {
+    /// Output of an HTTP backend, which has no exit code
+    fn http(stdout: String, stderr: String) -> Self {
+        Self {
+            stdout,
+            stderr,
+            ..Default::default()
+        }
+    }
+
+    /// What the agent said: the final assistant message in structured mode,
+    /// otherwise all of stdout
+    pub fn message(&self) -> &str {
+        match &self.structured {
+            Some(result) => &result.final_message,
+            None => &self.stdout,
+        }
+    }
+
+    /// The output as seen by rate-limit detection. In structured mode tool
+    /// results are left out; reported errors count as stderr.
+    fn for_classification(&self) -> ToolOutput {
+        let Some(result) = &self.structured else {
+            return self.clone();
+        };
+        let mut stderr = self.stderr.clone();
+        for error in &result.errors {
+            stderr.push('\n');
+            stderr.push_str(error);
+        }
+        ToolOutput {
+            stdout: result.final_message.clone(),
+            stderr,
+            exit_code: self.exit_code,
+            ..Default::default()
+        }
+    }
+}
+
+/// An agent conversation that a later invocation of the same tool can resume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentSession {
+    /// Name of the tool the session belongs to
+    pub tool: String,
+    pub id: String,
+    /// How full the context was at the end of the last turn, if known
+    pub context_tokens: Option<u64>,
+}
+
+/// A user-defined tool: its name in `--tools` plus its config table
+#[derive(Debug)]
+struct CustomTool {
+    name: String,
+    config: ToolConfig,
+}
+
+/// LLM tool configuration and invocation logic
+#[derive(Debug, Clone)]
+pub struct LlmTool {
+    kind: LlmToolKind,
+    model: Option<String>,
+    api_key: Option<String>,  // For HTTP-based tools like Warp Agent
+    api_base: Option<String>, // Endpoint override for HTTP-based tools
+    custom: Option<Arc<CustomTool>>,
+    /// Shared between clones so the script advances across invocations
+    replay: Option<Arc<ReplayTool>>,
+    timeouts: InvokeTimeouts,
+    /// How transient failures are retried before the chain falls back
+    retry: RetryPolicy,
+    /// Used to estimate cost when the tool doesn't report it
+    pricing: Option<TokenPricing>,
+    /// Request machine-readable output from CLIs that support it
+    structured: bool,
+    /// Session to resume instead of starting a new conversation
+    session: Option<String>,
+    /// Reasoning effort to ask for; `None` leaves the tool's default
+    effort: Option<ReasoningEffort>,
+    /// Set on Ctrl+C; remote tasks are cancelled when it is raised
+    shutdown: Option<Arc<AtomicBool>>,
+    /// How often remote agent tasks (Warp) are polled
+    poll_interval: Duration,
+    /// Environment, working directory and extra arguments for CLI processes
+    launch: Arc<LaunchSettings>,
+    /// Parallel instance id substituted for `{instance_id}`
+    instance_id: usize,
+}
+
+impl LlmTool {
+    pub fn from_name(name: &str) -> Result<Self> {
+        let kind = match name.to_lowercase().as_str() {
+            "gemini" => LlmToolKind::Gemini,
+            "codex" => LlmToolKind::Codex,
+            "claude" => LlmToolKind::Claude,
+            "aider" => LlmToolKind::Aider,
+            "opencode" => LlmToolKind::OpenCode,
+            "cursor" | "cursor-agent" => LlmToolKind::CursorAgent,
+            "warp" | "warp-agent" => LlmToolKind::WarpAgent,
+            "openai" | "openai-compatible" => LlmToolKind::OpenAi,
+            "replay" => anyhow::bail!(
+                "The replay tool needs a script: set replay_script in a [tool.replay] table in afkcode.toml"
+            ),
+            _ => anyhow::bail!(
+                "Unsupported LLM tool: {}. Supported: gemini, codex, claude, aider, opencode, cursor, warp, openai, replay, or a [tool.{}] table with a command or replay_script in afkcode.toml",
+                name,
+                name
+            ),
+        };
+        Ok(Self::base(kind))
+    }
+
+    /// A tool of `kind` with no model, credentials or per-tool settings
+    fn base(kind: LlmToolKind) -> Self {
+        Self {
+            kind,
+            model: None,
+            api_key: None,
+            api_base: None,
+            custom: None,
+            replay: None,
+            timeouts: InvokeTimeouts::default(),
+            retry: RetryPolicy::default(),
+            pricing: None,
+            structured: false,
+            session: None,
+            effort: None,
+            shutdown: None,
+            poll_interval: Duration::from_secs(DEFAULT_WARP_POLL_SECONDS),
+            launch: Arc::default(),
+            instance_id: 0,
+        }
+    }
+
+    /// Build a user-defined tool from its `[tool.<name>]` table.
+    pub fn custom(name: &str, config: &ToolConfig) -> Result<Self> {
+        if config.command.as_deref().is_none_or(|c| c.trim().is_empty()) {
+            anyhow::bail!("Custom tool '{}' has no command configured", name);
+        }
+        Ok(Self {
+            model: config.model.clone(),
+            custom: Some(Arc::new(CustomTool {
+                name: name.to_string(),
+                config: config.clone(),
+            })),
+            ..Self::base(LlmToolKind::Custom)
+        })
+    }
+
+    /// Build a scripted tool that answers from `script`.
+    pub fn replay(name: &str, script: &Path) -> Result<Self> {
+        Ok(Self::scripted(Arc::new(ReplayTool::load(name, script)?)))
+    }
+
+    /// Build a tool that answers with `name`'s exchanges from a cassette.
+    pub fn from_cassette(name: &str, cassette: &Cassette) -> Result<Self> {
+        Ok(Self::scripted(cassette.replay_tool(name)?))
+    }
+
+    fn scripted(replay: Arc<ReplayTool>) -> Self {
+        Self {
+            replay: Some(replay),
+            ..Self::base(LlmToolKind::Replay)
+        }
+    }
+
+    /// Resolve a tool name, preferring user-defined tools over built-ins.
+    /// When replaying a cassette every tool answers from the recording.
+    pub fn resolve(name: &str, model_config: &ModelConfig) -> Result<Self> {
+        let timeouts = model_config.get_timeouts_for_tool(name);
+        let pricing = model_config.get_pricing_for_tool(name);
+        let retry = model_config.get_retry_policy_for_tool(name);
+        let launch = model_config.get_launch_settings_for_tool(name)?;
+        // Scripted tools retry as configured, but without waiting
+        if let Some(cassette) = &model_config.replay_cassette {
+            return Ok(Self::from_cassette(name, cassette)?
+                .with_pricing(pricing)
+                .with_retry_policy(retry.immediate()));
+        }
+        if let Some(config) = model_config.tool_settings.get(name) {
+            if config.command.is_some() {
+                return Ok(Self::custom(name, config)?
+                    .with_timeouts(timeouts)
+                    .with_retry_policy(retry)
+                    .with_pricing(pricing)
+                    .with_reasoning_effort(model_config.get_reasoning_effort_for_tool(name))
+                    .with_launch_settings(launch)
+                    .with_shutdown_flag(model_config.shutdown_flag.clone()));
+            }
+            if let Some(script) = &config.replay_script {
+                return Ok(Self::replay(name, script)?
+                    .with_timeouts(timeouts)
+                    .with_retry_policy(retry.immediate())
+                    .with_pricing(pricing));
+            }
+        }
+
+        let tool = Self::from_name(name)?;
+        let structured = model_config.get_structured_output_for_tool(name)
+            || (model_config.resume_sessions && tool.supports_resume());
+        let model = model_config.get_model_for_tool(tool.kind);
+        let api_key = model_config.get_api_key_for_tool(tool.kind);
+        let api_base = model_config.get_api_base_for_tool(tool.kind);
+        Ok(tool
+            .with_model(model)
+            .with_api_key(api_key)
+            .with_api_base(api_base)
+            .with_timeouts(timeouts)
+            .with_retry_policy(retry)
+            .with_pricing(pricing)
+            .with_structured_output(structured)
+            .with_reasoning_effort(model_config.get_reasoning_effort_for_tool(name))
+            .with_launch_settings(launch)
+            .with_shutdown_flag(model_config.shutdown_flag.clone())
+            .with_poll_interval(model_config.warp_poll_interval))
+    }
+
+    pub fn with_model(mut self, model: Option<String>) -> Self {
+        self.model = model;
+        self
+    }
+    
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    pub fn with_api_base(mut self, api_base: Option<String>) -> Self {
+        self.api_base = api_base;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: InvokeTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    pub fn with_pricing(mut self, pricing: Option<TokenPricing>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// Ask Claude, Codex and Gemini for their JSON event streams
+    pub fn with_structured_output(mut self, structured: bool) -> Self {
+        self.structured = structured;
+        self
+    }
+
+    pub fn with_shutdown_flag(mut self, shutdown: Option<Arc<AtomicBool>>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Whether afkcode has been asked to shut down
+    fn shutdown_requested(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Sleep for `duration`, waking early if shutdown is requested. Returns
+    /// true if it was.
+    fn sleep_unless_shutdown(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.shutdown_requested() {
+                return true;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            std::thread::sleep(remaining.min(SHUTDOWN_CHECK_INTERVAL));
+        }
+    }
+
+    /// Poll interval for remote agent tasks; `None` keeps the default
+    pub fn with_poll_interval(mut self, interval: Option<Duration>) -> Self {
+        if let Some(interval) = interval {
+            self.poll_interval = interval;
+        }
+        self
+    }
+
+    fn with_launch_settings(mut self, launch: LaunchSettings) -> Self {
+        self.launch = Arc::new(launch);
+        self
+    }
+
+    /// Run as the given parallel instance, for `{instance_id}` in the
+    /// tool's process settings
+    pub fn with_instance_id(mut self, id: usize) -> Self {
+        self.instance_id = id;
+        self
+    }
+
+    pub fn with_reasoning_effort(mut self, effort: Option<ReasoningEffort>) -> Self {
+        self.effort = effort;
+        self
+    }
+
+    /// Ask for `effort` where that is safe without the user choosing it:
+    /// Claude Code (a prompt hint or thinking budget) and Codex (a setting
+    /// its models all take). Other tools keep their configured effort, since
+    /// their models may reject the parameter or the level.
+    pub fn with_default_reasoning_effort(self, effort: Option<ReasoningEffort>) -> Self {
+        match self.kind {
+            LlmToolKind::Claude | LlmToolKind::Codex if effort.is_some() => {
+                self.with_reasoning_effort(effort)
+            }
+            _ => self,
+        }
+    }
+
+    /// Continue the given session on the next invocation
+    pub fn with_session(mut self, session: Option<String>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Whether the tool can continue a previous session. Custom tools need
+    /// `resume_args`.
+    pub fn supports_resume(&self) -> bool {
+        match self.kind {
+            LlmToolKind::Claude | LlmToolKind::Codex | LlmToolKind::Gemini => true,
+            LlmToolKind::Custom => !self.custom_tool().config.resume_args.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Arguments that resume `session_id`, appended after all other arguments
+    fn resume_args(&self, session_id: &str) -> Vec<String> {
+        match self.kind {
+            LlmToolKind::Claude | LlmToolKind::Gemini => {
+                vec!["--resume".to_string(), session_id.to_string()]
+            }
+            // `codex exec [OPTIONS] resume <SESSION_ID>`, prompt still on stdin
+            LlmToolKind::Codex => vec!["resume".to_string(), session_id.to_string()],
+            LlmToolKind::Custom => self
+                .custom_tool()
+                .config
+                .resume_args
+                .iter()
+                .map(|arg| arg.replace("{session_id}", session_id))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The event schema this invocation will produce, if any. Custom tools
+    /// declare theirs with `event_format`; a cassette keeps the recorded one.
+    fn event_format(&self) -> Option<EventFormat> {
+        match self.kind {
+            LlmToolKind::Custom => self.custom_tool().config.event_format,
+            LlmToolKind::Replay => self.replay_tool().event_format,
+            _ if !self.structured => None,
+            LlmToolKind::Claude => Some(EventFormat::Claude),
+            LlmToolKind::Codex => Some(EventFormat::Codex),
+            LlmToolKind::Gemini => Some(EventFormat::Gemini),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self.kind {
+            LlmToolKind::Gemini => "gemini",
+            LlmToolKind::Codex => "codex",
+            LlmToolKind::Claude => "claude",
+            LlmToolKind::Aider => "aider",
+            LlmToolKind::OpenCode => "opencode",
+            LlmToolKind::CursorAgent => "cursor",
+            LlmToolKind::WarpAgent => "warp",
+            LlmToolKind::OpenAi => "openai",
+            LlmToolKind::Custom => self.custom_tool().name.as_str(),
+            LlmToolKind::Replay => self.replay_tool().name.as_str(),
+        }
+    }
+
+    pub fn kind(&self) -> LlmToolKind {
+        self.kind
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    pub fn api_key(&self) -> Option<&str> {
+        self.api_key.as_deref()
+    }
+
+    /// Program a CLI tool runs; `None` for HTTP backends and scripted tools
+    pub fn executable(&self) -> Option<&str> {
+        match self.kind {
+            LlmToolKind::WarpAgent | LlmToolKind::OpenAi | LlmToolKind::Replay => None,
+            _ => Some(self.command()),
+        }
+    }
+
+    /// First line printed by `<executable> --version`
+    pub fn version(&self, timeout: Duration) -> Result<String> {
+        let program = self
+            .executable()
+            .with_context(|| format!("{} is not a CLI tool", self.name()))?;
+        let mut cmd = Command::new(program);
+        cmd.arg("--version");
+        self.launch.configure(&mut cmd, self.instance_id)?;
+        let child = process::spawn(&mut cmd)
+            .with_context(|| format!("Failed to run {} --version", program))?;
+        let timeouts = InvokeTimeouts {
+            wall_clock: Some(timeout),
+            idle: None,
+        };
+        let output = process::collect_output(child, None, None, timeouts)?;
+        let first_line = |text: &str| {
+            text.lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(str::to_string)
+        };
+        match output.exit_code {
+            Some(0) => first_line(&output.stdout)
+                .or_else(|| first_line(&output.stderr))
+                .context("--version printed nothing"),
+            code => anyhow::bail!(
+                "--version exited with {}: {}",
+                code.map_or("a signal".to_string(), |c| c.to_string()),
+                first_line(&output.stderr).unwrap_or_default()
+            ),
+        }
+    }
+
+    fn custom_tool(&self) -> &CustomTool {
+        self.custom
+            .as_deref()
+            .expect("custom tool kind without a custom definition")
+    }
+
+    fn replay_tool(&self) -> &ReplayTool {
+        self.replay
+            .as_deref()
+            .expect("replay tool kind without a script")
+    }
+
+    fn command(&self) -> &str {
+        match self.kind {
+            LlmToolKind::Gemini => "gemini",
+            LlmToolKind::Codex => "codex",
+            LlmToolKind::Claude => "claude",
+            LlmToolKind::Aider => "aider",
+            LlmToolKind::OpenCode => "opencode",
+            LlmToolKind::CursorAgent => "cursor-agent",
+            LlmToolKind::WarpAgent => "warp",  // Not actually used for HTTP-based tool
+            LlmToolKind::OpenAi => "openai",   // Not actually used for HTTP-based tool
+            LlmToolKind::Replay => "replay",   // Not actually used; answers from its script
+            LlmToolKind::Custom => self.custom_tool().config.command.as_deref().unwrap_or_default(),
+        }
+    }
+
+    fn prompt_delivery(&self) -> PromptDelivery {
+        match self.kind {
+            LlmToolKind::Gemini
+            | LlmToolKind::Aider
+            | LlmToolKind::OpenCode
+            | LlmToolKind::CursorAgent => PromptDelivery::Argv,
+            LlmToolKind::Custom => self.custom_tool().config.prompt_mode.unwrap_or_default(),
+            _ => PromptDelivery::Stdin,
+        }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = match self.kind {
+            LlmToolKind::Gemini => vec!["--yolo".to_string()],
+            LlmToolKind::Codex => vec!["exec".to_string()],
+            LlmToolKind::Claude => vec![
+                "--print".to_string(),
+                "--dangerously-skip-permissions".to_string(),
+            ],
+            LlmToolKind::Aider => vec![
+                "--yes-always".to_string(),
+                "--no-pretty".to_string(),
+                "--no-stream".to_string(),
+                "--no-check-update".to_string(),
+                "--message".to_string(),
+                "{prompt}".to_string(),
+            ],
+            LlmToolKind::OpenCode => vec!["run".to_string()],
+            LlmToolKind::CursorAgent => vec![
+                "--print".to_string(),
+                "--force".to_string(),
+                "--output-format".to_string(),
+                "text".to_string(),
+            ],
+            LlmToolKind::WarpAgent | LlmToolKind::OpenAi | LlmToolKind::Replay => vec![],  // No CLI args
+            LlmToolKind::Custom => {
+                let model = self.model.as_deref().unwrap_or_default();
+                self.custom_tool()
+                    .config
+                    .args
+                    .iter()
+                    .map(|arg| arg.replace("{model}", model))
+                    .collect()
+            }
+        };
+
+        if self.event_format().is_some() {
+            match self.kind {
+                LlmToolKind::Gemini => args.extend(["--output-format".into(), "stream-json".into()]),
+                LlmToolKind::Codex => args.push("--json".to_string()),
+                // stream-json requires --verbose in print mode
+                LlmToolKind::Claude => args.extend([
+                    "--output-format".into(),
+                    "stream-json".into(),
+                    "--verbose".into(),
+                ]),
+                _ => {}
+            }
+        }
+
+        // Add model argument if specified
+        if let Some(ref model) = self.model {
+            match self.kind {
+                LlmToolKind::Gemini => {
+                    args.push("-m".to_string());
+                    args.push(model.clone());
+                }
+                LlmToolKind::Codex => {
+                    args.push("-m".to_string());
+                    args.push(model.clone());
+                }
+                LlmToolKind::Claude
+                | LlmToolKind::Aider
+                | LlmToolKind::OpenCode
+                | LlmToolKind::CursorAgent => {
+                    args.push("--model".to_string());
+                    args.push(model.clone());
+                }
+                LlmToolKind::WarpAgent | LlmToolKind::OpenAi => {
+                    // Model handled via HTTP API, not CLI args
+                }
+                LlmToolKind::Replay => {}
+                LlmToolKind::Custom => {
+                    if let Some(ref flag) = self.custom_tool().config.model_flag {
+                        args.push(flag.clone());
+                        args.push(model.clone());
+                    }
+                }
+            }
+        }
+
+        args
+    }
+
+    fn rate_limit_patterns(&self) -> Vec<&str> {
+        match self.kind {
+            LlmToolKind::Gemini => vec![
+                "rate limit",
+                "quota exceeded",
+                "too many requests",
+                "resource exhausted",
+            ],
+            LlmToolKind::Codex => vec![
+                "rate limit reached",
+                "rate_limit_error",
+                "too many requests",
+            ],
+            LlmToolKind::Claude => vec![
+                "usage limit reached",
+                "rate limit reached",
+                "rate_limit_error",
+                "limit will reset",
+            ],
+            LlmToolKind::Aider => vec![
+                "ratelimiterror",
+                "rate limit",
+                "quota exceeded",
+            ],
+            LlmToolKind::OpenCode => vec![
+                "rate limit",
+                "too many requests",
+                "quota exceeded",
+            ],
+            LlmToolKind::CursorAgent => vec![
+                "usage limit",
+                "rate limit",
+                "too many requests",
+            ],
+            LlmToolKind::WarpAgent => vec![
+                "rate limit",
+                "too many requests",
+                "quota exceeded",
+            ],
+            LlmToolKind::OpenAi => vec![
+                "rate limit",
+                "too many requests",
+                "quota exceeded",
+            ],
+            LlmToolKind::Replay => vec![
+                "rate limit",
+                "too many requests",
+                "quota exceeded",
+            ],
+            LlmToolKind::Custom => self
+                .custom_tool()
+                .config
+                .rate_limit_patterns
+                .iter()
+                .map(|p| p.as_str())
+                .collect(),
+        }
+    }
+
+    /// Check an invocation's output for a rate limit. HTTP 429 statuses and
+    /// structured error payloads are recognised for every tool.
+    pub fn rate_limit(&self, output: &ToolOutput) -> Option<RateLimit> {
+        ratelimit::detect(&self.rate_limit_patterns(), &output.for_classification())
+    }
+
+    pub fn invoke(&self, prompt: &str) -> Result<ToolOutput> {
+        self.invoke_with(prompt, None)
+    }
+
+    /// Invoke the LLM, passing each output line to `sink` as it arrives.
+    /// The full stdout/stderr are still returned once the tool finishes.
+    pub fn invoke_streaming(
+        &self,
+        prompt: &str,
+        sink: &mut OutputSink<'_>,
+    ) -> Result<ToolOutput> {
+        self.invoke_with(prompt, Some(sink))
+    }
+
+    fn invoke_with(
+        &self,
+        prompt: &str,
+        sink: Option<&mut OutputSink<'_>>,
+    ) -> Result<ToolOutput> {
+        let Some(format) = self.event_format() else {
+            let mut output = self.run_tool(prompt, sink)?;
+            if output.usage.is_none() {
+                output.usage = usage::parse_usage(&output.stdout, &output.stderr);
+            }
+            return Ok(self.with_cost(output));
+        };
+
+        // Show the events as readable text rather than raw JSON
+        let mut output = match sink {
+            Some(sink) => {
+                let mut parser = EventParser::new(format);
+                let mut render = |stream: OutputStream, line: &str| match stream {
+                    OutputStream::Stdout => {
+                        for shown in parser.push_line(line) {
+                            sink(OutputStream::Stdout, &shown);
+                        }
+                    }
+                    OutputStream::Stderr => sink(stream, line),
+                };
+                let output = self.run_tool(prompt, Some(&mut render));
+                for shown in parser.finish().1 {
+                    sink(OutputStream::Stdout, &shown);
+                }
+                output?
+            }
+            None => self.run_tool(prompt, None)?,
+        };
+        let result = EventParser::parse(format, &output.stdout);
+        if output.usage.is_none() {
+            output.usage = result.usage;
+        }
+        output.structured = Some(result);
+        Ok(self.with_cost(output))
+    }
+
+    fn with_cost(&self, mut output: ToolOutput) -> ToolOutput {
+        output.usage = output
+            .usage
+            .map(|usage| usage.with_estimated_cost(self.pricing));
+        output
+    }
+
+    fn run_tool(
+        &self,
+        prompt: &str,
+        sink: Option<&mut OutputSink<'_>>,
+    ) -> Result<ToolOutput> {
+        // HTTP-based and scripted tools don't spawn a process
+        let direct_result = match self.kind {
+            LlmToolKind::WarpAgent => Some(self.invoke_warp_agent(prompt)),
+            LlmToolKind::OpenAi => Some(self.invoke_openai(prompt)),
+            LlmToolKind::Replay => Some(self.replay_tool().respond(prompt, self.timeouts)),
+            _ => None,
+        };
+        if let Some(result) = direct_result {
+            let output = result?;
+            if let Some(sink) = sink {
+                process::replay_to_sink(&output.stdout, &output.stderr, sink);
+            }
+            return Ok(output);
+        }
+
+        let mut args = self.args();
+        let mut prompt = prompt.to_string();
+        let mut env = Vec::new();
+        if let Some(effort) = self.effort {
+            match self.kind {
+                // Claude Code has no effort flag: thinking is switched off in
+                // the prompt, or given a token budget
+                LlmToolKind::Claude => match effort.claude_thinking_tokens() {
+                    None => {
+                        prompt = format!("<thinking_mode>disabled</thinking_mode>\n\n{}", prompt)
+                    }
+                    Some(tokens) => env.push(("MAX_THINKING_TOKENS", tokens.to_string())),
+                },
+                LlmToolKind::Codex => {
+                    args.push("-c".to_string());
+                    args.push(format!("model_reasoning_effort=\"{}\"", effort));
+                }
+                LlmToolKind::Aider => {
+                    args.push("--reasoning-effort".to_string());
+                    args.push(effort.to_string());
+                }
+                LlmToolKind::Custom => args.extend(
+                    self.custom_tool()
+                        .config
+                        .reasoning_args
+                        .iter()
+                        .map(|arg| arg.replace("{effort}", effort.name())),
+                ),
+                // The other CLIs don't have a known effort setting
+                _ => {}
+            }
+        }
+
+        args.extend(self.launch.extra_args(self.instance_id));
+        if let Some(session_id) = &self.session {
+            args.extend(self.resume_args(session_id));
+        }
+
+        // Gemini takes the prompt as a positional argument, others use stdin;
+        // custom tools choose their own delivery.
+        // The temp file must outlive the child process in file mode.
+        let mut _prompt_file: Option<NamedTempFile> = None;
+        match self.prompt_delivery() {
+            PromptDelivery::Stdin => {}
+            PromptDelivery::Argv => insert_placeholder(&mut args, "{prompt}", &prompt),
+            PromptDelivery::File => {
+                let mut file = NamedTempFile::new().context("Failed to create prompt file")?;
+                file.write_all(prompt.as_bytes())
+                    .context("Failed to write prompt file")?;
+                file.flush().context("Failed to flush prompt file")?;
+                let path = file.path().to_string_lossy().to_string();
+                insert_placeholder(&mut args, "{prompt_file}", &path);
+                _prompt_file = Some(file);
+            }
+        }
+
+        let mut cmd = Command::new(self.command());
+        cmd.args(args).envs(env);
+        // Configured environment wins over the effort's
+        self.launch
+            .configure(&mut cmd, self.instance_id)
+            .with_context(|| InvokeFailure::Spawn(format!("Failed to start {}", self.name())))?;
+        let child = process::spawn(&mut cmd).with_context(|| {
+            InvokeFailure::Spawn(format!(
+                "Failed to spawn {} process. Is {} CLI installed?",
+                self.name(),
+                self.command()
+            ))
+        })?;
+
+        // Only stdin-delivered tools get the prompt on stdin; for the rest it
+        // is just closed so the child doesn't wait for input
+        let input = (self.prompt_delivery() == PromptDelivery::Stdin).then_some(prompt.as_str());
+        process::collect_output(child, input, sink, self.timeouts)
+    }
+}
+
+/// Substitute `placeholder` in the argument list, or append `value` as a final
+/// argument if no argument mentions the placeholder.
+fn insert_placeholder(args: &mut Vec<String>, placeholder: &str, value: &str) {
+    if args.iter().any(|arg| arg.contains(placeholder)) {
+        for arg in args.iter_mut() {
+            *arg = arg.replace(placeholder, value);
+        }
+    } else {
+        args.push(value.to_string());
+    }
+}
+
+/// Per-tool model configuration
+#[derive(Debug, Clone, Default)]
+pub struct ModelConfig {
+    pub gemini_model: Option<String>,
+    pub claude_model: Option<String>,
+    pub codex_model: Option<String>,
+    pub aider_model: Option<String>,
+    pub opencode_model: Option<String>,
+    pub cursor_model: Option<String>,
+    pub warp_model: Option<String>,
+    pub warp_api_key: Option<String>,
+    /// Warp Agent API base URL override (e.g. a local stand-in)
+    pub warp_api_base: Option<String>,
+    /// How often Warp Agent tasks are polled
+    pub warp_poll_interval: Option<Duration>,
+    pub openai_model: Option<String>,
+    pub openai_api_key: Option<String>,
+    pub openai_base_url: Option<String>,
+    /// `[tool.<name>]` tables from the config file (custom tool definitions)
+    pub tool_settings: HashMap<String, ToolConfig>,
+    /// Per-invocation limits, overridable per tool in `[tool.<name>]`
+    pub timeouts: InvokeTimeouts,
+    /// Retries after transient failures, overridable per tool
+    pub retry: RetryPolicy,
+    /// State file for rate-limit squelches; `None` keeps them in memory only
+    pub rate_limit_state: Option<PathBuf>,
+    /// Run Claude, Codex and Gemini in their JSON event-stream modes
+    pub structured_output: bool,
+    /// Worker turns resume sessions; built-in tools that can resume run in
+    /// structured mode regardless, since only their event streams report
+    /// session ids
+    pub resume_sessions: bool,
+    /// Raised on Ctrl+C so long-running remote tasks can be cancelled
+    pub shutdown_flag: Option<Arc<AtomicBool>>,
+    /// Where every exchange is recorded, if anywhere
+    pub recorder: Option<CassetteRecorder>,
+    /// Answer every prompt from this recording instead of running tools
+    pub replay_cassette: Option<Arc<Cassette>>,
+    /// How each invocation's starting tool is chosen
+    pub tool_strategy: ToolStrategy,
+    /// Give each parallel instance a different primary tool
+    pub spread_instances: bool,
+    /// Roles routed to their own tools or models (`[roles.<role>]` tables)
+    pub roles: HashMap<Role, RoleConfig>,
+}
+
+impl ModelConfig {
+    pub fn get_model_for_tool(&self, kind: LlmToolKind) -> Option<String> {
+        match kind {
+            LlmToolKind::Gemini => self.gemini_model.clone(),
+            LlmToolKind::Claude => self.claude_model.clone(),
+            LlmToolKind::Codex => self.codex_model.clone(),
+            LlmToolKind::Aider => self.aider_model.clone(),
+            LlmToolKind::OpenCode => self.opencode_model.clone(),
+            LlmToolKind::CursorAgent => self.cursor_model.clone(),
+            LlmToolKind::WarpAgent => self.warp_model.clone(),
+            LlmToolKind::OpenAi => self.openai_model.clone(),
+            LlmToolKind::Custom => None, // Taken from the tool's own table
+            LlmToolKind::Replay => None,
+        }
+    }
+    
+    pub fn get_api_key_for_tool(&self, kind: LlmToolKind) -> Option<String> {
+        match kind {
+            LlmToolKind::WarpAgent => self.warp_api_key.clone(),
+            LlmToolKind::OpenAi => self.openai_api_key.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn get_timeouts_for_tool(&self, name: &str) -> InvokeTimeouts {
+        let mut timeouts = self.timeouts;
+        if let Some(settings) = self.tool_settings.get(name) {
+            let to_limit = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+            if let Some(secs) = settings.timeout_seconds {
+                timeouts.wall_clock = to_limit(secs);
+            }
+            if let Some(secs) = settings.idle_timeout_seconds {
+                timeouts.idle = to_limit(secs);
+            }
+        }
+        timeouts
+    }
+
+    pub fn get_retry_policy_for_tool(&self, name: &str) -> RetryPolicy {
+        let mut retry = self.retry;
+        if let Some(settings) = self.tool_settings.get(name) {
+            if let Some(max_retries) = settings.max_retries {
+                retry.max_retries = max_retries;
+            }
+            if let Some(secs) = settings.retry_delay_seconds {
+                retry.base_delay = Duration::from_secs(secs);
+            }
+            if let Some(secs) = settings.retry_max_delay_seconds {
+                retry.max_delay = Duration::from_secs(secs);
+            }
+        }
+        retry
+    }
+
+    pub fn get_structured_output_for_tool(&self, name: &str) -> bool {
+        self.tool_settings
+            .get(name)
+            .and_then(|settings| settings.structured_output)
+            .unwrap_or(self.structured_output)
+    }
+
+    /// Process settings from the tool's table; empty for tools without one
+    fn get_launch_settings_for_tool(&self, name: &str) -> Result<LaunchSettings> {
+        match self.tool_settings.get(name) {
+            Some(settings) => LaunchSettings::from_config(settings)
+                .with_context(|| format!("Invalid [tool.{}] settings", name)),
+            None => Ok(LaunchSettings::default()),
+        }
+    }
+
+    pub fn get_pricing_for_tool(&self, name: &str) -> Option<TokenPricing> {
+        let settings = self.tool_settings.get(name)?;
+        if settings.input_price.is_none() && settings.output_price.is_none() {
+            return None;
+        }
+        Some(TokenPricing {
+            input_per_mtok: settings.input_price.unwrap_or_default(),
+            output_per_mtok: settings.output_price.unwrap_or_default(),
+        })
+    }
+
+    /// Effort a tool is asked for when the role doesn't set one
+    pub fn get_reasoning_effort_for_tool(&self, name: &str) -> Option<ReasoningEffort> {
+        self.tool_settings
+            .get(name)
+            .and_then(|settings| settings.reasoning_effort)
+    }
+
+    /// Effort `role`'s table sets for its prompts, overriding the tools'
+    pub fn get_reasoning_effort_for_role(&self, role: Role) -> Option<ReasoningEffort> {
+        self.roles
+            .get(&role)
+            .and_then(|settings| settings.reasoning_effort)
+    }
+
+    /// Tools `role` runs with: its own list, or `default`
+    pub fn get_tools_for_role<'a>(&'a self, role: Role, default: &'a str) -> &'a str {
+        self.roles
+            .get(&role)
+            .and_then(|settings| settings.tools.as_deref())
+            .unwrap_or(default)
+    }
+
+    /// The config a role routed with `[roles.<role>]` runs with: the role's
+    /// models applied, and no further routing. `None` for roles that keep the
+    /// run's tools and models.
+    pub fn for_role(&self, role: Role) -> Result<Option<ModelConfig>> {
+        let Some(settings) = self
+            .roles
+            .get(&role)
+            .filter(|settings| settings.tools.is_some() || !settings.models.is_empty())
+        else {
+            return Ok(None);
+        };
+        let mut config = ModelConfig {
+            roles: HashMap::new(),
+            ..self.clone()
+        };
+        for (tool, model) in &settings.models {
+            config
+                .set_model_for_tool(tool, model)
+                .with_context(|| format!("Invalid model override in [roles.{}]", role))?;
+        }
+        Ok(Some(config))
+    }
+
+    fn set_model_for_tool(&mut self, name: &str, model: &str) -> Result<()> {
+        let model = Some(model.to_string());
+        if let Some(settings) = self.tool_settings.get_mut(name) {
+            if settings.command.is_some() {
+                settings.model = model;
+                return Ok(());
+            }
+            // Scripted tools have no model
+            if settings.replay_script.is_some() {
+                return Ok(());
+            }
+        }
+        match LlmTool::from_name(name)?.kind {
+            LlmToolKind::Gemini => self.gemini_model = model,
+            LlmToolKind::Claude => self.claude_model = model,
+            LlmToolKind::Codex => self.codex_model = model,
+            LlmToolKind::Aider => self.aider_model = model,
+            LlmToolKind::OpenCode => self.opencode_model = model,
+            LlmToolKind::CursorAgent => self.cursor_model = model,
+            LlmToolKind::WarpAgent => self.warp_model = model,
+            LlmToolKind::OpenAi => self.openai_model = model,
+            LlmToolKind::Custom | LlmToolKind::Replay => {}
+        }
+        Ok(())
+    }
+
+    pub fn get_api_base_for_tool(&self, kind: LlmToolKind) -> Option<String> {
+        match kind {
+            LlmToolKind::OpenAi => self.openai_base_url.clone(),
+            LlmToolKind::WarpAgent => self.warp_api_base.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// Apply a builder step to every role chain
+fn map_roles(
+    roles: HashMap<Role, LlmToolChain>,
+    f: impl Fn(LlmToolChain) -> LlmToolChain,
+) -> HashMap<Role, LlmToolChain> {
+    roles.into_iter().map(|(role, chain)| (role, f(chain))).collect()
+}
+
+/// One invocation, as written to a cassette
+struct Exchange<'a> {
+    tool: &'a LlmTool,
+    prompt: &'a str,
+    duration: Duration,
+}
+
+/// Manages multiple LLM tools with automatic fallback
+pub struct LlmToolChain {
+    tools: Vec<LlmTool>,
+    /// Selection weight of each tool, for the weighted strategy
+    weights: Vec<u32>,
+    strategy: ToolStrategy,
+    current_index: usize,
+    /// Tool the current invocation started with; wrapping fallback stops here
+    turn_start: usize,
+    /// Where the next round-robin invocation starts
+    rotation: usize,
+    /// When each squelched tool becomes usable again
+    squelches: SquelchRegistry,
+    /// Squelch length when the tool gave no reset hint
+    rate_limit_timeout: Duration,
+    /// Prepended to each live output line (e.g. `[3]` for parallel instance 3)
+    output_prefix: Option<String>,
+    /// Parallel instance id, for per-instance usage totals
+    instance_id: Option<usize>,
+    /// Run-wide token and cost totals, possibly shared with other chains
+    usage: UsageLedger,
+    /// Usage since the last `take_turn_usage`
+    turn_usage: Option<Usage>,
+    /// Session the next invocation should continue, if its tool is used
+    resume: Option<AgentSession>,
+    /// Session the last successful invocation ran in
+    turn_session: Option<AgentSession>,
+    /// Cassette every invocation is written to
+    recorder: Option<CassetteRecorder>,
+    /// Chains for roles routed to their own tools or models
+    roles: HashMap<Role, LlmToolChain>,
+    /// Reasoning effort of each role that has one
+    role_efforts: HashMap<Role, ReasoningEffort>,
+    /// Effort of the role last selected with `for_role`, overriding the tools'
+    effort: Option<ReasoningEffort>,
+    /// That role's default effort, for tools that take one unasked
+    default_effort: Option<ReasoningEffort>,
+}
+
+impl LlmToolChain {
+    #[allow(dead_code)]
+    pub fn new(tool_names: &str) -> Result<Self> {
+        Self::with_models(tool_names, &ModelConfig::default())
+    }
+
+    pub fn with_models(tool_names: &str, model_config: &ModelConfig) -> Result<Self> {
+        let names: Vec<&str> = tool_names
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let tools = names
+            .iter()
+            .map(|name| LlmTool::resolve(name, model_config))
+            .collect::<Result<Vec<_>>>()?;
+
+        if tools.is_empty() {
+            anyhow::bail!("No valid LLM tools specified");
+        }
+        let weights = names
+            .iter()
+            .map(|name| {
+                model_config
+                    .tool_settings
+                    .get(*name)
+                    .and_then(|settings| settings.weight)
+                    .unwrap_or(1)
+            })
+            .collect();
+
+        let mut roles = HashMap::new();
+        for &role in model_config.roles.keys() {
+            if let Some(role_config) = model_config.for_role(role)? {
+                let role_tools = model_config.get_tools_for_role(role, tool_names);
+                let chain = Self::with_models(role_tools, &role_config)
+                    .with_context(|| format!("Invalid tools for role {}", role))?;
+                roles.insert(role, chain);
+            }
+        }
+
+        let role_efforts = Role::ALL
+            .into_iter()
+            .filter_map(|role| Some((role, model_config.get_reasoning_effort_for_role(role)?)))
+            .collect();
+
+        let squelches = SquelchRegistry::load(model_config.rate_limit_state.clone());
+        Ok(Self {
+            tools,
+            weights,
+            strategy: model_config.tool_strategy,
+            current_index: 0,
+            turn_start: 0,
+            rotation: 0,
+            squelches: squelches.clone(),
+            rate_limit_timeout: Duration::from_secs(DEFAULT_RATE_LIMIT_SECONDS),
+            output_prefix: None,
+            instance_id: None,
+            usage: UsageLedger::default(),
+            turn_usage: None,
+            resume: None,
+            turn_session: None,
+            recorder: model_config.recorder.clone(),
+            roles,
+            role_efforts,
+            effort: None,
+            default_effort: None,
+        }
+        .with_squelches(squelches))
+    }
+
+    /// The chain prompts of `role` go through: its own if the role is routed
+    /// with `[roles.<role>]`, otherwise this one. Its invocations then use
+    /// the role's reasoning effort.
+    pub fn for_role(&mut self, role: Role) -> &mut LlmToolChain {
+        let effort = self.role_efforts.get(&role).copied();
+        let chain = if self.roles.contains_key(&role) {
+            self.roles.get_mut(&role).expect("role chain")
+        } else {
+            self
+        };
+        chain.effort = effort;
+        chain.default_effort = role.default_reasoning_effort();
+        chain
+    }
+
+    /// Prefix live output lines with the given parallel instance id, and run
+    /// the tools as that instance
+    pub fn with_instance_id(mut self, id: usize) -> Self {
+        self.output_prefix = Some(format!("[{}]", id));
+        self.instance_id = Some(id);
+        self.tools = self
+            .tools
+            .into_iter()
+            .map(|tool| tool.with_instance_id(id))
+            .collect();
+        self.roles = map_roles(self.roles, |chain| chain.with_instance_id(id));
+        self
+    }
+
+    /// Make the tool `offset` places down the list the primary one, keeping
+    /// the rest in order after it (e.g. one primary tool per parallel instance)
+    pub fn with_rotated_tools(mut self, offset: usize) -> Self {
+        self.roles = map_roles(self.roles, |chain| chain.with_rotated_tools(offset));
+        let offset = offset % self.tools.len();
+        self.tools.rotate_left(offset);
+        self.weights.rotate_left(offset);
+        self
+    }
+
+    /// Make the next tool in the list the primary one, e.g. to hand a
+    /// stalled worker to a different agent. `None` with a single tool.
+    pub fn rotate_primary_tool(&mut self) -> Option<&str> {
+        if self.tools.len() < 2 {
+            return None;
+        }
+        self.tools.rotate_left(1);
+        self.weights.rotate_left(1);
+        self.current_index = 0;
+        self.rotation = 0;
+        Some(self.current_tool().name())
+    }
+
+    /// Count usage against a run-wide ledger (and its budget)
+    pub fn with_usage_ledger(mut self, usage: UsageLedger) -> Self {
+        self.roles = map_roles(self.roles, |chain| chain.with_usage_ledger(usage.clone()));
+        self.usage = usage;
+        self
+    }
+
+    /// Usage of the invocations since the previous call, e.g. one iteration
+    pub fn take_turn_usage(&mut self) -> Option<Usage> {
+        self.turn_usage.take()
+    }
+
+    /// Continue `session` on the next invocation if its tool is the one
+    /// chosen. Applies to that invocation only.
+    pub fn resume_session(&mut self, session: Option<AgentSession>) {
+        self.resume = session;
+    }
+
+    /// The resumable session the last invocation ran in, if the tool
+    /// reported one
+    pub fn take_turn_session(&mut self) -> Option<AgentSession> {
+        self.turn_session.take()
+    }
+
+    /// Describe the exhausted budget, if the run has used one up
+    pub fn budget_exceeded(&self) -> Option<String> {
+        self.usage.budget_exceeded()
+    }
+
+    /// Share rate-limit squelches with other chains (e.g. parallel instances)
+    pub fn with_squelches(mut self, squelches: SquelchRegistry) -> Self {
+        self.roles = map_roles(self.roles, |chain| chain.with_squelches(squelches.clone()));
+        self.squelches = squelches;
+        self
+    }
+
+    fn current_tool(&self) -> &LlmTool {
+        &self.tools[self.current_index]
+    }
+
+    /// Move to the next fallback that isn't squelched. Strategies other than
+    /// priority wrap around, stopping at the tool this invocation started with.
+    fn switch_to_next(&mut self) -> Option<&LlmTool> {
+        let len = self.tools.len();
+        let next = if self.strategy.wraps() {
+            (1..len)
+                .map(|offset| (self.current_index + offset) % len)
+                .take_while(|&index| index != self.turn_start)
+                .find(|&index| self.is_rate_limit_expired(&self.tools[index]))?
+        } else {
+            (self.current_index + 1..len)
+                .find(|&index| self.is_rate_limit_expired(&self.tools[index]))?
+        };
+        self.current_index = next;
+        Some(self.current_tool())
+    }
+
+    /// Squelch a tool until its limit resets, falling back to the default
+    /// timeout when the tool didn't say. Returns the squelch length.
+    fn mark_rate_limited(&mut self, tool: &LlmTool, limit: RateLimit) -> Duration {
+        let squelch = limit.retry_after.unwrap_or(self.rate_limit_timeout);
+        self.squelches.squelch(tool.name(), SystemTime::now() + squelch);
+        squelch
+    }
+
+    /// Check if a tool's rate limit has expired
+    fn is_rate_limit_expired(&self, tool: &LlmTool) -> bool {
+        !self.squelches.is_squelched(tool.name())
+    }
+
+    /// Pick the tool to start an invocation with, after picking up
+    /// squelches recorded by other afkcode processes
+    fn select_starting_tool(&mut self, logger: &mut Option<Logger>) {
+        self.squelches.refresh();
+        let available: Vec<usize> = (0..self.tools.len())
+            .filter(|&index| self.is_rate_limit_expired(&self.tools[index]))
+            .collect();
+
+        let index = match self.strategy {
+            ToolStrategy::Priority => {
+                self.select_preferred_tool(available.first().copied(), logger);
+                self.turn_start = self.current_index;
+                return;
+            }
+            ToolStrategy::RoundRobin => {
+                let start = self.rotation;
+                self.rotation = (self.rotation + 1) % self.tools.len();
+                balance::next_in_rotation(&available, self.tools.len(), start)
+            }
+            ToolStrategy::Weighted => {
+                balance::weighted_pick(&available, &self.weights, rand::random())
+                    .or(available.first().copied())
+            }
+            ToolStrategy::LeastRecentlyRateLimited => {
+                balance::least_recently_limited(&available, |index| {
+                    self.squelches.last_rate_limited(self.tools[index].name())
+                })
+            }
+        };
+        // Everything is squelched; keep trying the current tool
+        if let Some(index) = index {
+            self.current_index = index;
+        }
+        self.turn_start = self.current_index;
+    }
+
+    /// Switch to the most preferred tool that isn't squelched
+    fn select_preferred_tool(&mut self, preferred: Option<usize>, logger: &mut Option<Logger>) {
+        let Some(index) = preferred else {
+            // Everything is squelched; keep trying the current tool
+            return;
+        };
+
+        let msg = if index < self.current_index {
+            format!(
+                "Rate limit timeout expired for {}. Resetting to preferred tool.",
+                self.tools[index].name()
+            )
+        } else if index > self.current_index {
+            let current = self.current_tool().name();
+            let until = self
+                .squelches
+                .squelched_until(current)
+                .map(chrono::DateTime::<chrono::Local>::from)
+                .map(|until| until.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            format!(
+                "{} is rate limited until {}. Using {}.",
+                current,
+                until,
+                self.tools[index].name()
+            )
+        } else {
+            return;
+        };
+        println!("{}", msg);
+        if let Some(log) = logger.as_mut() {
+            let _ = log.logln(&msg);
+        }
+        self.current_index = index;
+    }
+
+    pub fn invoke_with_fallback(
+        &mut self,
+        prompt: &str,
+        logger: &mut Option<Logger>,
+    ) -> Result<(String, String)> {
+        self.run_with_fallback(prompt, logger, false)
+    }
+
+    /// Invoke with fallback, teeing the tool's output to the console and log
+    /// line by line as it is produced
+    pub fn stream_with_fallback(
+        &mut self,
+        prompt: &str,
+        logger: &mut Option<Logger>,
+    ) -> Result<(String, String)> {
+        self.run_with_fallback(prompt, logger, true)
+    }
+
+    fn run_with_fallback(
+        &mut self,
+        prompt: &str,
+        logger: &mut Option<Logger>,
+        live: bool,
+    ) -> Result<(String, String)> {
+        // Try to reset to a more preferred tool if rate limit has expired
+        self.select_starting_tool(logger);
+        let mut resume = self.resume.take();
+        self.turn_session = None;
+
+        // Retries of the current tool after transient failures
+        let mut attempt = 0;
+
+        loop {
+            let session_id = resume
+                .as_ref()
+                .filter(|session| session.tool == self.current_tool().name())
+                .map(|session| session.id.clone());
+            let mut tool = self.current_tool().clone().with_session(session_id.clone());
+            if self.effort.is_some() {
+                tool = tool.with_reasoning_effort(self.effort);
+            } else {
+                tool = tool.with_default_reasoning_effort(self.default_effort);
+            }
+            if let Some(id) = &session_id {
+                let resume_msg = format!("Resuming {} session {}", tool.name(), id);
+                println!("{}", resume_msg);
+                if let Some(log) = logger.as_mut() {
+                    let _ = log.logln(&resume_msg);
+                }
+            }
+            let tool_msg = match tool.effort {
+                Some(effort) => {
+                    format!("Using LLM tool: {} (reasoning effort: {})", tool.name(), effort)
+                }
+                None => format!("Using LLM tool: {}", tool.name()),
+            };
+            println!("{}", tool_msg);
+            if let Some(log) = logger.as_mut() {
+                let _ = log.logln(&tool_msg);
+            }
+
+            let started = Instant::now();
+            let result = if live {
+                self.invoke_live(&tool, prompt, logger)
+            } else {
+                tool.invoke(prompt)
+            };
+            let exchange = Exchange {
+                tool: &tool,
+                prompt,
+                duration: started.elapsed(),
+            };
+            let rate_limit = result.as_ref().ok().and_then(|output| tool.rate_limit(output));
+            let classification = Classification::of(result.as_ref(), rate_limit.is_some());
+            self.record_exchange(exchange, result.as_ref(), classification);
+
+            // A failed turn's output, kept in case no tool does better
+            let (error, error_msg, failed_output) = match result {
+                Ok(output) => {
+                    self.record_usage(&tool, &output, logger);
+
+                    if let Some(limit) = rate_limit {
+                        let squelch = self.mark_rate_limited(&tool, limit);
+                        let until = chrono::Local::now()
+                            + chrono::Duration::from_std(squelch).unwrap_or_default();
+
+                        let rate_limit_msg = format!(
+                            "Rate limit detected for {}. Squelching until {} ({}s{}).",
+                            tool.name(),
+                            until.format("%Y-%m-%d %H:%M:%S"),
+                            squelch.as_secs(),
+                            if limit.retry_after.is_some() { ", from reset hint" } else { "" }
+                        );
+                        println!("{}", rate_limit_msg);
+                        if let Some(log) = logger.as_mut() {
+                            let _ = log.logln(&rate_limit_msg);
+                        }
+
+                        if let Some(next_tool) = self.switch_to_next() {
+                            let switch_msg =
+                                format!("Switching to fallback tool: {}", next_tool.name());
+                            println!("{}", switch_msg);
+                            if let Some(log) = logger.as_mut() {
+                                let _ = log.logln(&switch_msg);
+                            }
+                            attempt = 0;
+                            continue;
+                        } else {
+                            anyhow::bail!("All LLM tools exhausted due to rate limits");
+                        }
+                    }
+
+                    // A turn cut short by shutdown is returned as is, not retried
+                    if classification == Classification::Ok || tool.shutdown_requested() {
+                        return Ok(self.finish_turn(&tool, output, logger));
+                    }
+
+                    let msg = format!("{} {}", tool.name(), classification.describe(&output));
+                    (anyhow::anyhow!("{}", msg), msg, Some(output))
+                }
+                Err(e) => {
+                    let msg = format!("Error invoking {}: {}", tool.name(), e);
+                    (e, msg, None)
+                }
+            };
+            println!("{}", error_msg);
+            if let Some(log) = logger.as_mut() {
+                let _ = log.logln(&error_msg);
+            }
+            if tool.shutdown_requested() {
+                return Err(error);
+            }
+
+            // The session may be gone; retry the same tool from scratch
+            if session_id.is_some() {
+                resume = None;
+                let fresh_msg = format!("Starting a fresh {} session instead.", tool.name());
+                println!("{}", fresh_msg);
+                if let Some(log) = logger.as_mut() {
+                    let _ = log.logln(&fresh_msg);
+                }
+                continue;
+            }
+
+            let retry = tool.retry_policy();
+            if classification.is_transient() && attempt < retry.max_retries {
+                attempt += 1;
+                let delay = retry.delay(attempt, rand::random());
+                let retry_msg = format!(
+                    "Retrying {} in {:.1}s (retry {}/{}).",
+                    tool.name(),
+                    delay.as_secs_f64(),
+                    attempt,
+                    retry.max_retries
+                );
+                println!("{}", retry_msg);
+                if let Some(log) = logger.as_mut() {
+                    let _ = log.logln(&retry_msg);
+                }
+                if tool.sleep_unless_shutdown(delay) {
+                    return Err(error);
+                }
+                continue;
+            }
+
+            if let Some(next_tool) = self.switch_to_next() {
+                let switch_msg = format!("Switching to fallback tool: {}", next_tool.name());
+                println!("{}", switch_msg);
+                if let Some(log) = logger.as_mut() {
+                    let _ = log.logln(&switch_msg);
+                }
+                attempt = 0;
+                continue;
+            }
+
+            // Out of retries and tools. A turn that ran to the end, or was
+            // killed for running too long, is over and the loop carries on;
+            // one that couldn't run at all ends it.
+            if failed_output.is_none() && classification != Classification::Timeout {
+                return Err(error);
+            }
+            let give_up_msg = format!(
+                "No tool left to try; continuing after {}'s failed turn.",
+                tool.name()
+            );
+            println!("{}", give_up_msg);
+            if let Some(log) = logger.as_mut() {
+                let _ = log.logln(&give_up_msg);
+            }
+            return Ok(match failed_output {
+                Some(output) => self.finish_turn(&tool, output, logger),
+                None => (String::new(), error_msg),
+            });
+        }
+    }
+
+    /// Note the session and summary of an invocation whose output is used
+    /// and return the agent's reply with stderr
+    fn finish_turn(
+        &mut self,
+        tool: &LlmTool,
+        output: ToolOutput,
+        logger: &mut Option<Logger>,
+    ) -> (String, String) {
+        if let Some(result) = &output.structured {
+            if tool.supports_resume()
+                && let Some(id) = &result.session_id
+            {
+                self.turn_session = Some(AgentSession {
+                    tool: tool.name().to_string(),
+                    id: id.clone(),
+                    context_tokens: result
+                        .context_tokens
+                        .or(output.usage.map(|usage| usage.input_tokens)),
+                });
+            }
+            let summary = format!(
+                "{} finished: {} tool call(s), {} error(s){}",
+                tool.name(),
+                result.tool_calls.len(),
+                result.errors.len(),
+                result
+                    .session_id
+                    .as_ref()
+                    .map(|id| format!(", session {}", id))
+                    .unwrap_or_default()
+            );
+            println!("{}", summary);
+            if let Some(log) = logger.as_mut() {
+                let _ = log.logln(&summary);
+            }
+        }
+
+        // Callers look for stop tokens in the agent's reply, not tool noise
+        let message = output.message().to_string();
+        (message, output.stderr)
+    }
+
+    /// Append an invocation to the cassette, if one is being recorded
+    fn record_exchange(
+        &self,
+        exchange: Exchange<'_>,
+        result: Result<&ToolOutput, &anyhow::Error>,
+        classification: Classification,
+    ) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let (stdout, stderr, exit_code, error) = match result {
+            Ok(output) => (output.stdout.clone(), output.stderr.clone(), output.exit_code, None),
+            Err(e) => (String::new(), String::new(), None, Some(format!("{:#}", e))),
+        };
+        recorder.record(CassetteEntry {
+            seq: 0,
+            timestamp: chrono::Local::now().to_rfc3339(),
+            instance: self.instance_id,
+            tool: exchange.tool.name().to_string(),
+            model: exchange.tool.model.clone(),
+            reasoning_effort: exchange.tool.effort,
+            prompt: exchange.prompt.to_string(),
+            stdout,
+            stderr,
+            exit_code,
+            duration_ms: exchange.duration.as_millis() as u64,
+            classification,
+            error,
+            event_format: exchange.tool.event_format(),
+        });
+    }
+
+    fn record_usage(&mut self, tool: &LlmTool, output: &ToolOutput, logger: &mut Option<Logger>) {
+        let Some(usage) = output.usage else {
+            return;
+        };
+        self.usage.record(self.instance_id, tool.name(), usage);
+        *self.turn_usage.get_or_insert_with(Usage::default) += usage;
+
+        let usage_msg = format!("Usage ({}): {}", tool.name(), usage);
+        println!("{}", usage_msg);
+        if let Some(log) = logger.as_mut() {
+            let _ = log.logln(&usage_msg);
+        }
+    }
+
+    /// Invoke `tool`, echoing each output line (with the instance prefix, if
+    /// any) to the console and log as it arrives
+    fn invoke_live(
+        &self,
+        tool: &LlmTool,
+        prompt: &str,
+        logger: &mut Option<Logger>,
+    ) -> Result<ToolOutput> {
+        let prefix = self.output_prefix.as_deref();
+        let mut sink = |stream: OutputStream, line: &str| {
+            let line = match prefix {
+                Some(prefix) => format!("{} {}", prefix, line),
+                None => line.to_string(),
+            };
+            match stream {
+                OutputStream::Stdout => println!("{}", line),
+                OutputStream::Stderr => eprintln!("{}", line),
+            }
+            if let Some(log) = logger.as_mut() {
+                let _ = log.logln(&line);
+            }
+        };
+        tool.invoke_streaming(prompt, &mut sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_config(args: &[&str]) -> ToolConfig {
+        ToolConfig {
+            command: Some("my-agent".to_string()),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_insert_placeholder() {
+        let mut args = vec!["--message={prompt}".to_string(), "-y".to_string()];
+        insert_placeholder(&mut args, "{prompt}", "hello");
+        assert_eq!(args, vec!["--message=hello", "-y"]);
+
+        let mut args = vec!["run".to_string()];
+        insert_placeholder(&mut args, "{prompt}", "hello");
+        assert_eq!(args, vec!["run", "hello"]);
+    }
+
+    #[test]
+    fn test_custom_tool_args_and_model_flag() {
+        let mut config = custom_config(&["--headless", "--profile={model}"]);
+        config.model = Some("big".to_string());
+        config.model_flag = Some("--model".to_string());
+
+        let tool = LlmTool::custom("mytool", &config).unwrap();
+        assert_eq!(tool.name(), "mytool");
+        assert_eq!(tool.command(), "my-agent");
+        assert_eq!(
+            tool.args(),
+            vec!["--headless", "--profile=big", "--model", "big"]
+        );
+        assert_eq!(tool.prompt_delivery(), PromptDelivery::Stdin);
+    }
+
+    #[test]
+    fn test_headless_agent_adapters() {
+        let aider = LlmTool::from_name("aider")
+            .unwrap()
+            .with_model(Some("sonnet".to_string()));
+        let mut args = aider.args();
+        insert_placeholder(&mut args, "{prompt}", "do it");
+        assert_eq!(aider.command(), "aider");
+        assert_eq!(
+            args,
+            vec![
+                "--yes-always",
+                "--no-pretty",
+                "--no-stream",
+                "--no-check-update",
+                "--message",
+                "do it",
+                "--model",
+                "sonnet"
+            ]
+        );
+
+        let opencode = LlmTool::from_name("opencode").unwrap();
+        assert_eq!(opencode.args(), vec!["run"]);
+        assert_eq!(opencode.prompt_delivery(), PromptDelivery::Argv);
+
+        let cursor = LlmTool::from_name("cursor-agent").unwrap();
+        assert_eq!(cursor.name(), "cursor");
+        assert_eq!(cursor.command(), "cursor-agent");
+        assert!(cursor.args().contains(&"--force".to_string()));
+        let limited = ToolOutput {
+            stderr: "Error: usage limit exceeded".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        assert!(cursor.rate_limit(&limited).is_some());
+    }
+
+    #[test]
+    fn test_structured_output_mode() {
+        let claude = LlmTool::from_name("claude").unwrap().with_structured_output(true);
+        assert_eq!(claude.event_format(), Some(EventFormat::Claude));
+        let args = claude.args();
+        assert!(args.contains(&"stream-json".to_string()));
+        assert!(args.contains(&"--verbose".to_string()));
+        assert!(LlmTool::from_name("codex").unwrap().with_structured_output(true).args().contains(&"--json".to_string()));
+        assert_eq!(LlmTool::from_name("aider").unwrap().with_structured_output(true).event_format(), None);
+        assert_eq!(LlmTool::from_name("claude").unwrap().event_format(), None);
+
+        // A failed turn whose tool results mention a limit, but whose reply doesn't
+        let output = ToolOutput {
+            stdout: "{\"type\":\"user\",\"message\":{\"content\":[{\"type\":\"tool_result\",\"content\":\"rate limit reached\"}]}}".to_string(),
+            exit_code: Some(1),
+            structured: Some(StructuredResult {
+                final_message: "The test suite failed.".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(output.message(), "The test suite failed.");
+        assert!(claude.rate_limit(&output).is_none());
+
+        let limited = ToolOutput {
+            exit_code: Some(1),
+            structured: Some(StructuredResult {
+                errors: vec!["Claude AI usage limit reached|1750000000".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(claude.rate_limit(&limited).is_some());
+    }
+
+    #[test]
+    fn test_resume_args() {
+        let claude = LlmTool::from_name("claude").unwrap();
+        assert!(claude.supports_resume());
+        assert_eq!(claude.resume_args("abc"), ["--resume", "abc"]);
+        assert_eq!(
+            LlmTool::from_name("codex").unwrap().resume_args("t-1"),
+            ["resume", "t-1"]
+        );
+        assert!(!LlmTool::from_name("aider").unwrap().supports_resume());
+
+        let mut config = custom_config(&[]);
+        assert!(!LlmTool::custom("mytool", &config).unwrap().supports_resume());
+        config.resume_args = vec!["--session={session_id}".to_string()];
+        let tool = LlmTool::custom("mytool", &config).unwrap();
+        assert!(tool.supports_resume());
+        assert_eq!(tool.resume_args("s9"), ["--session=s9"]);
+
+        // Resuming forces structured mode only on tools that can resume
+        let model_config = ModelConfig {
+            resume_sessions: true,
+            ..Default::default()
+        };
+        assert!(LlmTool::resolve("claude", &model_config).unwrap().structured);
+        assert!(!LlmTool::resolve("aider", &model_config).unwrap().structured);
+        assert!(!LlmTool::resolve("claude", &ModelConfig::default()).unwrap().structured);
+    }
+
+    #[test]
+    fn test_retry_delay_ends_on_shutdown() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let tool = LlmTool::from_name("codex")
+            .unwrap()
+            .with_shutdown_flag(Some(shutdown.clone()));
+        assert!(!tool.sleep_unless_shutdown(Duration::from_millis(10)));
+
+        let started = Instant::now();
+        let flag = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::SeqCst);
+        });
+        assert!(tool.sleep_unless_shutdown(Duration::from_secs(60)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_custom_tool_requires_command() {
+        let config = ToolConfig::default();
+        assert!(LlmTool::custom("broken", &config).is_err());
+    }
+
+    #[test]
+    fn test_resolve_prefers_custom_definition() {
+        let mut model_config = ModelConfig::default();
+        model_config
+            .tool_settings
+            .insert("claude".to_string(), custom_config(&[]));
+
+        let tool = LlmTool::resolve("claude", &model_config).unwrap();
+        assert_eq!(tool.kind, LlmToolKind::Custom);
+        assert_eq!(tool.command(), "my-agent");
+
+        let tool = LlmTool::resolve("codex", &model_config).unwrap();
+        assert_eq!(tool.kind, LlmToolKind::Codex);
+        assert!(LlmTool::resolve("unknown", &model_config).is_err());
+    }
+
+    #[test]
+    fn test_custom_rate_limit_patterns() {
+        let mut config = custom_config(&[]);
+        config.rate_limit_patterns = vec!["Quota Exhausted".to_string()];
+        let tool = LlmTool::custom("mytool", &config).unwrap();
+
+        let limited = ToolOutput {
+            stderr: "error: quota exhausted for today".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        assert!(tool.rate_limit(&limited).is_some());
+        let fine = ToolOutput {
+            stdout: "all good".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        assert!(tool.rate_limit(&fine).is_none());
+    }
+
+    #[test]
+    fn test_chain_skips_tools_squelched_by_earlier_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ratelimits.json");
+        SquelchRegistry::load(Some(path.clone()))
+            .squelch("gemini", SystemTime::now() + Duration::from_secs(600));
+
+        let model_config = ModelConfig {
+            rate_limit_state: Some(path),
+            ..Default::default()
+        };
+        let mut chain = LlmToolChain::with_models("gemini,codex", &model_config).unwrap();
+        chain.select_starting_tool(&mut None);
+        assert_eq!(chain.current_tool().name(), "codex");
+    }
+
+    #[test]
+    fn test_chains_sharing_squelches_switch_together() {
+        let squelches = SquelchRegistry::load(None);
+        let mut first = LlmToolChain::new("gemini,codex")
+            .unwrap()
+            .with_squelches(squelches.clone());
+        let mut second = LlmToolChain::new("gemini,codex")
+            .unwrap()
+            .with_squelches(squelches.clone());
+
+        let gemini = first.current_tool().clone();
+        let limit = RateLimit {
+            retry_after: Some(Duration::from_secs(600)),
+        };
+        first.mark_rate_limited(&gemini, limit);
+        second.select_starting_tool(&mut None);
+        assert_eq!(second.current_tool().name(), "codex");
+
+        // Expiry brings both back to the preferred tool
+        squelches.squelch("gemini", SystemTime::now());
+        first.select_starting_tool(&mut None);
+        second.select_starting_tool(&mut None);
+        assert_eq!(first.current_tool().name(), "gemini");
+        assert_eq!(second.current_tool().name(), "gemini");
+    }
+
+    #[test]
+    fn test_round_robin_rotates_and_wraps_fallback() {
+        let model_config = ModelConfig {
+            tool_strategy: ToolStrategy::RoundRobin,
+            ..Default::default()
+        };
+        let mut chain = LlmToolChain::with_models("gemini,codex,claude", &model_config).unwrap();
+        let mut starts = Vec::new();
+        for _ in 0..4 {
+            chain.select_starting_tool(&mut None);
+            starts.push(chain.current_tool().name().to_string());
+        }
+        assert_eq!(starts, ["gemini", "codex", "claude", "gemini"]);
+
+        // Starting at codex, fallback wraps to gemini but never revisits codex
+        chain.select_starting_tool(&mut None);
+        assert_eq!(chain.current_tool().name(), "codex");
+        let fallbacks: Vec<String> = std::iter::from_fn(|| {
+            chain.switch_to_next().map(|tool| tool.name().to_string())
+        })
+        .collect();
+        assert_eq!(fallbacks, ["claude", "gemini"]);
+    }
+
+    #[test]
+    fn test_least_recently_rate_limited_and_rotation() {
+        let model_config = ModelConfig {
+            tool_strategy: ToolStrategy::LeastRecentlyRateLimited,
+            ..Default::default()
+        };
+        let squelches = SquelchRegistry::load(None);
+        let mut chain = LlmToolChain::with_models("gemini,codex", &model_config)
+            .unwrap()
+            .with_squelches(squelches.clone());
+        // gemini was limited, but its squelch is already over
+        squelches.squelch("gemini", SystemTime::now());
+        chain.select_starting_tool(&mut None);
+        assert_eq!(chain.current_tool().name(), "codex");
+
+        let rotated = LlmToolChain::new("gemini,codex,claude")
+            .unwrap()
+            .with_rotated_tools(4);
+        let names: Vec<_> = rotated.tools.iter().map(|tool| tool.name()).collect();
+        assert_eq!(names, ["codex", "claude", "gemini"]);
+
+        let mut chain = rotated;
+        assert_eq!(chain.rotate_primary_tool(), Some("claude"));
+        assert_eq!(LlmToolChain::new("codex").unwrap().rotate_primary_tool(), None);
+    }
+
+    #[test]
+    fn test_chain_retries_transient_failures_before_falling_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("flaky.toml");
+        std::fs::write(
+            &script,
+            "[[step]]\nstdout = \"crashed\"\nexit_code = 1\n\n[[step]]\n\n[[step]]\nstdout = \"done\"\n",
+        )
+        .unwrap();
+        let fallback = dir.path().join("fallback.toml");
+        std::fs::write(&fallback, "when_exhausted = \"repeat_last\"\n\n[[step]]\nstdout = \"fallback\"\n").unwrap();
+
+        let mut model_config = ModelConfig::default();
+        for (name, path) in [("flaky", &script), ("steady", &fallback)] {
+            let settings = ToolConfig {
+                replay_script: Some(path.clone()),
+                ..Default::default()
+            };
+            model_config.tool_settings.insert(name.to_string(), settings);
+        }
+        let mut chain = LlmToolChain::with_models("flaky,steady", &model_config).unwrap();
+        let (reply, _) = chain.invoke_with_fallback("go", &mut None).unwrap();
+        assert_eq!(reply, "done");
+
+        // With retries disabled the first failure falls back
+        model_config.tool_settings.get_mut("flaky").unwrap().max_retries = Some(0);
+        let mut chain = LlmToolChain::with_models("flaky,steady", &model_config).unwrap();
+        let (reply, _) = chain.invoke_with_fallback("go", &mut None).unwrap();
+        assert_eq!(reply, "fallback");
+
+        // With nothing to fall back to, the failed turn's output is used
+        let mut chain = LlmToolChain::with_models("flaky", &model_config).unwrap();
+        let (reply, _) = chain.invoke_with_fallback("go", &mut None).unwrap();
+        assert_eq!(reply, "crashed");
+    }
+
+    #[test]
+    fn test_roles_get_their_own_tools_and_models() {
+        let mut model_config = ModelConfig {
+            claude_model: Some("sonnet".to_string()),
+            ..Default::default()
+        };
+        let role = |tools: Option<&str>, model: &str| RoleConfig {
+            tools: tools.map(str::to_string),
+            models: HashMap::from([("claude".to_string(), model.to_string())]),
+            ..Default::default()
+        };
+        model_config.roles.insert(Role::Confirmation, role(Some("claude"), "haiku"));
+        model_config.roles.insert(Role::Verifier, role(None, "opus"));
+
+        let mut chain = LlmToolChain::with_models("codex,claude", &model_config).unwrap();
+        assert_eq!(chain.tools[1].model(), Some("sonnet"));
+        assert_eq!(chain.for_role(Role::Worker).tools.len(), 2);
+        let confirmation = chain.for_role(Role::Confirmation);
+        assert_eq!(confirmation.tools.len(), 1);
+        assert_eq!(confirmation.current_tool().model(), Some("haiku"));
+        let verifier = chain.for_role(Role::Verifier);
+        assert_eq!(verifier.current_tool().name(), "codex");
+        assert_eq!(verifier.tools[1].model(), Some("opus"));
+
+        model_config.roles.insert(
+            Role::Audit,
+            RoleConfig {
+                models: HashMap::from([("nonesuch".to_string(), "x".to_string())]),
+                ..Default::default()
+            },
+        );
+        assert!(LlmToolChain::with_models("codex,claude", &model_config).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_custom_tool_prompt_delivery_modes() {
+        let mut config = ToolConfig {
+            command: Some("sh".to_string()),
+            args: vec!["-c".to_string(), "cat".to_string()],
+            ..Default::default()
+        };
+        let tool = LlmTool::custom("stdin-tool", &config).unwrap();
+        let output = tool.invoke("via stdin").unwrap();
+        assert_eq!(output.stdout, "via stdin");
+
+        config.args = vec!["-c".to_string(), "printf '%s' \"$0\"".to_string()];
+        config.prompt_mode = Some(PromptDelivery::Argv);
+        let tool = LlmTool::custom("argv-tool", &config).unwrap();
+        let output = tool.invoke("via argv").unwrap();
+        assert_eq!(output.stdout, "via argv");
+
+        config.args = vec!["-c".to_string(), "cat \"$0\"".to_string()];
+        config.prompt_mode = Some(PromptDelivery::File);
+        let tool = LlmTool::custom("file-tool", &config).unwrap();
+        let output = tool.invoke("via file").unwrap();
+        assert_eq!(output.stdout, "via file");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tool_process_settings_per_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let work = dir.path().join("work-1");
+        std::fs::create_dir(&work).unwrap();
+        let config = ToolConfig {
+            command: Some("sh".to_string()),
+            args: vec![
+                "-c".to_string(),
+                "printf '%s %s %s' \"$ACCOUNT\" \"$0\" \"$(basename \"$PWD\")\"".to_string(),
+            ],
+            env: HashMap::from([("ACCOUNT".to_string(), "acct-{instance_id}".to_string())]),
+            cwd: Some(dir.path().join("work-{instance_id}")),
+            extra_args: vec!["--profile={instance_id}".to_string()],
+            ..Default::default()
+        };
+        let mut model_config = ModelConfig::default();
+        model_config
+            .tool_settings
+            .insert("agent".to_string(), config);
+
+        let chain = LlmToolChain::with_models("agent", &model_config)
+            .unwrap()
+            .with_instance_id(1);
+        let output = chain.tools[0].invoke("prompt").unwrap();
+        assert_eq!(output.stdout, "acct-1 --profile=1 work-1");
+
+        // Instance 0 has no working directory of its own
+        let tool = LlmTool::resolve("agent", &model_config).unwrap();
+        let err = tool.invoke("prompt").unwrap_err();
+        assert_eq!(
+            Classification::of(Err(&err), false),
+            Classification::SpawnFailure
+        );
+        assert!(format!("{:#}", err).contains("work-0 does not exist"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reasoning_effort_per_tool_and_role() {
+        let config = ToolConfig {
+            command: Some("sh".to_string()),
+            args: vec!["-c".to_string(), "printf '%s' \"$*\"".to_string(), "sh".to_string()],
+            reasoning_effort: Some(ReasoningEffort::Low),
+            reasoning_args: vec!["--effort".to_string(), "{effort}".to_string()],
+            ..Default::default()
+        };
+        let mut model_config = ModelConfig::default();
+        model_config.tool_settings.insert("agent".to_string(), config);
+        model_config.roles.insert(
+            Role::Worker,
+            RoleConfig {
+                reasoning_effort: Some(ReasoningEffort::High),
+                ..Default::default()
+            },
+        );
+
+        let mut chain = LlmToolChain::with_models("agent", &model_config).unwrap();
+        // A role that only sets an effort keeps the run's chain
+        assert!(chain.roles.is_empty());
+        let mut reply = |role| chain.for_role(role).invoke_with_fallback("go", &mut None).unwrap().0;
+        assert_eq!(reply(Role::Worker), "--effort high");
+        assert_eq!(reply(Role::Controller), "--effort low");
+        // The intent check's default minimal is only for Claude and Codex
+        assert_eq!(reply(Role::IntentCheck), "--effort low");
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenAI-compatible chat-completions backend.
+//!
+//! Talks to any server exposing `/v1/chat/completions` (OpenAI, llama.cpp,
+//! vLLM, Ollama, ...). The reply is mapped onto the same `(stdout, stderr)`
+//! contract as the CLI tools so rate-limit detection and fallback still apply.
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::{usage, LlmTool, ReasoningEffort, ToolOutput};
+use crate::constants::OPENAI_API_BASE;
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
+}
+
+impl<'a> ChatCompletionRequest<'a> {
+    /// A single-message request. Servers take `low`, `medium` and `high`;
+    /// only some OpenAI models know `minimal`, so it is sent as `low`.
+    fn new(model: &'a str, prompt: &'a str, effort: Option<ReasoningEffort>) -> Self {
+        Self {
+            model,
+            messages: vec![ChatMessage {
+                role: "user",
+                content: prompt,
+            }],
+            reasoning_effort: effort.map(|effort| effort.max(ReasoningEffort::Low)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    #[serde(default)]
+    choices: Vec<ChatChoice>,
+    /// Token counts; the raw value is read by the shared usage parser
+    #[serde(default)]
+    usage: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatChoiceMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// Build the chat-completions URL from a base such as `http://localhost:8080/v1`.
+fn completions_url(base: &str) -> String {
+    format!("{}/chat/completions", base.trim_end_matches('/'))
+}
+
+impl LlmTool {
+    /// Invoke an OpenAI-compatible `/chat/completions` endpoint
+    pub(super) fn invoke_openai(&self, prompt: &str) -> Result<ToolOutput> {
+        let model = self.model.as_deref().ok_or_else(|| {
+            anyhow!("OpenAI-compatible backend needs a model. Set openai_model in config")
+        })?;
+        let base = self.api_base.as_deref().unwrap_or(OPENAI_API_BASE);
+
+        // None disables reqwest's 30s default for long generations
+        let client = Client::builder().timeout(self.timeouts.wall_clock).build()?;
+
+        let request = ChatCompletionRequest::new(model, prompt, self.effort);
+
+        let mut builder = client.post(completions_url(base)).json(&request);
+        // Local servers usually run without authentication
+        if let Some(ref api_key) = self.api_key {
+            builder = builder.header("Authorization", format!("Bearer {}", api_key));
+        }
+
+        let response = builder
+            .send()
+            .with_context(|| format!("Failed to reach OpenAI-compatible endpoint at {}", base))?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            // Surface on stderr so the regular rate-limit detection squelches
+            // this tool, passing the server's Retry-After along as a reset hint
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(|secs| format!(" (retry after {}s)", secs))
+                .unwrap_or_default();
+            let body = response.text().unwrap_or_default();
+            return Ok(ToolOutput::http(
+                String::new(),
+                format!("HTTP 429 Too Many Requests{}: {}", retry_after, body),
+            ));
+        }
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            anyhow::bail!("OpenAI-compatible API returned error {}: {}", status, body);
+        }
+
+        let completion: ChatCompletionResponse = response
+            .json()
+            .context("Failed to parse chat completion response")?;
+        let usage = completion
+            .usage
+            .map(|usage| serde_json::json!({ "usage": usage }))
+            .and_then(|value| usage::usage_from_value(&value));
+        let content = completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| anyhow!("Chat completion response contained no message"))?;
+
+        Ok(ToolOutput {
+            usage,
+            ..ToolOutput::http(content, String::new())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions_url() {
+        assert_eq!(
+            completions_url("http://localhost:8080/v1"),
+            "http://localhost:8080/v1/chat/completions"
+        );
+        assert_eq!(
+            completions_url("http://localhost:8080/v1/"),
+            "http://localhost:8080/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_reasoning_effort_only_when_configured() {
+        let body = |effort| serde_json::to_value(ChatCompletionRequest::new("m", "hi", effort)).unwrap();
+        assert!(body(None).get("reasoning_effort").is_none());
+        assert_eq!(body(Some(ReasoningEffort::High))["reasoning_effort"], "high");
+        assert_eq!(body(Some(ReasoningEffort::Minimal))["reasoning_effort"], "low");
+    }
+}
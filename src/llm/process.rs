@@ -0,0 +1,445 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Subprocess plumbing for CLI-based LLM tools.
+//!
+//! Output is read line by line on background threads so callers can tee it
+//! to the console and log while the agent is still running, instead of
+//! waiting for the whole turn to finish. A wall-clock and an inactivity
+//! timeout bound each run; on expiry the child's whole process group is killed.
+//! Running groups are tracked so a forced exit can take them down too.
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::retry::InvokeFailure;
+use super::ToolOutput;
+use crate::constants::{DEFAULT_IDLE_TIMEOUT_SECONDS, DEFAULT_TIMEOUT_SECONDS};
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+
+/// Which output stream a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Receives output lines (without trailing newline) as they are produced
+pub type OutputSink<'a> = dyn FnMut(OutputStream, &str) + 'a;
+
+/// Time limits for a single tool invocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvokeTimeouts {
+    /// Maximum total run time
+    pub wall_clock: Option<Duration>,
+    /// Maximum time without a line on stdout or stderr
+    pub idle: Option<Duration>,
+}
+
+impl InvokeTimeouts {
+    /// Build from second counts, where 0 disables that limit
+    pub fn from_secs(wall_clock: u64, idle: u64) -> Self {
+        let to_limit = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+        Self {
+            wall_clock: to_limit(wall_clock),
+            idle: to_limit(idle),
+        }
+    }
+}
+
+impl Default for InvokeTimeouts {
+    fn default() -> Self {
+        Self::from_secs(DEFAULT_TIMEOUT_SECONDS, DEFAULT_IDLE_TIMEOUT_SECONDS)
+    }
+}
+
+/// Process groups of tool invocations that are still running
+static RUNNING: Lazy<ProcessGroups> = Lazy::new(ProcessGroups::default);
+
+/// Ids of running process groups (each child leads its own)
+#[derive(Debug, Default)]
+struct ProcessGroups {
+    pgids: Mutex<HashSet<u32>>,
+}
+
+impl ProcessGroups {
+    /// Track `pgid` until the returned guard is dropped
+    fn track(&self, pgid: u32) -> TrackedGroup<'_> {
+        self.pgids.lock().unwrap().insert(pgid);
+        TrackedGroup { groups: self, pgid }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pgids.lock().unwrap().is_empty()
+    }
+
+    /// Send SIGTERM to every tracked group, give them `grace` to exit, then
+    /// SIGKILL whatever is left. Returns how many groups were signalled.
+    #[cfg(unix)]
+    fn terminate(&self, grace: Duration) -> usize {
+        let pgids: Vec<u32> = self.pgids.lock().unwrap().iter().copied().collect();
+        for &pgid in &pgids {
+            signal_group(pgid, libc::SIGTERM);
+        }
+        // Groups are untracked once their invocation has reaped the child
+        let deadline = Instant::now() + grace;
+        while !self.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        // Also catches grandchildren that outlived their group leader
+        for &pgid in &pgids {
+            signal_group(pgid, libc::SIGKILL);
+        }
+        pgids.len()
+    }
+
+    /// Without process groups there is nothing to signal
+    #[cfg(not(unix))]
+    fn terminate(&self, _grace: Duration) -> usize {
+        0
+    }
+}
+
+/// Stops tracking a process group when dropped
+struct TrackedGroup<'a> {
+    groups: &'a ProcessGroups,
+    pgid: u32,
+}
+
+impl Drop for TrackedGroup<'_> {
+    fn drop(&mut self) {
+        self.groups.pgids.lock().unwrap().remove(&self.pgid);
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(pgid: u32, signal: libc::c_int) {
+    // SAFETY: killpg has no memory-safety preconditions
+    unsafe {
+        libc::killpg(pgid as libc::pid_t, signal);
+    }
+}
+
+/// Terminate the process groups of all running tool invocations: SIGTERM,
+/// then SIGKILL for any still running after `grace`. Returns how many groups
+/// were signalled.
+pub fn terminate_running_tools(grace: Duration) -> usize {
+    RUNNING.terminate(grace)
+}
+
+/// Spawn `cmd` with all stdio piped.
+pub(super) fn spawn(cmd: &mut Command) -> std::io::Result<Child> {
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // Spawn in a new process group so it won't receive terminal SIGINT
+    // This allows graceful Ctrl+C handling - the parent catches SIGINT and
+    // waits for the child to complete instead of both being killed
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    cmd.spawn()
+}
+
+/// Run a command that isn't an LLM tool (e.g. the build/test gate) the same
+/// way: in its own process group, tracked for a forced exit, and killed if it
+/// outlives `timeout`. Output lines are passed to `sink` as they arrive.
+pub fn run_command(
+    cmd: &mut Command,
+    sink: Option<&mut OutputSink<'_>>,
+    timeout: Option<Duration>,
+) -> Result<ToolOutput> {
+    let child = spawn(cmd)?;
+    let timeouts = InvokeTimeouts {
+        wall_clock: timeout,
+        idle: None,
+    };
+    collect_output(child, None, sink, timeouts)
+}
+
+/// Feed `input` to the child's stdin (closing it afterwards), then collect
+/// stdout and stderr until the child exits, passing each line to `sink` as it
+/// arrives. Returns the complete stdout and stderr buffers with the exit code,
+/// or an [`InvokeFailure::TimedOut`] if a timeout expired (the child is
+/// killed in that case).
+pub(super) fn collect_output(
+    mut child: Child,
+    input: Option<&str>,
+    mut sink: Option<&mut OutputSink<'_>>,
+    timeouts: InvokeTimeouts,
+) -> Result<ToolOutput> {
+    // The child was spawned with process_group(0), so its pid is the pgid
+    let _tracked = RUNNING.track(child.id());
+
+    // Write stdin from its own thread so a child that streams a lot of output
+    // before reading all of its input can't deadlock against us
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.unwrap_or_default().as_bytes().to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+
+    let (tx, rx) = mpsc::channel();
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(spawn_reader(stdout, OutputStream::Stdout, tx.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(spawn_reader(stderr, OutputStream::Stderr, tx.clone()));
+    }
+    drop(tx);
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let started = Instant::now();
+    let mut last_output = started;
+    // Ends once both readers hit EOF and drop their senders
+    loop {
+        let now = Instant::now();
+        let wall_left = timeouts
+            .wall_clock
+            .map(|limit| limit.saturating_sub(now - started));
+        let idle_left = timeouts
+            .idle
+            .map(|limit| limit.saturating_sub(now - last_output));
+        let wait = match (wall_left, idle_left) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let received = match wait {
+            Some(wait) => rx.recv_timeout(wait),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok((stream, line)) => {
+                last_output = Instant::now();
+                match stream {
+                    OutputStream::Stdout => stdout.push_str(&line),
+                    OutputStream::Stderr => stderr.push_str(&line),
+                }
+                if let Some(sink) = sink.as_mut() {
+                    sink(stream, line.trim_end_matches(['\n', '\r']));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                kill_process_group(&mut child);
+                // Reader and writer threads are left to finish on their own:
+                // a grandchild that escaped the group could keep a pipe open
+                let _ = child.wait();
+                let elapsed = started.elapsed();
+                let message = if timeouts.wall_clock.is_some_and(|limit| elapsed >= limit) {
+                    format!(
+                        "LLM process timed out after {}s (wall-clock limit)",
+                        elapsed.as_secs()
+                    )
+                } else {
+                    format!(
+                        "LLM process produced no output for {}s (inactivity limit); killed after {}s",
+                        timeouts.idle.unwrap_or_default().as_secs(),
+                        elapsed.as_secs()
+                    )
+                };
+                return Err(InvokeFailure::TimedOut(message).into());
+            }
+        }
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let status = child.wait().context("Failed to wait for LLM process")?;
+
+    if let Some(writer) = writer
+        && let Ok(Err(e)) = writer.join()
+        && e.kind() != ErrorKind::BrokenPipe
+    {
+        return Err(e).context("Failed to write prompt to LLM process");
+    }
+
+    Ok(ToolOutput {
+        stdout,
+        stderr,
+        exit_code: status.code(),
+        usage: None,
+        structured: None,
+    })
+}
+
+/// Pass each line of already-collected output to `sink`, for backends that
+/// can't stream (HTTP APIs).
+pub(super) fn replay_to_sink(stdout: &str, stderr: &str, sink: &mut OutputSink<'_>) {
+    for line in stdout.lines() {
+        sink(OutputStream::Stdout, line);
+    }
+    for line in stderr.lines() {
+        sink(OutputStream::Stderr, line);
+    }
+}
+
+/// Kill the child and everything in the process group it leads
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        // The child was spawned with process_group(0), so its pid is the pgid
+        signal_group(child.id(), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+fn spawn_reader<R: Read + Send + 'static>(
+    source: R,
+    stream: OutputStream,
+    tx: Sender<(OutputStream, String)>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(source);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buf).into_owned();
+                    if tx.send((stream, line)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_output_tees_lines_and_keeps_buffers() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "cat; echo err >&2; printf 'no newline'"]);
+        let child = spawn(&mut cmd).unwrap();
+
+        let mut seen = Vec::new();
+        let mut sink = |stream: OutputStream, line: &str| seen.push((stream, line.to_string()));
+        let output = collect_output(child, Some("one\ntwo\n"), Some(&mut sink), InvokeTimeouts::default())
+                .unwrap();
+
+        assert_eq!(output.stdout, "one\ntwo\nno newline");
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.exit_code, Some(0));
+        assert!(seen.contains(&(OutputStream::Stdout, "one".to_string())));
+        assert!(seen.contains(&(OutputStream::Stdout, "no newline".to_string())));
+        assert!(seen.contains(&(OutputStream::Stderr, "err".to_string())));
+    }
+
+    #[test]
+    fn test_collect_output_ignores_unread_stdin() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo done"]);
+        let child = spawn(&mut cmd).unwrap();
+
+        let big_prompt = "x".repeat(1 << 20);
+        let output = collect_output(child, Some(&big_prompt), None, InvokeTimeouts::default()).unwrap();
+        assert_eq!(output.stdout, "done\n");
+    }
+
+    #[test]
+    fn test_idle_timeout_kills_process_group() {
+        let mut cmd = Command::new("sh");
+        // The grandchild sleep shares the group and holds the pipes open
+        cmd.args(["-c", "echo started; sleep 30 & wait"]);
+        let child = spawn(&mut cmd).unwrap();
+
+        let begin = Instant::now();
+        let timeouts = InvokeTimeouts {
+            wall_clock: None,
+            idle: Some(Duration::from_millis(300)),
+        };
+        let err = collect_output(child, None, None, timeouts).unwrap_err();
+        assert!(err.to_string().contains("inactivity"));
+        assert!(begin.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_wall_clock_timeout() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "while true; do echo tick; sleep 0.05; done"]);
+        let child = spawn(&mut cmd).unwrap();
+
+        let timeouts = InvokeTimeouts {
+            wall_clock: Some(Duration::from_millis(300)),
+            idle: Some(Duration::from_secs(5)),
+        };
+        let err = collect_output(child, None, None, timeouts).unwrap_err();
+        assert!(err.to_string().contains("wall-clock"));
+    }
+
+    #[test]
+    fn test_terminate_escalates_to_sigkill() {
+        let groups = ProcessGroups::default();
+        let spawn_tracked = |script: &str| {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", script]);
+            let mut child = spawn(&mut cmd).unwrap();
+            // Wait until the script is running (and its trap installed)
+            let mut line = String::new();
+            BufReader::new(child.stdout.take().unwrap())
+                .read_line(&mut line)
+                .unwrap();
+            child
+        };
+
+        thread::scope(|scope| {
+            let reap = |mut child: Child| {
+                let tracked = groups.track(child.id());
+                scope.spawn(move || {
+                    let status = child.wait().unwrap();
+                    drop(tracked);
+                    status
+                })
+            };
+            let polite = reap(spawn_tracked("echo ready; sleep 30"));
+            let begin = Instant::now();
+            assert_eq!(groups.terminate(Duration::from_secs(10)), 1);
+            assert!(begin.elapsed() < Duration::from_secs(5));
+            assert!(polite.join().unwrap().code().is_none());
+
+            let stubborn = reap(spawn_tracked("trap '' TERM; echo ready; sleep 30"));
+            let begin = Instant::now();
+            assert_eq!(groups.terminate(Duration::from_millis(300)), 1);
+            assert!(begin.elapsed() >= Duration::from_millis(300));
+            assert!(stubborn.join().unwrap().code().is_none());
+        });
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_timeouts_from_secs() {
+        let timeouts = InvokeTimeouts::from_secs(60, 0);
+        assert_eq!(timeouts.wall_clock, Some(Duration::from_secs(60)));
+        assert_eq!(timeouts.idle, None);
+    }
+}
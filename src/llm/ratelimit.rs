@@ -0,0 +1,360 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate-limit detection and reset-time parsing.
+//!
+//! An agent's transcript routinely mentions "429" or "rate limit" while it
+//! works, so stdout (structured payloads included) is only searched when the
+//! tool exited unsuccessfully. Stderr, where structured-mode errors are also
+//! reported, and HTTP status lines are the primary signals. Reset hints
+//! ("retry after 120s", "limit will reset at 3pm") are turned into a squelch
+//! duration so the tool is retried when it is usable again.
+
+use chrono::{DateTime, Duration as ChronoDuration, FixedOffset, Local, NaiveTime, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::time::Duration;
+
+use super::ToolOutput;
+
+/// Longest squelch a reset hint may request; guards against misparsed dates
+const MAX_SQUELCH: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// A 429 in a status form ("HTTP 429", "HTTP/1.1 429", "status: 429",
+/// "code 429", "429 Too Many Requests"), so a bare "429" (a line number, a
+/// test count) does not count
+static HTTP_429: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?i)(?:\bhttp(?:/\d(?:\.\d)?)?:?\s*429\b|\b(?:status|code)(?:[ _]code)?"?\s*[:=]?\s*"?429\b|\b429\b[^\n]{0,5}too many requests)"#,
+    )
+    .unwrap()
+});
+
+/// Claude's CLI reports a usage limit as `...usage limit reached|<unix epoch>`
+static EPOCH_RESET: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)limit reached\|(\d{9,11})").unwrap());
+
+/// "retry after 120s", "Retry-After: 120", "retryDelay": "34s", "try again in 5 minutes"
+static RELATIVE_RESET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(?:retry[-_ ]?(?:after|delay|in)|try again in|resets? in)\W{0,4}(\d+(?:\.\d+)?)\s*([a-z]*)")
+        .unwrap()
+});
+
+/// "limit will reset at 3pm", "resets 5:30 PM", "try again at 15:00"
+static CLOCK_RESET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:resets?|again)\s+(at\s+)?(\d{1,2})(?::(\d{2}))?\s*(?:([ap])\.?m\b\.?)?")
+        .unwrap()
+});
+
+/// The zone after a clock hint: "(UTC)", "GMT+2", "UTC-05:30", or a named
+/// zone ("(America/Los_Angeles)", "PST") that can't be resolved here
+static CLOCK_ZONE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^\s*\(?\s*(?:(?i:(?:utc|gmt)\b)(?:\s*([+-])(\d{1,2})(?::?(\d{2}))?)?|([A-Za-z]+/[A-Za-z_]+|[A-Z]{1,4}T\b))",
+    )
+    .unwrap()
+});
+
+/// A detected rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// How long until the limit lifts, if the tool said so
+    pub retry_after: Option<Duration>,
+}
+
+/// Decide whether `output` is a rate-limit failure, using the tool's
+/// case-insensitive `patterns`.
+pub(super) fn detect(patterns: &[&str], output: &ToolOutput) -> Option<RateLimit> {
+    let failed = output.exit_code.is_some_and(|code| code != 0);
+    let produced_nothing = output.stdout.trim().is_empty();
+
+    // Structured errors count as stderr; a payload on stdout may just be code
+    // the agent was working on
+    let is_payload = |text: &str| has_rate_limit_payload(text) || EPOCH_RESET.is_match(text);
+    let structured = is_payload(&output.stderr) || (failed && is_payload(&output.stdout));
+    // HTTP backends have no exit code; their stderr only carries our own error text
+    let stderr_trusted = output.exit_code != Some(0) || produced_nothing;
+    let in_stderr = stderr_trusted && mentions_rate_limit(patterns, &output.stderr);
+    let in_stdout = failed && mentions_rate_limit(patterns, &output.stdout);
+
+    if !(structured || in_stderr || in_stdout) {
+        return None;
+    }
+
+    let hint_source = if in_stdout || structured {
+        format!("{}\n{}", output.stderr, output.stdout)
+    } else {
+        output.stderr.clone()
+    };
+    Some(RateLimit {
+        retry_after: parse_reset_hint(&hint_source, Local::now()),
+    })
+}
+
+fn mentions_rate_limit(patterns: &[&str], text: &str) -> bool {
+    if text.is_empty() {
+        return false;
+    }
+    let lower = text.to_lowercase();
+    HTTP_429.is_match(text)
+        || patterns
+            .iter()
+            .filter(|pattern| !pattern.is_empty())
+            .any(|pattern| lower.contains(&pattern.to_lowercase()))
+}
+
+/// Look for a JSON error object (on a line of its own or after a prefix such
+/// as `Error: `) that describes a rate limit.
+fn has_rate_limit_payload(text: &str) -> bool {
+    text.lines().any(|line| {
+        line.find('{')
+            .and_then(|start| serde_json::from_str::<Value>(line[start..].trim()).ok())
+            .is_some_and(|value| is_rate_limit_value(&value))
+    })
+}
+
+fn is_rate_limit_value(value: &Value) -> bool {
+    let Some(object) = value.as_object() else {
+        return false;
+    };
+    let text_matches = |key: &str| {
+        object.get(key).and_then(Value::as_str).is_some_and(|s| {
+            let s = s.to_lowercase();
+            s.contains("rate_limit") || s == "resource_exhausted" || s.contains("quota")
+        })
+    };
+    let status_429 = ["code", "status", "status_code"]
+        .iter()
+        .any(|key| object.get(*key).and_then(Value::as_u64) == Some(429));
+
+    status_429
+        || text_matches("type")
+        || text_matches("code")
+        || text_matches("status")
+        || object.get("error").is_some_and(is_rate_limit_value)
+}
+
+/// Extract how long until a limit resets from free-form error text
+pub(super) fn parse_reset_hint(text: &str, now: DateTime<Local>) -> Option<Duration> {
+    let hint = parse_epoch(text, now)
+        .or_else(|| parse_relative(text))
+        .or_else(|| parse_clock_time(text, now))?;
+    Some(hint.min(MAX_SQUELCH))
+}
+
+fn parse_epoch(text: &str, now: DateTime<Local>) -> Option<Duration> {
+    let epoch: i64 = EPOCH_RESET.captures(text)?[1].parse().ok()?;
+    let reset = Local.timestamp_opt(epoch, 0).single()?;
+    (reset - now).to_std().ok()
+}
+
+fn parse_relative(text: &str) -> Option<Duration> {
+    let caps = RELATIVE_RESET.captures(text)?;
+    let amount: f64 = caps[1].parse().ok()?;
+    let unit = caps[2].to_lowercase();
+    let seconds = match unit.as_str() {
+        "ms" | "msec" | "millis" | "milliseconds" => amount / 1000.0,
+        u if u.starts_with('m') => amount * 60.0,
+        u if u.starts_with('h') => amount * 3600.0,
+        u if u.starts_with('d') => amount * 86400.0,
+        _ => amount,
+    };
+    Some(Duration::from_secs_f64(seconds.max(0.0)))
+}
+
+fn parse_clock_time(text: &str, now: DateTime<Local>) -> Option<Duration> {
+    for caps in CLOCK_RESET.captures_iter(text) {
+        let has_at = caps.get(1).is_some();
+        let meridiem = caps.get(4).map(|m| m.as_str().to_lowercase());
+        let minute: u32 = caps.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        // "resets 3" is too vague; need "at" or a clock-like form
+        if !has_at && meridiem.is_none() && caps.get(3).is_none() {
+            continue;
+        }
+
+        let mut hour: u32 = caps[2].parse().ok()?;
+        match meridiem.as_deref() {
+            Some("p") if hour < 12 => hour += 12,
+            Some("a") if hour == 12 => hour = 0,
+            _ => {}
+        }
+        let time = NaiveTime::from_hms_opt(hour, minute, 0)?;
+
+        let Some(zone) = CLOCK_ZONE.captures(&text[caps.get(0)?.end()..]) else {
+            return until_next(&Local, time, now);
+        };
+        if zone.get(4).is_some() {
+            // A named zone we can't convert; guessing would squelch for hours
+            return None;
+        }
+        let offset_hours: i32 = zone.get(2).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        let offset_minutes: i32 = zone.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        let mut offset = offset_hours * 3600 + offset_minutes * 60;
+        if zone.get(1).is_some_and(|sign| sign.as_str() == "-") {
+            offset = -offset;
+        }
+        return match FixedOffset::east_opt(offset) {
+            Some(zone) if offset != 0 => until_next(&zone, time, now),
+            _ => until_next(&Utc, time, now),
+        };
+    }
+    None
+}
+
+/// Time from `now` until the next `time` of day in `zone`
+fn until_next<Tz: TimeZone>(zone: &Tz, time: NaiveTime, now: DateTime<Local>) -> Option<Duration> {
+    let local_now = now.with_timezone(zone).naive_local();
+    let mut reset = local_now.date().and_time(time);
+    if reset <= local_now {
+        reset += ChronoDuration::days(1);
+    }
+    let reset = zone.from_local_datetime(&reset).earliest()?;
+    reset.signed_duration_since(now).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(stdout: &str, stderr: &str, exit_code: Option<i32>) -> ToolOutput {
+        ToolOutput {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            exit_code,
+            ..Default::default()
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2025, 6, 1, hour, minute, 0)
+            .earliest()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_transcript_mentions_do_not_trigger() {
+        let patterns = ["rate limit"];
+        let transcript = "Fixed the off-by-one on line 429 and added rate limit handling.";
+        assert_eq!(detect(&patterns, &output(transcript, "", Some(0))), None);
+        // Even on failure a bare number is not an HTTP status
+        assert_eq!(detect(&[], &output(transcript, "", Some(1))), None);
+        for stderr in ["error on line 429", "error: 429 tests failed", "Error 429 lines changed"] {
+            assert_eq!(detect(&[], &output("", stderr, Some(1))), None, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_http_status_forms() {
+        for stderr in [
+            "HTTP 429",
+            "HTTP/1.1 429 Too Many Requests",
+            "HTTP/2 429",
+            "status: 429",
+            "status 429",
+            "status_code=429",
+            "error code 429",
+            r#"{"code": 429}"#,
+            "Error 429: Too Many Requests",
+        ] {
+            assert!(detect(&[], &output("", stderr, Some(1))).is_some(), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_stderr_and_exit_code_signals() {
+        let patterns = ["quota exceeded"];
+        assert!(detect(&patterns, &output("", "Error: Quota exceeded", Some(1))).is_some());
+        assert!(detect(&patterns, &output("", "HTTP 429 Too Many Requests", None)).is_some());
+        assert!(detect(&patterns, &output("quota exceeded", "", Some(2))).is_some());
+        // Successful run that merely logged the phrase while working
+        assert!(detect(&patterns, &output("done", "checking quota exceeded path", Some(0))).is_none());
+    }
+
+    #[test]
+    fn test_structured_payload() {
+        let payload = r#"Error: {"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#;
+        // An agent that printed a payload (or edited code containing one) and
+        // finished successfully isn't rate-limited
+        assert!(detect(&[], &output(payload, "", Some(0))).is_none());
+        assert!(detect(&[], &output(r#"{"status":429}"#, "", Some(0))).is_none());
+        assert!(detect(&[], &output(payload, "", Some(1))).is_some());
+        // Structured-mode errors are reported as stderr
+        assert!(detect(&[], &output("done", payload, Some(0))).is_some());
+
+        let gemini = r#"{"error":{"code":429,"status":"RESOURCE_EXHAUSTED","details":[{"retryDelay":"34s"}]}}"#;
+        let limit = detect(&[], &output("", gemini, Some(1))).unwrap();
+        assert_eq!(limit.retry_after, Some(Duration::from_secs(34)));
+    }
+
+    #[test]
+    fn test_relative_hints() {
+        let now = at(10, 0);
+        assert_eq!(
+            parse_reset_hint("Rate limited, retry after 120s", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_reset_hint("Retry-After: 90", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_reset_hint("please try again in 5 minutes", now),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(parse_reset_hint("no hint here", now), None);
+    }
+
+    #[test]
+    fn test_clock_hints() {
+        let now = at(10, 0);
+        let utc_ten = Local.from_utc_datetime(&at(10, 0).naive_local());
+        assert_eq!(
+            parse_reset_hint("Claude usage limit reached. Your limit will reset at 3pm (UTC).", utc_ten),
+            Some(Duration::from_secs(5 * 3600))
+        );
+        assert_eq!(
+            parse_reset_hint("limit resets at 3pm UTC+2", utc_ten),
+            Some(Duration::from_secs(3 * 3600))
+        );
+        // A zone that can't be resolved leaves the default squelch
+        assert_eq!(
+            parse_reset_hint("limit resets 3pm (America/Los_Angeles)", now),
+            None
+        );
+        assert_eq!(
+            parse_reset_hint("5-hour limit reached - resets 3pm", now),
+            Some(Duration::from_secs(5 * 3600))
+        );
+        assert_eq!(
+            parse_reset_hint("5-hour limit reached - resets 10:30am", now),
+            Some(Duration::from_secs(30 * 60))
+        );
+        // Already past today: tomorrow
+        assert_eq!(
+            parse_reset_hint("limit will reset at 9am", now),
+            Some(Duration::from_secs(23 * 3600))
+        );
+    }
+
+    #[test]
+    fn test_epoch_hint() {
+        let now = at(10, 0);
+        let reset = now.timestamp() + 600;
+        let text = format!("Claude AI usage limit reached|{}", reset);
+        assert_eq!(parse_reset_hint(&text, now), Some(Duration::from_secs(600)));
+        assert!(detect(&[], &output(&text, "", Some(1))).is_some());
+    }
+}
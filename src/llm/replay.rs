@@ -0,0 +1,492 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scripted `replay` backend.
+//!
+//! Answers prompts from a TOML script instead of running an agent, so whole
+//! orchestration configs (parallel, verify, spiral) can be dry-run
+//! deterministically. A script has prompt-keyed `[[rule]]`s, checked first,
+//! and a sequence of `[[step]]`s used in order:
+//!
+//! ```toml
+//! when_exhausted = "repeat_last"   # or "error" (default), "cycle"
+//!
+//! [[rule]]
+//! prompt = "previous response emitted the stop"
+//! stdout = "__ALL_TASKS_COMPLETE__"
+//!
+//! [[step]]
+//! rate_limit = "Rate limit reached, retry after 30s"
+//!
+//! [[step]]
+//! stdout = "Finished the task"
+//! delay_seconds = 0.5
+//! edits = [{ path = "checklist.md", find = "- [ ] Task", replace = "- [x] Task" }]
+//! ```
+//!
+//! A recorded cassette can be replayed the same way: each tool answers with
+//! its own recorded exchanges, in order.
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use super::cassette::CassetteEntry;
+use super::retry::{Classification, InvokeFailure};
+use super::{EventFormat, InvokeTimeouts, ToolOutput};
+
+/// What to do once every step has been used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenExhausted {
+    /// Fail the invocation, like a crashed tool
+    #[default]
+    Error,
+    /// Keep answering with the last step
+    RepeatLast,
+    /// Start over from the first step
+    Cycle,
+}
+
+/// One scripted answer
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayResponse {
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    /// Defaults to 0, or 1 for a simulated rate limit
+    pub exit_code: Option<i32>,
+    /// Seconds to wait before answering; subject to the wall-clock limit
+    #[serde(default)]
+    pub delay_seconds: f64,
+    /// Simulate a rate limit: this message goes to stderr with exit code 1
+    pub rate_limit: Option<String>,
+    /// Simulate a tool that fails to run at all
+    pub error: Option<String>,
+    /// Files to change before answering, as the agent would
+    #[serde(default)]
+    pub edits: Vec<FileEdit>,
+    /// Set when the response comes from a cassette
+    #[serde(skip)]
+    recorded: Option<RecordedExchange>,
+}
+
+/// Where a replayed cassette response came from
+#[derive(Debug, Clone, PartialEq)]
+struct RecordedExchange {
+    seq: usize,
+    prompt: String,
+    /// Kept as recorded; HTTP backends have none
+    exit_code: Option<i32>,
+    /// Replayed failures fail the same way, so the chain retries or falls
+    /// back as it did when recording
+    classification: Classification,
+}
+
+/// A side effect applied to a file, relative to the working directory
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileEdit {
+    pub path: PathBuf,
+    /// Text to replace (first occurrence unless `all`); omit to append
+    pub find: Option<String>,
+    #[serde(default)]
+    pub replace: String,
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// A response chosen by matching the prompt
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    prompt: String,
+    /// How many times the rule may fire; unlimited when absent
+    times: Option<usize>,
+    #[serde(flatten)]
+    response: ReplayResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptConfig {
+    #[serde(default)]
+    when_exhausted: WhenExhausted,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleConfig>,
+    #[serde(default, rename = "step")]
+    steps: Vec<ReplayResponse>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    prompt: Regex,
+    times: Option<usize>,
+    response: ReplayResponse,
+}
+
+/// A loaded script plus how far through it we are. Clones of a tool share
+/// one script, so the sequence advances across invocations.
+#[derive(Debug)]
+pub struct ReplayTool {
+    pub(super) name: String,
+    /// "Replay script <path>" or "Cassette <path>", for error messages
+    source: String,
+    /// Event schema of the recorded output, for structured cassettes
+    pub(super) event_format: Option<EventFormat>,
+    when_exhausted: WhenExhausted,
+    rules: Vec<Rule>,
+    steps: Vec<ReplayResponse>,
+    state: Mutex<ReplayState>,
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    next_step: usize,
+    rule_uses: Vec<usize>,
+}
+
+impl ReplayTool {
+    pub fn load(name: &str, path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read replay script {}", path.display()))?;
+        Self::parse(name, path, &content)
+    }
+
+    fn parse(name: &str, path: &Path, content: &str) -> Result<Self> {
+        let config: ScriptConfig = toml::from_str(content)
+            .with_context(|| format!("Failed to parse replay script {}", path.display()))?;
+        let rules = config
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let prompt = Regex::new(&rule.prompt).with_context(|| {
+                    format!("Invalid prompt regex '{}' in {}", rule.prompt, path.display())
+                })?;
+                Ok(Rule {
+                    prompt,
+                    times: rule.times,
+                    response: rule.response,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if rules.is_empty() && config.steps.is_empty() {
+            anyhow::bail!("Replay script {} has no [[rule]] or [[step]] entries", path.display());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            source: format!("Replay script {}", path.display()),
+            event_format: None,
+            when_exhausted: config.when_exhausted,
+            state: Mutex::new(ReplayState {
+                next_step: 0,
+                rule_uses: vec![0; rules.len()],
+            }),
+            rules,
+            steps: config.steps,
+        })
+    }
+
+    /// Replay the exchanges `name` answered in a recorded cassette
+    pub fn from_cassette(name: &str, path: &Path, entries: &[CassetteEntry]) -> Result<Self> {
+        let entries: Vec<&CassetteEntry> = entries.iter().filter(|e| e.tool == name).collect();
+        if entries.is_empty() {
+            anyhow::bail!("Cassette {} has no exchanges for tool {}", path.display(), name);
+        }
+        let steps = entries
+            .iter()
+            .map(|entry| ReplayResponse {
+                stdout: entry.stdout.clone(),
+                stderr: entry.stderr.clone(),
+                error: entry.classification.is_error().then(|| {
+                    entry
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| "recorded invocation failed".to_string())
+                }),
+                recorded: Some(RecordedExchange {
+                    seq: entry.seq,
+                    prompt: entry.prompt.clone(),
+                    exit_code: entry.exit_code,
+                    classification: entry.classification,
+                }),
+                ..Default::default()
+            })
+            .collect();
+
+        Ok(Self {
+            name: name.to_string(),
+            source: format!("Cassette {}", path.display()),
+            event_format: entries.iter().find_map(|e| e.event_format),
+            when_exhausted: WhenExhausted::Error,
+            rules: Vec::new(),
+            steps,
+            state: Mutex::new(ReplayState::default()),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Pick the response for `prompt`: the first matching rule with uses
+    /// left, otherwise the next step
+    fn next_response(&self, prompt: &str) -> Result<ReplayResponse> {
+        let mut state = self.lock();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.times.is_some_and(|times| state.rule_uses[index] >= times)
+                || !rule.prompt.is_match(prompt)
+            {
+                continue;
+            }
+            state.rule_uses[index] += 1;
+            return Ok(rule.response.clone());
+        }
+
+        let index = if state.next_step < self.steps.len() {
+            state.next_step
+        } else {
+            match self.when_exhausted {
+                WhenExhausted::Error => None,
+                WhenExhausted::RepeatLast => self.steps.len().checked_sub(1),
+                WhenExhausted::Cycle if !self.steps.is_empty() => {
+                    Some(state.next_step % self.steps.len())
+                }
+                WhenExhausted::Cycle => None,
+            }
+            .ok_or_else(|| {
+                anyhow!("{} has no response left for {}", self.source, self.name)
+            })?
+        };
+        state.next_step += 1;
+        Ok(self.steps[index].clone())
+    }
+
+    /// Answer `prompt` as the scripted agent would
+    pub(super) fn respond(&self, prompt: &str, timeouts: InvokeTimeouts) -> Result<ToolOutput> {
+        let response = self.next_response(prompt)?;
+        if let Some(recorded) = &response.recorded
+            && recorded.prompt != prompt
+        {
+            eprintln!(
+                "Warning: prompt differs from recorded exchange {} ({})",
+                recorded.seq, self.name
+            );
+        }
+
+        if response.delay_seconds > 0.0 {
+            let delay = Duration::from_secs_f64(response.delay_seconds);
+            if let Some(limit) = timeouts.wall_clock
+                && delay > limit
+            {
+                thread::sleep(limit);
+                return Err(InvokeFailure::TimedOut(format!(
+                    "{} timed out after {}s (wall-clock limit)",
+                    self.name,
+                    limit.as_secs()
+                ))
+                .into());
+            }
+            thread::sleep(delay);
+        }
+
+        if let Some(error) = response.error {
+            return Err(match response.recorded.map(|r| r.classification) {
+                Some(Classification::SpawnFailure) => InvokeFailure::Spawn(error).into(),
+                Some(Classification::Timeout) => InvokeFailure::TimedOut(error).into(),
+                _ => anyhow!(error),
+            });
+        }
+        for edit in &response.edits {
+            apply_edit(edit)?;
+        }
+
+        let (stderr, exit_code) = match (response.recorded, response.rate_limit) {
+            (Some(recorded), _) => (response.stderr, recorded.exit_code),
+            (None, Some(message)) => (message, Some(response.exit_code.unwrap_or(1))),
+            (None, None) => (response.stderr, Some(response.exit_code.unwrap_or(0))),
+        };
+        Ok(ToolOutput {
+            stdout: response.stdout,
+            stderr,
+            exit_code,
+            ..Default::default()
+        })
+    }
+}
+
+fn apply_edit(edit: &FileEdit) -> Result<()> {
+    let path = &edit.path;
+    let content = if path.exists() {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        String::new()
+    };
+
+    let updated = match &edit.find {
+        Some(find) if !content.contains(find.as_str()) => {
+            anyhow::bail!("Replay edit: '{}' not found in {}", find, path.display())
+        }
+        Some(find) if edit.all => content.replace(find.as_str(), &edit.replace),
+        Some(find) => content.replacen(find.as_str(), &edit.replace, 1),
+        None => content + &edit.replace,
+    };
+    fs::write(path, updated).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn script(content: &str) -> ReplayTool {
+        ReplayTool::parse("replay", Path::new("script.toml"), content).unwrap()
+    }
+
+    #[test]
+    fn test_steps_then_exhausted() {
+        let tool = script("[[step]]\nstdout = \"one\"\n\n[[step]]\nstdout = \"two\"\n");
+        let timeouts = InvokeTimeouts::default();
+        assert_eq!(tool.respond("x", timeouts).unwrap().stdout, "one");
+        assert_eq!(tool.respond("x", timeouts).unwrap().stdout, "two");
+        assert!(tool.respond("x", timeouts).is_err());
+
+        let repeat = script("when_exhausted = \"repeat_last\"\n[[step]]\nstdout = \"a\"\n[[step]]\nstdout = \"b\"\n");
+        let answers: Vec<_> = (0..3).map(|_| repeat.respond("x", timeouts).unwrap().stdout).collect();
+        assert_eq!(answers, ["a", "b", "b"]);
+
+        let cycle = script("when_exhausted = \"cycle\"\n[[step]]\nstdout = \"a\"\n[[step]]\nstdout = \"b\"\n");
+        let answers: Vec<_> = (0..3).map(|_| cycle.respond("x", timeouts).unwrap().stdout).collect();
+        assert_eq!(answers, ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn test_rules_match_prompt_and_run_out() {
+        let tool = script(
+            "[[rule]]\nprompt = \"(?i)confirm\"\ntimes = 1\nstdout = \"confirmed\"\n\n[[step]]\nstdout = \"working\"\n",
+        );
+        let timeouts = InvokeTimeouts::default();
+        assert_eq!(tool.respond("please CONFIRM", timeouts).unwrap().stdout, "confirmed");
+        // The rule is used up, so the sequence answers
+        assert_eq!(tool.respond("please confirm", timeouts).unwrap().stdout, "working");
+    }
+
+    #[test]
+    fn test_rate_limit_and_error() {
+        let tool = script(
+            "[[step]]\nrate_limit = \"Rate limit reached, retry after 30s\"\n\n[[step]]\nerror = \"boom\"\n",
+        );
+        let timeouts = InvokeTimeouts::default();
+        let limited = tool.respond("x", timeouts).unwrap();
+        assert_eq!(limited.exit_code, Some(1));
+        assert!(limited.stderr.contains("retry after 30s"));
+        assert_eq!(tool.respond("x", timeouts).unwrap_err().to_string(), "boom");
+    }
+
+    #[test]
+    fn test_delay_respects_wall_clock() {
+        let tool = script("[[step]]\ndelay_seconds = 5\n");
+        let timeouts = InvokeTimeouts {
+            wall_clock: Some(Duration::from_millis(10)),
+            idle: None,
+        };
+        let err = tool.respond("x", timeouts).unwrap_err();
+        assert!(err.to_string().contains("wall-clock limit"));
+    }
+
+    #[test]
+    fn test_edits() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("checklist.md");
+        fs::write(&path, "- [ ] One\n- [ ] Two\n").unwrap();
+
+        apply_edit(&FileEdit {
+            path: path.clone(),
+            find: Some("- [ ]".to_string()),
+            replace: "- [x]".to_string(),
+            all: false,
+        })
+        .unwrap();
+        apply_edit(&FileEdit {
+            path: path.clone(),
+            find: None,
+            replace: "- [ ] Three\n".to_string(),
+            all: false,
+        })
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "- [x] One\n- [ ] Two\n- [ ] Three\n"
+        );
+        assert!(apply_edit(&FileEdit {
+            path,
+            find: Some("missing".to_string()),
+            replace: String::new(),
+            all: false,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_cassette_replays_tool_exchanges() {
+        let entry = |seq: usize, tool: &str, classification: Classification| CassetteEntry {
+            seq,
+            timestamp: String::new(),
+            instance: None,
+            tool: tool.to_string(),
+            model: None,
+            reasoning_effort: None,
+            prompt: format!("prompt {}", seq),
+            stdout: format!("answer {}", seq),
+            stderr: String::new(),
+            exit_code: None,
+            duration_ms: 0,
+            classification,
+            error: Some("timed out".to_string()).filter(|_| classification.is_error()),
+            event_format: None,
+        };
+        let entries = [
+            entry(1, "gemini", Classification::Timeout),
+            entry(2, "codex", Classification::Ok),
+            entry(3, "gemini", Classification::Ok),
+        ];
+        let path = Path::new("run.jsonl");
+        let tool = ReplayTool::from_cassette("gemini", path, &entries).unwrap();
+        let timeouts = InvokeTimeouts::default();
+        let err = tool.respond("prompt 1", timeouts).unwrap_err();
+        assert_eq!(err.to_string(), "timed out");
+        assert_eq!(Classification::of(Err(&err), false), Classification::Timeout);
+        let output = tool.respond("prompt 3", timeouts).unwrap();
+        assert_eq!(output.stdout, "answer 3");
+        assert_eq!(output.exit_code, None);
+        assert!(tool.respond("prompt 4", timeouts).is_err());
+
+        assert!(ReplayTool::from_cassette("claude", path, &entries).is_err());
+    }
+
+    #[test]
+    fn test_invalid_scripts() {
+        assert!(ReplayTool::parse("replay", Path::new("s.toml"), "").is_err());
+        assert!(ReplayTool::parse("replay", Path::new("s.toml"), "[[rule]]\nprompt = \"(\"\n").is_err());
+        assert!(ReplayTool::parse("replay", Path::new("s.toml"), "[[step]]\nstdot = \"typo\"\n").is_err());
+    }
+}
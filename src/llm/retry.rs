@@ -0,0 +1,218 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Invocation outcomes and retry with backoff.
+//!
+//! Every invocation is classified before the chain decides what to do next.
+//! Transient failures (empty output, a non-zero exit, a timeout or any other
+//! error) are retried on the same tool with exponential backoff and jitter,
+//! up to the tool's retry limit, before falling back. A tool that can't be
+//! started is skipped straight away, and a rate limit squelches the tool.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use super::ToolOutput;
+use crate::constants::{
+    DEFAULT_MAX_RETRIES, DEFAULT_RETRY_DELAY_SECONDS, DEFAULT_RETRY_MAX_DELAY_SECONDS,
+};
+
+/// How an invocation ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Classification {
+    /// Used as the turn's response
+    Ok,
+    /// Squelched; the chain moved on to a fallback
+    RateLimited,
+    /// Exited cleanly but said nothing
+    EmptyOutput,
+    /// Exited with a non-zero status
+    NonZeroExit,
+    /// The CLI could not be started (e.g. not installed)
+    SpawnFailure,
+    /// Killed by the wall-clock or inactivity limit
+    Timeout,
+    /// Any other failure, e.g. an HTTP error
+    Error,
+}
+
+impl Classification {
+    /// Classify an invocation's result. `rate_limited` is whether its output
+    /// matched the tool's rate-limit detection.
+    pub(super) fn of(result: Result<&ToolOutput, &anyhow::Error>, rate_limited: bool) -> Self {
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                return match e.downcast_ref::<InvokeFailure>() {
+                    Some(InvokeFailure::Spawn(_)) => Self::SpawnFailure,
+                    Some(InvokeFailure::TimedOut(_)) => Self::Timeout,
+                    None => Self::Error,
+                };
+            }
+        };
+        if rate_limited {
+            Self::RateLimited
+        } else if output.exit_code.is_some_and(|code| code != 0) {
+            Self::NonZeroExit
+        } else if output.message().trim().is_empty() {
+            Self::EmptyOutput
+        } else {
+            Self::Ok
+        }
+    }
+
+    /// Whether the invocation failed without producing any output
+    pub(super) fn is_error(self) -> bool {
+        matches!(self, Self::SpawnFailure | Self::Timeout | Self::Error)
+    }
+
+    /// Whether trying the same tool again might succeed
+    pub(super) fn is_transient(self) -> bool {
+        matches!(
+            self,
+            Self::EmptyOutput | Self::NonZeroExit | Self::Timeout | Self::Error
+        )
+    }
+
+    /// Why a failed invocation can't be used, for log messages
+    pub(super) fn describe(self, output: &ToolOutput) -> String {
+        match self {
+            Self::Ok => "succeeded".to_string(),
+            Self::RateLimited => "was rate limited".to_string(),
+            Self::EmptyOutput => "produced no output".to_string(),
+            Self::NonZeroExit => format!(
+                "exited with code {}",
+                output.exit_code.map_or("?".to_string(), |code| code.to_string())
+            ),
+            Self::SpawnFailure => "could not be started".to_string(),
+            Self::Timeout => "timed out".to_string(),
+            Self::Error => "failed".to_string(),
+        }
+    }
+}
+
+/// Failures the chain treats differently from other errors. Attached to the
+/// `anyhow::Error` returned by an invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvokeFailure {
+    Spawn(String),
+    TimedOut(String),
+}
+
+impl fmt::Display for InvokeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn(message) | Self::TimedOut(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for InvokeFailure {}
+
+/// How often, and how patiently, a tool is retried after a transient failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries on the same tool before falling back (0 disables)
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each one after
+    pub base_delay: Duration,
+    /// Upper bound on a single delay
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (starting at 1). `jitter` in
+    /// [0, 1) picks a point in the upper half of the backoff window, so
+    /// parallel instances that failed together don't retry in lockstep.
+    pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        backoff.mul_f64(0.5 + 0.5 * jitter.clamp(0.0, 1.0))
+    }
+
+    /// The same number of retries with no delay between them
+    pub fn immediate(self) -> Self {
+        Self {
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            ..self
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_secs(DEFAULT_RETRY_DELAY_SECONDS),
+            max_delay: Duration::from_secs(DEFAULT_RETRY_MAX_DELAY_SECONDS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classify_outputs() {
+        let output = |stdout: &str, exit_code: Option<i32>| ToolOutput {
+            stdout: stdout.to_string(),
+            exit_code,
+            ..Default::default()
+        };
+        let classify = |o: &ToolOutput| Classification::of(Ok(o), false);
+        assert_eq!(classify(&output("done", Some(0))), Classification::Ok);
+        assert_eq!(classify(&output("done", None)), Classification::Ok);
+        assert_eq!(classify(&output(" \n", Some(0))), Classification::EmptyOutput);
+        assert_eq!(classify(&output("", Some(2))), Classification::NonZeroExit);
+        assert_eq!(
+            Classification::of(Ok(&output("", Some(1))), true),
+            Classification::RateLimited
+        );
+    }
+
+    #[test]
+    fn test_classify_errors() {
+        let spawn: anyhow::Error = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .with_context(|| InvokeFailure::Spawn("Failed to spawn gemini".to_string()))
+            .unwrap_err();
+        assert_eq!(Classification::of(Err(&spawn), false), Classification::SpawnFailure);
+        assert!(!Classification::SpawnFailure.is_transient());
+
+        let timeout = anyhow::Error::new(InvokeFailure::TimedOut("timed out".to_string()));
+        assert_eq!(Classification::of(Err(&timeout), false), Classification::Timeout);
+        let other = anyhow::anyhow!("HTTP 500");
+        assert_eq!(Classification::of(Err(&other), false), Classification::Error);
+        assert!(Classification::Error.is_transient());
+    }
+
+    #[test]
+    fn test_backoff_doubles_with_jitter_and_cap() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+        };
+        assert_eq!(policy.delay(1, 0.999_999).as_secs(), 1);
+        assert_eq!(policy.delay(1, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_secs(2));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_secs(6));
+        assert_eq!(policy.delay(4, 0.0), Duration::from_secs(5));
+        assert_eq!(policy.delay(40, 0.0), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-role tool routing.
+//!
+//! Each kind of prompt afkcode sends is a role. By default every role uses
+//! the run's `tools` list and models; a `[roles.<role>]` table gives a role
+//! its own tool list and per-tool models, e.g. a cheap model for stop
+//! confirmations and a strong one for verification:
+//!
+//! ```toml
+//! [roles.confirmation]
+//! tools = "claude"
+//! models = { claude = "haiku" }
+//! ```
+
+use std::fmt;
+
+use super::ReasoningEffort;
+
+/// A kind of prompt, routable to its own tools and models
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Role {
+    /// Controller prompt in controller mode
+    Controller,
+    /// Worker turns
+    Worker,
+    /// Second turn confirming a worker's stop token
+    Confirmation,
+    /// Check that a controller's stop token was deliberate
+    IntentCheck,
+    /// Standing orders audit
+    Audit,
+    /// Verification phase of parallel runs
+    Verifier,
+    /// `generate` and `add-batch`
+    Generate,
+    /// `update`
+    Update,
+}
+
+impl Role {
+    pub const ALL: [Role; 8] = [
+        Role::Controller,
+        Role::Worker,
+        Role::Confirmation,
+        Role::IntentCheck,
+        Role::Audit,
+        Role::Verifier,
+        Role::Generate,
+        Role::Update,
+    ];
+
+    /// Key of the role's `[roles.<name>]` table
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Controller => "controller",
+            Role::Worker => "worker",
+            Role::Confirmation => "confirmation",
+            Role::IntentCheck => "intent_check",
+            Role::Audit => "audit",
+            Role::Verifier => "verifier",
+            Role::Generate => "generate",
+            Role::Update => "update",
+        }
+    }
+
+    /// Effort for the role's prompts on tools that take one unasked (see
+    /// `LlmTool::with_default_reasoning_effort`) when its `[roles.<role>]`
+    /// table doesn't set one. Checking a stop token's intent needs no
+    /// deliberation.
+    pub fn default_reasoning_effort(self) -> Option<ReasoningEffort> {
+        match self {
+            Role::IntentCheck => Some(ReasoningEffort::Minimal),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = s.to_lowercase().replace('-', "_");
+        Role::ALL
+            .into_iter()
+            .find(|role| role.name() == normalized)
+            .ok_or_else(|| {
+                let names: Vec<_> = Role::ALL.iter().map(|role| role.name()).collect();
+                format!("Invalid role: {}. Supported: {}", s, names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_role() {
+        assert_eq!("intent-check".parse(), Ok(Role::IntentCheck));
+        assert_eq!("Verifier".parse(), Ok(Role::Verifier));
+        assert!("reviewer".parse::<Role>().is_err());
+        for role in Role::ALL {
+            assert_eq!(role.name().parse(), Ok(role));
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate-limit squelch state shared between afkcode invocations.
+//!
+//! Expiries are kept as unix timestamps in a small JSON file (by default
+//! `.afkcode/ratelimits.json`) so a restart, or a separate `generate` or
+//! `update` run, doesn't immediately retry a tool whose quota is exhausted.
+//! Every access takes a file lock, so concurrent afkcode processes see each
+//! other's squelches.
+//!
+//! Within one process the registry is a cheaply cloned handle: parallel
+//! instances share one, so a limit hit by any instance moves all of them to
+//! the fallback tool, and they return to the preferred tool together.
+
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tool name -> unix time (seconds) at which its squelch expires
+type Expiries = BTreeMap<String, u64>;
+
+/// When each rate-limited tool becomes usable again, optionally persisted.
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct SquelchRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    expiries: Expiries,
+    path: Option<PathBuf>,
+    /// When each tool was last squelched by this process
+    limited_at: BTreeMap<String, SystemTime>,
+}
+
+impl SquelchRegistry {
+    /// Load the registry from `path`, or start empty and in-memory only when
+    /// `path` is `None`. An unreadable state file is reported and ignored.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let registry = Self {
+            inner: Arc::new(Mutex::new(Registry {
+                expiries: Expiries::new(),
+                path,
+                limited_at: BTreeMap::new(),
+            })),
+        };
+        registry.refresh();
+        registry
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        // The state stays consistent even if a holder panicked
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Re-read the state file to pick up squelches recorded by other processes
+    pub fn refresh(&self) {
+        let mut registry = self.lock();
+        if let Some(path) = registry.path.clone() {
+            match read_state(&path) {
+                Ok(expiries) => {
+                    // Keep our own entries if the file lost them (e.g. a failed write)
+                    for (tool, until) in expiries {
+                        let entry = registry.expiries.entry(tool).or_insert(until);
+                        *entry = (*entry).max(until);
+                    }
+                }
+                Err(e) => eprintln!("Warning: Failed to read rate-limit state: {:#}", e),
+            }
+        }
+        let now = unix_now();
+        registry.expiries.retain(|_, until| *until > now);
+    }
+
+    /// Squelch `tool` until `until` and persist the change
+    pub fn squelch(&self, tool: &str, until: SystemTime) {
+        let until = to_unix(until);
+        let mut registry = self.lock();
+        registry.expiries.insert(tool.to_string(), until);
+        registry.limited_at.insert(tool.to_string(), SystemTime::now());
+        if let Some(path) = registry.path.as_deref()
+            && let Err(e) = update_state(path, |expiries| {
+                expiries.insert(tool.to_string(), until);
+            })
+        {
+            eprintln!("Warning: Failed to save rate-limit state: {:#}", e);
+        }
+    }
+
+    /// When `tool`'s squelch expires, if it is currently squelched
+    pub fn squelched_until(&self, tool: &str) -> Option<SystemTime> {
+        let until = *self.lock().expiries.get(tool)?;
+        (until > unix_now()).then(|| UNIX_EPOCH + Duration::from_secs(until))
+    }
+
+    pub fn is_squelched(&self, tool: &str) -> bool {
+        self.squelched_until(tool).is_some()
+    }
+
+    /// When `tool` was last squelched in this run, if ever
+    pub fn last_rate_limited(&self, tool: &str) -> Option<SystemTime> {
+        self.lock().limited_at.get(tool).copied()
+    }
+}
+
+fn unix_now() -> u64 {
+    to_unix(SystemTime::now())
+}
+
+/// Round up so a squelch never ends early
+fn to_unix(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() + u64::from(since_epoch.subsec_nanos() > 0)
+}
+
+fn read_state(path: &Path) -> Result<Expiries> {
+    if !path.exists() {
+        return Ok(Expiries::new());
+    }
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    file.lock_shared()
+        .with_context(|| format!("Failed to lock {}", path.display()))?;
+    let result = read_locked(&mut file, path);
+    let _ = file.unlock();
+    result
+}
+
+/// Read-modify-write the state file under an exclusive lock, dropping
+/// expired entries along the way.
+fn update_state(path: &Path, change: impl FnOnce(&mut Expiries)) -> Result<()> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+        && !dir.exists()
+    {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        // Keep afkcode's state out of the agents' `git add -A`
+        let _ = fs::write(dir.join(".gitignore"), "*\n");
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.lock_exclusive()
+        .with_context(|| format!("Failed to lock {}", path.display()))?;
+
+    let result = (|| {
+        let mut expiries = read_locked(&mut file, path).unwrap_or_default();
+        change(&mut expiries);
+        let now = unix_now();
+        expiries.retain(|_, until| *until > now);
+
+        let json = serde_json::to_string_pretty(&expiries)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(json.as_bytes())?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        Ok(())
+    })();
+    let _ = file.unlock();
+    result
+}
+
+fn read_locked(file: &mut File, path: &Path) -> Result<Expiries> {
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if content.trim().is_empty() {
+        return Ok(Expiries::new());
+    }
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_squelch_persists_across_loads() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(".afkcode").join("ratelimits.json");
+
+        let registry = SquelchRegistry::load(Some(path.clone()));
+        registry.squelch("gemini", SystemTime::now() + Duration::from_secs(600));
+        registry.squelch("codex", SystemTime::now() - Duration::from_secs(1));
+        assert!(registry.is_squelched("gemini"));
+        assert!(!registry.is_squelched("codex"));
+
+        let reloaded = SquelchRegistry::load(Some(path.clone()));
+        assert!(reloaded.is_squelched("gemini"));
+        assert!(!reloaded.is_squelched("claude"));
+        assert!(dir.path().join(".afkcode").join(".gitignore").exists());
+    }
+
+    #[test]
+    fn test_refresh_sees_other_writers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ratelimits.json");
+
+        let first = SquelchRegistry::load(Some(path.clone()));
+        let second = SquelchRegistry::load(Some(path.clone()));
+        second.squelch("claude", SystemTime::now() + Duration::from_secs(600));
+
+        assert!(!first.is_squelched("claude"));
+        first.refresh();
+        assert!(first.is_squelched("claude"));
+    }
+
+    #[test]
+    fn test_clones_share_state_across_threads() {
+        let registry = SquelchRegistry::load(None);
+        let handle = registry.clone();
+        std::thread::spawn(move || {
+            handle.squelch("gemini", SystemTime::now() + Duration::from_secs(60));
+        })
+        .join()
+        .unwrap();
+        assert!(registry.is_squelched("gemini"));
+    }
+
+    #[test]
+    fn test_corrupt_state_is_ignored() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ratelimits.json");
+        fs::write(&path, "not json").unwrap();
+
+        let registry = SquelchRegistry::load(Some(path.clone()));
+        assert!(!registry.is_squelched("gemini"));
+        registry.squelch("gemini", SystemTime::now() + Duration::from_secs(60));
+        assert!(SquelchRegistry::load(Some(path)).is_squelched("gemini"));
+    }
+}
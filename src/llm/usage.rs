@@ -0,0 +1,316 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Token and cost accounting.
+//!
+//! Usage is read from whatever the tool reports: JSON result and turn events
+//! (Claude `--output-format json`, Codex `exec --json`), the `usage` field
+//! of HTTP responses, or Codex's plain-text "tokens used" footer. A
+//! [`UsageLedger`] aggregates it per tool and per instance for the whole run
+//! and enforces the optional cost and token budgets.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Codex's text-mode footer: "tokens used: 12,345" or "tokens used\n12,345"
+static TOKENS_USED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\btokens used:?\s*([\d,]+)").unwrap());
+
+/// Tokens and cost of one or more invocations
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// At least `input_tokens + output_tokens`; some tools only report a total
+    pub total_tokens: u64,
+    /// Cost in USD, when the tool reported it or a price is configured
+    pub cost_usd: Option<f64>,
+}
+
+impl Usage {
+    fn new(input_tokens: u64, output_tokens: u64, total_tokens: u64) -> Self {
+        Self {
+            input_tokens,
+            output_tokens,
+            total_tokens: total_tokens.max(input_tokens + output_tokens),
+            cost_usd: None,
+        }
+    }
+
+    /// Fill in a missing cost from per-million-token prices
+    pub fn with_estimated_cost(mut self, pricing: Option<TokenPricing>) -> Self {
+        if self.cost_usd.is_none()
+            && let Some(pricing) = pricing
+        {
+            self.cost_usd = Some(
+                (self.input_tokens as f64 * pricing.input_per_mtok
+                    + self.output_tokens as f64 * pricing.output_per_mtok)
+                    / 1_000_000.0,
+            );
+        }
+        self
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost_usd = match (self.cost_usd, other.cost_usd) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} tokens (in {}, out {})",
+            self.total_tokens, self.input_tokens, self.output_tokens
+        )?;
+        if let Some(cost) = self.cost_usd {
+            write!(f, ", ${:.4}", cost)?;
+        }
+        Ok(())
+    }
+}
+
+/// USD per million tokens, from `input_price` / `output_price` in a
+/// `[tool.<name>]` table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenPricing {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+/// Extract usage from a CLI tool's output. A final `result` event wins;
+/// otherwise top-level `usage` objects (one per turn) are summed.
+pub(super) fn parse_usage(stdout: &str, stderr: &str) -> Option<Usage> {
+    let mut summed: Option<Usage> = None;
+    for line in stdout.lines().chain(stderr.lines()) {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            continue;
+        }
+        let Ok(event) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let Some(usage) = usage_from_value(&event) else {
+            continue;
+        };
+        if event.get("type").and_then(Value::as_str) == Some("result") {
+            return Some(usage);
+        }
+        *summed.get_or_insert_with(Usage::default) += usage;
+    }
+
+    summed.or_else(|| {
+        let caps = TOKENS_USED.captures_iter(stderr).chain(TOKENS_USED.captures_iter(stdout)).last()?;
+        let total = caps[1].replace(',', "").parse().ok()?;
+        Some(Usage::new(0, 0, total))
+    })
+}
+
+/// Read a top-level `usage` object (Anthropic, OpenAI and Codex field names)
+/// plus any reported cost
+pub(super) fn usage_from_value(value: &Value) -> Option<Usage> {
+    let cost = ["total_cost_usd", "cost_usd"]
+        .iter()
+        .find_map(|key| value.get(*key).and_then(Value::as_f64));
+    let usage = value.get("usage").filter(|u| u.is_object());
+    if usage.is_none() && cost.is_none() {
+        return None;
+    }
+
+    let field = |keys: &[&str]| {
+        usage
+            .map(|usage| {
+                keys.iter()
+                    .filter_map(|key| usage.get(*key).and_then(Value::as_u64))
+                    .sum::<u64>()
+            })
+            .unwrap_or_default()
+    };
+    let input = field(&[
+        "input_tokens",
+        "prompt_tokens",
+        "cache_creation_input_tokens",
+        "cache_read_input_tokens",
+    ]);
+    let output = field(&["output_tokens", "completion_tokens"]);
+    let total = field(&["total_tokens"]);
+
+    Some(Usage {
+        cost_usd: cost,
+        ..Usage::new(input, output, total)
+    })
+}
+
+/// Optional limits for a whole run
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageBudget {
+    /// Maximum spend in USD (only reported or estimated costs count)
+    pub max_cost: Option<f64>,
+    pub max_tokens: Option<u64>,
+}
+
+/// Run-wide usage totals. Clones share the same ledger, so every parallel
+/// instance counts against one budget.
+#[derive(Debug, Clone, Default)]
+pub struct UsageLedger {
+    inner: Arc<Mutex<Ledger>>,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    budget: UsageBudget,
+    total: Usage,
+    invocations: usize,
+    by_tool: BTreeMap<String, Usage>,
+    by_instance: BTreeMap<usize, Usage>,
+}
+
+impl UsageLedger {
+    pub fn new(budget: UsageBudget) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Ledger {
+                budget,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ledger> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(&self, instance: Option<usize>, tool: &str, usage: Usage) {
+        let mut ledger = self.lock();
+        ledger.total += usage;
+        ledger.invocations += 1;
+        *ledger.by_tool.entry(tool.to_string()).or_default() += usage;
+        if let Some(instance) = instance {
+            *ledger.by_instance.entry(instance).or_default() += usage;
+        }
+    }
+
+    /// Describe which budget the run has used up, if any
+    pub fn budget_exceeded(&self) -> Option<String> {
+        let ledger = self.lock();
+        let budget = ledger.budget;
+        if let (Some(max), Some(spent)) = (budget.max_cost, ledger.total.cost_usd)
+            && spent >= max
+        {
+            return Some(format!("cost ${:.4} reached max_cost ${:.2}", spent, max));
+        }
+        if let Some(max) = budget.max_tokens
+            && ledger.total.total_tokens >= max
+        {
+            return Some(format!(
+                "{} tokens reached max_tokens {}",
+                ledger.total.total_tokens, max
+            ));
+        }
+        None
+    }
+
+    /// Multi-line totals for the end-of-run report
+    pub fn summary(&self) -> String {
+        let ledger = self.lock();
+        if ledger.invocations == 0 {
+            return "Usage: no usage reported by the tools used".to_string();
+        }
+        let mut lines = vec![format!(
+            "Usage: {} over {} invocation(s)",
+            ledger.total, ledger.invocations
+        )];
+        for (tool, usage) in &ledger.by_tool {
+            lines.push(format!("  tool {}: {}", tool, usage));
+        }
+        for (instance, usage) in &ledger.by_instance {
+            lines.push(format!("  instance {}: {}", instance, usage));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claude_result_event() {
+        let stdout = r#"{"type":"assistant","message":{"usage":{"input_tokens":5,"output_tokens":5}}}
+{"type":"result","total_cost_usd":0.25,"usage":{"input_tokens":100,"cache_read_input_tokens":900,"output_tokens":50}}"#;
+        let usage = parse_usage(stdout, "").unwrap();
+        assert_eq!(usage.input_tokens, 1000);
+        assert_eq!(usage.output_tokens, 50);
+        assert_eq!(usage.total_tokens, 1050);
+        assert_eq!(usage.cost_usd, Some(0.25));
+    }
+
+    #[test]
+    fn test_codex_turn_events_and_text_footer() {
+        let events = r#"{"type":"turn.completed","usage":{"input_tokens":10,"cached_input_tokens":4,"output_tokens":3}}
+{"type":"turn.completed","usage":{"input_tokens":20,"output_tokens":7}}"#;
+        let usage = parse_usage(events, "").unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (30, 10));
+
+        let usage = parse_usage("done", "[2025-06-01] tokens used: 12,345\n").unwrap();
+        assert_eq!(usage.total_tokens, 12345);
+        assert_eq!(parse_usage("no usage here", ""), None);
+    }
+
+    #[test]
+    fn test_estimated_cost() {
+        let pricing = TokenPricing {
+            input_per_mtok: 3.0,
+            output_per_mtok: 15.0,
+        };
+        let usage = Usage::new(1_000_000, 100_000, 0).with_estimated_cost(Some(pricing));
+        assert_eq!(usage.cost_usd, Some(4.5));
+    }
+
+    #[test]
+    fn test_ledger_budgets() {
+        let ledger = UsageLedger::new(UsageBudget {
+            max_cost: Some(1.0),
+            max_tokens: Some(1000),
+        });
+        let shared = ledger.clone();
+        shared.record(Some(0), "claude", Usage::new(400, 100, 0));
+        assert_eq!(ledger.budget_exceeded(), None);
+
+        shared.record(Some(1), "codex", Usage::new(400, 100, 0));
+        assert!(ledger.budget_exceeded().unwrap().contains("max_tokens"));
+
+        let ledger = UsageLedger::new(UsageBudget {
+            max_cost: Some(1.0),
+            max_tokens: None,
+        });
+        let mut expensive = Usage::new(10, 10, 0);
+        expensive.cost_usd = Some(1.5);
+        ledger.record(None, "claude", expensive);
+        assert!(ledger.budget_exceeded().unwrap().contains("max_cost"));
+        assert!(ledger.summary().contains("tool claude"));
+    }
+}
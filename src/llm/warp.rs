@@ -0,0 +1,325 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Warp Agent API backend.
+//!
+//! A prompt becomes a remote agent task that is polled until it finishes.
+//! The task's final output is returned as stdout so stop tokens work as with
+//! the CLI tools. Polling stops at the tool's wall-clock limit, or after
+//! [`DEFAULT_WARP_MAX_SECONDS`] without one, and the remote task is cancelled
+//! when afkcode is asked to shut down.
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+use super::retry::InvokeFailure;
+use super::{LlmTool, ToolOutput};
+use crate::constants::{DEFAULT_WARP_MAX_SECONDS, WARP_AGENT_API_BASE};
+
+/// Consecutive failed polls (network errors, 5xx) tolerated before giving up
+const MAX_POLL_FAILURES: usize = 5;
+
+/// Warp Agent API request/response types
+#[derive(Debug, Serialize)]
+pub struct RunAgentRequest {
+    prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<AmbientAgentConfig>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AmbientAgentConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunAgentResponse {
+    task_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TaskResponse {
+    state: String,
+    #[serde(default)]
+    session_link: Option<String>,
+    /// Everything else; where the final output lives varies between API versions
+    #[serde(flatten)]
+    rest: serde_json::Map<String, Value>,
+}
+
+impl TaskResponse {
+    /// The agent's final output: an `output`/`result`/`summary` string or
+    /// the last message of a transcript
+    fn final_output(&self) -> Option<String> {
+        let non_empty = |s: &str| (!s.trim().is_empty()).then(|| s.to_string());
+        for key in ["output", "result", "final_output", "summary"] {
+            if let Some(text) = self.rest.get(key).and_then(Value::as_str).and_then(non_empty) {
+                return Some(text);
+            }
+        }
+        for key in ["transcript", "messages"] {
+            let last = self
+                .rest
+                .get(key)
+                .and_then(Value::as_array)
+                .and_then(|messages| messages.iter().rev().find_map(message_text));
+            if last.is_some() {
+                return last;
+            }
+        }
+        None
+    }
+
+    fn status_message(&self) -> Option<String> {
+        let status = self.rest.get("status_message")?;
+        status
+            .as_str()
+            .or_else(|| status.get("message").and_then(Value::as_str))
+            .filter(|s| !s.trim().is_empty())
+            .map(str::to_string)
+    }
+}
+
+/// Text of a transcript entry, skipping the user's own prompt
+fn message_text(message: &Value) -> Option<String> {
+    if let Some(text) = message.as_str() {
+        return Some(text.to_string());
+    }
+    let role = message.get("role").and_then(Value::as_str);
+    if matches!(role, Some("user") | Some("system")) {
+        return None;
+    }
+    ["content", "text", "message"]
+        .iter()
+        .find_map(|key| message.get(*key).and_then(Value::as_str))
+        .filter(|s| !s.trim().is_empty())
+        .map(str::to_string)
+}
+
+/// Rate-limit and error output for a non-2xx response, in the shape the
+/// shared rate-limit detection understands
+fn http_error_output(response: Response) -> ToolOutput {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(|secs| format!(" (retry after {}s)", secs))
+        .unwrap_or_default();
+    let body = response.text().unwrap_or_default();
+    ToolOutput::http(
+        String::new(),
+        format!("HTTP {}{}: {}", status, retry_after, body),
+    )
+}
+
+impl LlmTool {
+    /// Invoke Warp Agent via HTTP API
+    pub(super) fn invoke_warp_agent(&self, prompt: &str) -> Result<ToolOutput> {
+        let api_key = self.api_key.as_ref()
+            .ok_or_else(|| anyhow!("Warp Agent API key not configured. Set WARP_API_KEY environment variable or warp_api_key in config"))?;
+        let base = self
+            .api_base
+            .as_deref()
+            .unwrap_or(WARP_AGENT_API_BASE)
+            .trim_end_matches('/');
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(300))
+            .build()?;
+
+        // Create agent task
+        let request = RunAgentRequest {
+            prompt: prompt.to_string(),
+            title: Some("afkcode task".to_string()),
+            config: Some(AmbientAgentConfig {
+                model_id: self.model.clone(),
+            }),
+        };
+
+        let create_url = format!("{}/agent/run", base);
+        let response = client
+            .post(&create_url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(&request)
+            .send()
+            .context("Failed to create Warp Agent task")?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Ok(http_error_output(response));
+        }
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().unwrap_or_default();
+            anyhow::bail!("Warp Agent API returned error {}: {}", status, body);
+        }
+
+        let run_response: RunAgentResponse = response.json()
+            .context("Failed to parse Warp Agent response")?;
+        let task_id = run_response.task_id;
+
+        // Poll for completion
+        let task_url = format!("{}/agent/tasks/{}", base, task_id);
+        let started = Instant::now();
+        let limit = self
+            .timeouts
+            .wall_clock
+            .unwrap_or(Duration::from_secs(DEFAULT_WARP_MAX_SECONDS));
+        let mut failures = 0;
+
+        loop {
+            if self.wait_for_poll() {
+                self.cancel_warp_task(&client, &task_url, api_key);
+                return Ok(ToolOutput::http(
+                    String::new(),
+                    format!("Warp Agent task {} cancelled on shutdown", task_id),
+                ));
+            }
+
+            if started.elapsed() >= limit {
+                self.cancel_warp_task(&client, &task_url, api_key);
+                return Err(InvokeFailure::TimedOut(format!(
+                    "Warp Agent task {} timed out after {} seconds (wall-clock limit)",
+                    task_id,
+                    limit.as_secs()
+                ))
+                .into());
+            }
+
+            let task_response = match client
+                .get(&task_url)
+                .header("Authorization", format!("Bearer {}", api_key))
+                .send()
+            {
+                Ok(response) => response,
+                Err(e) if failures < MAX_POLL_FAILURES => {
+                    failures += 1;
+                    eprintln!("Warning: Failed to poll Warp Agent task: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e).context("Failed to get Warp Agent task status"),
+            };
+
+            let status = task_response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                return Ok(http_error_output(task_response));
+            }
+            if status.is_server_error() && failures < MAX_POLL_FAILURES {
+                failures += 1;
+                eprintln!("Warning: Warp Agent task poll returned {}", status);
+                continue;
+            }
+            if !status.is_success() {
+                let body = task_response.text().unwrap_or_default();
+                anyhow::bail!("Warp Agent task poll returned error {}: {}", status, body);
+            }
+            failures = 0;
+
+            let task: TaskResponse = task_response.json()
+                .context("Failed to parse task status")?;
+
+            match task.state.as_str() {
+                "SUCCEEDED" => {
+                    let link = task
+                        .session_link
+                        .as_deref()
+                        .map(|link| format!("Warp Agent session: {}", link))
+                        .unwrap_or_default();
+                    if let Some(output) = task.final_output() {
+                        return Ok(ToolOutput::http(output, link));
+                    }
+                    // A summary at best, so a stop token is unlikely to be in it
+                    if let Some(status) = task.status_message() {
+                        eprintln!(
+                            "Warning: Warp Agent task {} returned no output or transcript; using its status message",
+                            task_id
+                        );
+                        return Ok(ToolOutput::http(status, link));
+                    }
+                    return Ok(ToolOutput::http(
+                        String::new(),
+                        format!("Warp Agent task succeeded without output. {}", link),
+                    ));
+                }
+                "FAILED" | "ERROR" | "CANCELLED" | "CANCELED" => {
+                    let reason = task.status_message().unwrap_or_else(|| task.state.clone());
+                    return Ok(ToolOutput::http(
+                        String::new(),
+                        format!("Warp Agent task failed: {}", reason),
+                    ));
+                }
+                // QUEUED, INPROGRESS and states we don't know yet
+                _ => continue,
+            }
+        }
+    }
+
+    /// Sleep for one poll interval. Returns true if shutdown was requested.
+    fn wait_for_poll(&self) -> bool {
+        self.sleep_unless_shutdown(self.poll_interval)
+    }
+
+    /// Ask Warp to stop a task we are abandoning; failures are only reported
+    fn cancel_warp_task(&self, client: &Client, task_url: &str, api_key: &str) {
+        let result = client
+            .post(format!("{}/cancel", task_url))
+            .header("Authorization", format!("Bearer {}", api_key))
+            .timeout(Duration::from_secs(10))
+            .send();
+        match result {
+            Ok(response) if response.status().is_success() => {
+                eprintln!("Cancelled Warp Agent task");
+            }
+            Ok(response) => {
+                eprintln!("Warning: Failed to cancel Warp Agent task: HTTP {}", response.status());
+            }
+            Err(e) => eprintln!("Warning: Failed to cancel Warp Agent task: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(json: &str) -> TaskResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_final_output_sources() {
+        let direct = task(r#"{"state":"SUCCEEDED","output":"Done.\n__ALL_TASKS_COMPLETE__"}"#);
+        assert_eq!(direct.final_output().as_deref(), Some("Done.\n__ALL_TASKS_COMPLETE__"));
+
+        let transcript = task(
+            r#"{"state":"SUCCEEDED","messages":[{"role":"user","content":"do it"},{"role":"assistant","content":"All fixed."},{"role":"user","content":""}]}"#,
+        );
+        assert_eq!(transcript.final_output().as_deref(), Some("All fixed."));
+
+        let status = task(r#"{"state":"SUCCEEDED","status_message":{"message":"Opened PR #4"}}"#);
+        assert_eq!(status.final_output(), None);
+        assert_eq!(status.status_message().as_deref(), Some("Opened PR #4"));
+
+        let empty = task(r#"{"state":"SUCCEEDED","session_link":"https://app.warp.dev/session/1"}"#);
+        assert_eq!(empty.final_output(), None);
+    }
+}
@@ -34,13 +34,6 @@ impl Logger {
         })
     }
 
-    pub fn log(&mut self, message: &str) -> Result<()> {
-        self.writer.write_all(message.as_bytes())?;
-        // Flush periodically to ensure responsive logging
-        self.writer.flush()?;
-        Ok(())
-    }
-
     pub fn logln(&mut self, message: &str) -> Result<()> {
         self.writer.write_all(message.as_bytes())?;
         self.writer.write_all(b"\n")?;
@@ -19,53 +19,134 @@ mod commands;
 mod config;
 mod constants;
 mod coordinator;
+mod doctor;
+mod gate;
 mod gimme;
+mod git;
+mod limits;
 mod llm;
 mod logger;
 mod parallel;
 mod prompts;
 mod runner;
+mod shutdown;
+mod stall;
 mod verifier;
 mod wakelock;
 
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 use cli::{Cli, Commands, RunMode};
 use commands::*;
 use config::Config;
-use constants::{DEFAULT_COMPLETION_TOKEN, DEFAULT_CONTROLLER_PROMPT};
-use llm::ModelConfig;
+use constants::{
+    DEFAULT_COMPLETION_TOKEN, DEFAULT_CONTROLLER_PROMPT, DEFAULT_GATE_TIMEOUT_SECONDS,
+    DEFAULT_IDLE_TIMEOUT_SECONDS, DEFAULT_MAX_RETRIES, DEFAULT_RETRY_DELAY_SECONDS,
+    DEFAULT_SESSION_MAX_CONTEXT_TOKENS, DEFAULT_SESSION_MAX_TURNS, DEFAULT_TIMEOUT_SECONDS,
+    RATE_LIMIT_STATE_FILE,
+};
+use gate::Gate;
+use limits::{ActiveHours, RunLimits};
+use llm::{
+    Cassette, CassetteRecorder, InvokeTimeouts, ModelConfig, RetryPolicy, Role, ToolStrategy,
+    UsageBudget,
+};
+use runner::SessionPolicy;
+use stall::{StallPolicy, DEFAULT_STALL_LADDER};
 
-fn main() -> Result<()> {
-    let shutdown_flag = Arc::new(AtomicBool::new(false));
-    let last_sigint = Arc::new(Mutex::new(None::<Instant>));
+/// Merge per-tool model settings from the CLI and config file.
+/// CLI flags take precedence; HTTP backend settings fall back to environment
+/// variables (`WARP_API_KEY`, `OPENAI_API_KEY`, `OPENAI_BASE_URL`).
+fn build_model_config(
+    config: &Config,
+    gemini_model: Option<String>,
+    claude_model: Option<String>,
+    codex_model: Option<String>,
+) -> Result<ModelConfig> {
+    let warp_api_key = config
+        .warp_api_key
+        .clone()
+        .or_else(|| std::env::var("WARP_API_KEY").ok());
+    let warp_api_base = config
+        .warp_api_base
+        .clone()
+        .or_else(|| std::env::var("WARP_API_BASE").ok());
+    let openai_api_key = config
+        .openai_api_key
+        .clone()
+        .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+    let openai_base_url = config
+        .openai_base_url
+        .clone()
+        .or_else(|| std::env::var("OPENAI_BASE_URL").ok());
 
-    let shutdown_flag_clone = shutdown_flag.clone();
-    let last_sigint_clone = last_sigint.clone();
+    let roles = config
+        .roles
+        .iter()
+        .map(|(name, settings)| {
+            let role = name.parse::<Role>().map_err(|err| anyhow!(err))?;
+            Ok((role, settings.clone()))
+        })
+        .collect::<Result<_>>()?;
 
-    // Handle Ctrl+C gracefully
-    ctrlc::set_handler(move || {
-        let now = Instant::now();
-        let mut last = last_sigint_clone.lock().unwrap();
-        
-        if let Some(t) = *last {
-            if now.duration_since(t) < Duration::from_secs(5) {
-                println!("\nInterrupted again. Force exiting.");
-                std::process::exit(0);
-            }
-        }
-        
-        *last = Some(now);
-        shutdown_flag_clone.store(true, Ordering::SeqCst);
-        println!("\nInterrupted. Finishing current turn... (Press Ctrl+C again within 5s to force exit)");
+    Ok(ModelConfig {
+        gemini_model: gemini_model.or(config.gemini_model.clone()),
+        claude_model: claude_model.or(config.claude_model.clone()),
+        codex_model: codex_model.or(config.codex_model.clone()),
+        aider_model: config.aider_model.clone(),
+        opencode_model: config.opencode_model.clone(),
+        cursor_model: config.cursor_model.clone(),
+        warp_model: config.warp_model.clone(),
+        warp_api_key,
+        warp_api_base,
+        warp_poll_interval: config.warp_poll_seconds.map(Duration::from_secs),
+        openai_model: config.openai_model.clone(),
+        openai_api_key,
+        openai_base_url,
+        tool_settings: config.tool.clone(),
+        timeouts: InvokeTimeouts::from_secs(
+            config.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+            config
+                .idle_timeout_seconds
+                .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECONDS),
+        ),
+        retry: RetryPolicy {
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            base_delay: Duration::from_secs(
+                config
+                    .retry_delay_seconds
+                    .unwrap_or(DEFAULT_RETRY_DELAY_SECONDS),
+            ),
+            ..RetryPolicy::default()
+        },
+        rate_limit_state: Some(PathBuf::from(RATE_LIMIT_STATE_FILE)),
+        structured_output: config.structured_output.unwrap_or(false),
+        resume_sessions: false,
+        shutdown_flag: None,
+        recorder: None,
+        replay_cassette: None,
+        tool_strategy: ToolStrategy::default(),
+        spread_instances: config.spread_instances.unwrap_or(false),
+        roles,
     })
-    .context("Error setting Ctrl-C handler")?;
+}
+
+fn main() -> Result<()> {
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    // First interrupt finishes the turn, the second stops running tools
+    shutdown::install(shutdown_flag.clone())?;
 
+    let result = run(shutdown_flag);
+    shutdown::wait_for_forced_exit();
+    result
+}
+
+fn run(shutdown_flag: Arc<AtomicBool>) -> Result<()> {
     let cli = Cli::parse();
 
     // Load config from specified path or default afkcode.toml
@@ -83,6 +164,8 @@ fn main() -> Result<()> {
             worker_prompt,
             completion_token,
             sleep_seconds,
+            timeout_seconds,
+            idle_timeout_seconds,
             mode,
             run_audit,
             audit_orders_path,
@@ -102,6 +185,23 @@ fn main() -> Result<()> {
             verifier_tools,
             spiral,
             max_spirals,
+            max_cost,
+            max_tokens,
+            max_iterations,
+            deadline,
+            active_hours,
+            stall_threshold,
+            stall_ladder,
+            auto_commit,
+            gate_command,
+            gate_timeout_seconds,
+            structured_output,
+            resume_sessions,
+            session_max_turns,
+            record_cassette,
+            replay_cassette,
+            tool_strategy,
+            spread_instances,
         } => {
             // Validate that exactly one of checklist or checklist_dir is provided
             let (checklist_path, multi_checklist_mode) = match (&checklist, &checklist_dir) {
@@ -165,7 +265,7 @@ fn main() -> Result<()> {
             } else {
                 config.commit_audit.unwrap_or(true)
             };
-            let merged_tools = config.merge_with_cli(
+            let mut merged_tools = config.merge_with_cli(
                 tools.clone(),
                 config.tools.clone(),
                 "gemini,codex,claude".to_string(),
@@ -177,21 +277,105 @@ fn main() -> Result<()> {
             );
 
             // Merge model configurations (CLI takes precedence over config file)
-            // For Warp Agent API key, try: CLI flag -> env var -> config file
-            let warp_api_key = config.warp_api_key.clone()
-                .or_else(|| std::env::var("WARP_API_KEY").ok());
-            
-            let model_config = ModelConfig {
-                gemini_model: gemini_model.or(config.gemini_model.clone()),
-                claude_model: claude_model.or(config.claude_model.clone()),
-                codex_model: codex_model.or(config.codex_model.clone()),
-                warp_model: config.warp_model.clone(),
-                warp_api_key,
+            let mut model_config =
+                build_model_config(&config, gemini_model, claude_model, codex_model)?;
+            model_config.timeouts = InvokeTimeouts::from_secs(
+                config.merge_with_cli(
+                    timeout_seconds,
+                    config.timeout_seconds,
+                    DEFAULT_TIMEOUT_SECONDS,
+                ),
+                config.merge_with_cli(
+                    idle_timeout_seconds,
+                    config.idle_timeout_seconds,
+                    DEFAULT_IDLE_TIMEOUT_SECONDS,
+                ),
+            );
+            let session_policy = SessionPolicy {
+                enabled: resume_sessions || config.resume_sessions.unwrap_or(false),
+                max_turns: config.merge_with_cli(
+                    session_max_turns,
+                    config.session_max_turns,
+                    DEFAULT_SESSION_MAX_TURNS,
+                ),
+                max_context_tokens: config
+                    .session_max_context_tokens
+                    .unwrap_or(DEFAULT_SESSION_MAX_CONTEXT_TOKENS),
+            };
+            model_config.shutdown_flag = Some(shutdown_flag.clone());
+            model_config.tool_strategy = if tool_strategy == ToolStrategy::Priority
+                && let Some(strategy) = &config.tool_strategy
+            {
+                strategy.parse::<ToolStrategy>().map_err(|err| anyhow!(err))?
+            } else {
+                tool_strategy
+            };
+            model_config.spread_instances |= spread_instances;
+            model_config.structured_output |= structured_output;
+            model_config.resume_sessions = session_policy.enabled;
+            if session_policy.enabled && !model_config.structured_output {
+                println!(
+                    "Resuming sessions: claude, codex and gemini run in structured output mode, \
+                     since only their event streams report session ids"
+                );
+            }
+
+            let run_limits = RunLimits::new(
+                max_iterations.or(config.max_iterations),
+                deadline
+                    .or(config.deadline.clone())
+                    .map(|value| limits::parse_deadline(&value, chrono::Local::now()))
+                    .transpose()?,
+                active_hours
+                    .or(config.active_hours.clone())
+                    .map(|value| value.parse::<ActiveHours>())
+                    .transpose()
+                    .context("Invalid active hours")?,
+            );
+
+            let stall_policy = StallPolicy {
+                threshold: config.merge_with_cli(stall_threshold, config.stall_threshold, 0),
+                ladder: stall_ladder
+                    .or(config.stall_ladder.clone())
+                    .unwrap_or_else(|| DEFAULT_STALL_LADDER.to_vec()),
             };
 
+            let gate = gate_command.or(config.gate_command.clone()).map(|command| {
+                Gate::new(
+                    command,
+                    config.merge_with_cli(
+                        gate_timeout_seconds,
+                        config.gate_timeout_seconds,
+                        DEFAULT_GATE_TIMEOUT_SECONDS,
+                    ),
+                )
+            });
+
             // Merge parallel/gimme settings
             let merged_num_instances =
                 config.merge_with_cli(num_instances, config.num_instances, 1usize);
+
+            if let Some(path) = record_cassette.or(config.record_cassette.clone()) {
+                model_config.recorder = Some(CassetteRecorder::create(&path)?);
+                println!("Recording exchanges to {}", path.display());
+            }
+            if let Some(path) = replay_cassette {
+                // Parallel instances interleave nondeterministically
+                if merged_num_instances > 1 {
+                    return Err(anyhow!("--replay-cassette only supports single-instance runs"));
+                }
+                let cassette = Cassette::load(&path)?;
+                merged_tools = cassette.tool_names().join(",");
+                println!(
+                    "Replaying {} exchange(s) from {} with tools: {}",
+                    cassette.entries.len(),
+                    path.display(),
+                    merged_tools
+                );
+                model_config.replay_cassette = Some(Arc::new(cassette));
+                // Keep replayed rate limits away from the real tools' squelches
+                model_config.rate_limit_state = None;
+            }
             let merged_warmup_delay =
                 config.merge_with_cli(warmup_delay, config.warmup_delay, 30u64);
             let merged_gimme_enabled = if no_gimme {
@@ -232,32 +416,41 @@ fn main() -> Result<()> {
                 eprintln!("Warning: --verify flag only works with --checklist-dir (multi-checklist mode)");
             }
 
-            cmd_run(
-                checklist_path,
-                merged_controller_prompt,
-                merged_worker_prompt,
-                merged_completion_token,
-                merged_sleep_seconds,
-                merged_mode,
-                merged_skip_audit,
-                merged_audit_orders_path,
-                merged_commit_audit,
-                merged_tools,
-                merged_log_file,
+            cmd_run(RunOptions {
+                checklist: checklist_path,
+                controller_prompt: merged_controller_prompt,
+                worker_prompt: merged_worker_prompt,
+                completion_token: merged_completion_token,
+                sleep_seconds: merged_sleep_seconds,
+                mode: merged_mode,
+                skip_audit: merged_skip_audit,
+                audit_orders_path: merged_audit_orders_path,
+                commit_audit: merged_commit_audit,
+                tools: merged_tools,
+                log_file: merged_log_file,
                 model_config,
                 shutdown_flag,
-                merged_num_instances,
-                merged_warmup_delay,
-                merged_gimme_enabled,
-                merged_gimme_base_path,
-                merged_items_per_instance,
+                num_instances: merged_num_instances,
+                warmup_delay: merged_warmup_delay,
+                gimme_enabled: merged_gimme_enabled,
+                gimme_base_path: merged_gimme_base_path,
+                items_per_instance: merged_items_per_instance,
                 multi_checklist_mode,
-                verify && multi_checklist_mode,
+                verify_enabled: verify && multi_checklist_mode,
                 verifier_prompt,
                 verifier_tools,
-                spiral,
+                spiral_enabled: spiral,
                 max_spirals,
-            )
+                usage_budget: UsageBudget {
+                    max_cost: max_cost.or(config.max_cost),
+                    max_tokens: max_tokens.or(config.max_tokens),
+                },
+                session_policy,
+                run_limits,
+                stall_policy,
+                auto_commit: auto_commit || config.auto_commit.unwrap_or(false),
+                gate,
+            })
         }
         Commands::Init {
             checklist,
@@ -277,15 +470,7 @@ fn main() -> Result<()> {
                 config.tools.clone(),
                 "gemini,codex,claude".to_string(),
             );
-            let warp_api_key = config.warp_api_key.clone()
-                .or_else(|| std::env::var("WARP_API_KEY").ok());
-            let model_config = ModelConfig {
-                gemini_model: gemini_model.or(config.gemini_model.clone()),
-                claude_model: claude_model.or(config.claude_model.clone()),
-                codex_model: codex_model.or(config.codex_model.clone()),
-                warp_model: config.warp_model.clone(),
-                warp_api_key,
-            };
+            let model_config = build_model_config(&config, gemini_model, claude_model, codex_model)?;
             cmd_generate(checklist, prompt, merged_tools, model_config)
         }
         Commands::Add {
@@ -307,15 +492,7 @@ fn main() -> Result<()> {
                 config.tools.clone(),
                 "gemini,codex,claude".to_string(),
             );
-            let warp_api_key = config.warp_api_key.clone()
-                .or_else(|| std::env::var("WARP_API_KEY").ok());
-            let model_config = ModelConfig {
-                gemini_model: gemini_model.or(config.gemini_model.clone()),
-                claude_model: claude_model.or(config.claude_model.clone()),
-                codex_model: codex_model.or(config.codex_model.clone()),
-                warp_model: config.warp_model.clone(),
-                warp_api_key,
-            };
+            let model_config = build_model_config(&config, gemini_model, claude_model, codex_model)?;
             cmd_add_batch(checklist, description, merged_tools, model_config)
         }
         Commands::Remove {
@@ -336,16 +513,33 @@ fn main() -> Result<()> {
                 config.tools.clone(),
                 "gemini,codex,claude".to_string(),
             );
-            let warp_api_key = config.warp_api_key.clone()
-                .or_else(|| std::env::var("WARP_API_KEY").ok());
-            let model_config = ModelConfig {
-                gemini_model: gemini_model.or(config.gemini_model.clone()),
-                claude_model: claude_model.or(config.claude_model.clone()),
-                codex_model: codex_model.or(config.codex_model.clone()),
-                warp_model: config.warp_model.clone(),
-                warp_api_key,
-            };
+            let model_config = build_model_config(&config, gemini_model, claude_model, codex_model)?;
             cmd_update(checklist, instruction, merged_tools, model_config)
         }
+        Commands::Doctor {
+            checklist,
+            checklist_dir,
+            tools,
+            gemini_model,
+            claude_model,
+            codex_model,
+            no_probe,
+            probe_timeout,
+        } => {
+            let merged_tools = config.merge_with_cli(
+                tools.clone(),
+                config.tools.clone(),
+                "gemini,codex,claude".to_string(),
+            );
+            let model_config = build_model_config(&config, gemini_model, claude_model, codex_model)?;
+            cmd_doctor(doctor::DoctorOptions {
+                tools: merged_tools,
+                model_config,
+                checklist,
+                checklist_dir,
+                probe: !no_probe,
+                probe_timeout: Duration::from_secs(probe_timeout),
+            })
+        }
     }
 }
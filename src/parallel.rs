@@ -26,8 +26,9 @@ use std::time::Duration;
 
 use crate::checklist::scanner::has_incomplete_items;
 use crate::coordinator::{StopCoordinator, SubprocessResult};
+use crate::gate::Gate;
 use crate::gimme::{self, ChecklistItem, CheckoutFilters, CheckoutRequest};
-use crate::llm::{LlmToolChain, ModelConfig};
+use crate::llm::{LlmToolChain, ModelConfig, Role, SquelchRegistry, UsageLedger};
 use crate::logger::Logger;
 use crate::runner::{self, RunConfig};
 use crate::verifier::{run_verifier, VerifierConfig, VerifierResult};
@@ -63,18 +64,23 @@ pub struct ParallelConfig {
     pub spiral_enabled: bool,
     /// Maximum number of verify/work spirals.
     pub max_spirals: usize,
+    /// Run-wide usage totals and budget, shared by every instance.
+    pub usage: UsageLedger,
 }
 
 /// Run multiple LLM instances in parallel with optional verify/spiral loop.
 ///
 /// Launches instances with staggered warmup delays. Each instance has its
-/// own independent LlmToolChain for fallback tracking. If any instance
-/// confirms stop, all others finish their current iteration and exit.
+/// own LlmToolChain, but all chains share one rate-limit squelch registry so
+/// a limit hit by any instance moves every instance to the fallback tool.
+/// If any instance confirms stop, all others finish their current iteration
+/// and exit.
 ///
 /// If verify_enabled, runs a verification LLM after workers complete.
 /// If spiral_enabled, restarts workers when verifier finds new work.
 pub fn run_parallel(config: ParallelConfig) -> Result<()> {
     let mut spiral_count = 0;
+    let squelches = SquelchRegistry::load(config.model_config.rate_limit_state.clone());
 
     loop {
         // Check for shutdown before starting spiral iteration
@@ -83,6 +89,16 @@ pub fn run_parallel(config: ParallelConfig) -> Result<()> {
             break;
         }
 
+        if let Some(reason) = config.usage.budget_exceeded() {
+            println!("Budget exhausted: {}. Exiting spiral loop.", reason);
+            break;
+        }
+
+        if let Some(reason) = config.run_config.limits.exceeded() {
+            println!("Run limit reached: {}. Exiting spiral loop.", reason);
+            break;
+        }
+
         // Phase 1: Run workers until completion (scanner-based in multi-checklist mode)
         if spiral_count > 0 {
             println!("=== Spiral iteration {} ===", spiral_count);
@@ -92,24 +108,33 @@ pub fn run_parallel(config: ParallelConfig) -> Result<()> {
         if config.run_config.multi_checklist_mode {
             if let Some(ref base_path) = config.run_config.gimme_base_path {
                 match has_incomplete_items(base_path) {
-                    Ok(false) => {
-                        println!("Scanner: No incomplete items found before worker phase.");
-                        // Skip worker phase, go directly to verifier (if enabled)
-                    }
+                    Ok(false) => match config.run_config.gate.as_ref().map(Gate::run) {
+                        Some(Err(failure)) => {
+                            println!(
+                                "Scanner: No incomplete items, but the gate {}. Running workers to fix it.",
+                                failure.summary
+                            );
+                            run_workers_phase(&config, &squelches)?;
+                        }
+                        _ => {
+                            println!("Scanner: No incomplete items found before worker phase.");
+                            // Skip worker phase, go directly to verifier (if enabled)
+                        }
+                    },
                     Ok(true) => {
                         // Work exists, run workers
-                        run_workers_phase(&config)?;
+                        run_workers_phase(&config, &squelches)?;
                     }
                     Err(e) => {
                         eprintln!("Warning: Scanner error: {}. Running workers anyway.", e);
-                        run_workers_phase(&config)?;
+                        run_workers_phase(&config, &squelches)?;
                     }
                 }
             } else {
-                run_workers_phase(&config)?;
+                run_workers_phase(&config, &squelches)?;
             }
         } else {
-            run_workers_phase(&config)?;
+            run_workers_phase(&config, &squelches)?;
         }
 
         // Check for shutdown after workers
@@ -118,6 +143,16 @@ pub fn run_parallel(config: ParallelConfig) -> Result<()> {
             break;
         }
 
+        if let Some(reason) = config.usage.budget_exceeded() {
+            println!("Budget exhausted after worker phase: {}. Exiting.", reason);
+            break;
+        }
+
+        if let Some(reason) = config.run_config.limits.exceeded() {
+            println!("Run limit reached after worker phase: {}. Exiting.", reason);
+            break;
+        }
+
         // Phase 2: Run verifier if enabled
         if config.verify_enabled {
             println!("=== Starting verification phase ===");
@@ -128,18 +163,21 @@ pub fn run_parallel(config: ParallelConfig) -> Result<()> {
                 completion_token: config.run_config.completion_token.clone(),
             };
 
-            // Use verifier_tools with default models if specified, otherwise use worker config
-            let mut tool_chain = if let Some(ref vtools) = config.verifier_tools {
-                // Verifier uses specified tools with DEFAULT models (no overrides)
-                let default_model_config = ModelConfig::default();
-                println!("Verifier using tools: {} (with default models)", vtools);
-                LlmToolChain::with_models(vtools, &default_model_config)?
-            } else {
-                LlmToolChain::with_models(&config.tools, &config.model_config)?
-            };
+            // --verifier-tools overrides the verifier role's tools; its models still apply
+            let mut model_config = config.model_config.clone();
+            if let Some(ref vtools) = config.verifier_tools {
+                model_config.roles.entry(Role::Verifier).or_default().tools = Some(vtools.clone());
+            }
+            println!(
+                "Verifier using tools: {}",
+                model_config.get_tools_for_role(Role::Verifier, &config.tools)
+            );
+            let mut tool_chain = LlmToolChain::with_models(&config.tools, &model_config)?
+                .with_squelches(squelches.clone())
+                .with_usage_ledger(config.usage.clone());
             let mut logger = Logger::new(&format!("{}.verifier", config.log_file)).ok();
 
-            match run_verifier(&verifier_config, &mut tool_chain, &mut logger) {
+            match run_verifier(&verifier_config, tool_chain.for_role(Role::Verifier), &mut logger) {
                 Ok(VerifierResult::FoundWork(n)) => {
                     spiral_count += 1;
                     println!("Verifier found {} new work items (spiral {})", n, spiral_count);
@@ -180,7 +218,7 @@ pub fn run_parallel(config: ParallelConfig) -> Result<()> {
 }
 
 /// Run the parallel workers phase.
-fn run_workers_phase(config: &ParallelConfig) -> Result<()> {
+fn run_workers_phase(config: &ParallelConfig, squelches: &SquelchRegistry) -> Result<()> {
     let coordinator = Arc::new(StopCoordinator::new(config.num_instances));
     let mut handles: Vec<(usize, JoinHandle<Result<SubprocessResult>>)> = Vec::new();
 
@@ -221,6 +259,11 @@ fn run_workers_phase(config: &ParallelConfig) -> Result<()> {
             break;
         }
 
+        if let Some(reason) = config.run_config.limits.exceeded() {
+            println!("Run limit reached: {}. Not launching instance {}", reason, id);
+            break;
+        }
+
         // Checkout work items if gimme mode enabled
         let work_items = if config.gimme_enabled {
             match checkout_work_items(config, id) {
@@ -243,8 +286,15 @@ fn run_workers_phase(config: &ParallelConfig) -> Result<()> {
             vec![]
         };
 
-        // Create independent LlmToolChain for this subprocess
-        let tool_chain = LlmToolChain::with_models(&config.tools, &config.model_config)?;
+        // Each subprocess gets its own LlmToolChain, sharing rate-limit squelches
+        let mut tool_chain = LlmToolChain::with_models(&config.tools, &config.model_config)?;
+        if config.model_config.spread_instances {
+            tool_chain = tool_chain.with_rotated_tools(id);
+        }
+        let tool_chain = tool_chain
+            .with_instance_id(id)
+            .with_squelches(squelches.clone())
+            .with_usage_ledger(config.usage.clone());
 
         // Create independent logger with subprocess ID in filename
         let log_file = format!("{}.{}", config.log_file, id);
@@ -274,6 +324,25 @@ fn run_workers_phase(config: &ParallelConfig) -> Result<()> {
             break;
         }
 
+        // Instances stop at their next iteration once the shared budget is spent
+        if let Some(reason) = config.usage.budget_exceeded() {
+            println!(
+                "Budget exhausted: {}. Waiting for instances to finish current iteration...",
+                reason
+            );
+            coordinator.wait_for_all_complete(Duration::from_secs(300));
+            break;
+        }
+
+        if let Some(reason) = config.run_config.limits.exceeded() {
+            println!(
+                "Run limit reached: {}. Waiting for instances to finish current iteration...",
+                reason
+            );
+            coordinator.wait_for_all_complete(Duration::from_secs(300));
+            break;
+        }
+
         // Check for Ctrl+C
         if config.run_config.shutdown_flag.load(Ordering::Relaxed) {
             println!("Shutdown requested. Waiting for instances to finish...");
@@ -321,6 +390,7 @@ fn checkout_work_items(config: &ParallelConfig, subprocess_id: usize) -> Result<
     };
 
     let result = gimme::checkout::checkout(request, subprocess_id)?;
+    gimme::checkout::track_outstanding(&result.items);
     Ok(result.items)
 }
 
@@ -364,6 +434,7 @@ fn spawn_subprocess(
                 coordinator.mark_completed(id, SubprocessResult::Error(e.to_string()));
             }
         }
+        gimme::checkout::release_outstanding(&work_items);
 
         result
     })
@@ -20,6 +20,21 @@ Completion is determined by the orchestrator scanning all checklists.
 Your job is to do work, not judge project completion.
 "#;
 
+/// Prepended to the worker prompt when the worker appears stalled.
+pub const STALL_NUDGE_PROMPT: &str = r#"You appear to be stuck: your recent turns produced no commits, no checklist updates and no new changes.
+Do not repeat the same approach. Re-read @{checklist}, take a different angle or a smaller piece of the task, and make a concrete change you can commit.
+If the task cannot be done as written, record why in the checklist and move on to another item.
+"#;
+
+/// Prepended to the worker prompt while the gate command is failing.
+pub const GATE_FAILURE_PROMPT: &str = r#"FIX THIS FIRST: the gate command `{gate_command}` {gate_summary} after the last turn.
+Before any other work on @{checklist}, make it pass and commit the fix. The run cannot finish while it fails.
+Last lines of its output:
+```
+{gate_output}
+```
+"#;
+
 pub const STOP_CONFIRMATION_PROMPT: &str = r#"I detected that the previous response emitted the stop/completion token "{completion_token}".
 Re-open @{checklist}. Confirm that every requirement and task is complete, the code builds cleanly, and all changes are committed.
 Emit "{completion_token}" again on a line by itself at the very end ONLY if the loop should end.
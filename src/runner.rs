@@ -25,10 +25,14 @@ use crate::audit::{run_standing_orders_audit, AuditConfig};
 use crate::checklist::scanner::has_incomplete_items;
 use crate::cli::RunMode;
 use crate::coordinator::{StopCoordinator, SubprocessResult};
+use crate::gate::{Gate, GateStatus};
 use crate::gimme::{self, ChecklistItem};
-use crate::llm::LlmToolChain;
+use crate::git::{self, CommitLedger, TurnChanges, TurnStart};
+use crate::limits::{IterationClaim, RunLimits};
+use crate::llm::{AgentSession, LlmToolChain, Role};
 use crate::logger::Logger;
 use crate::prompts;
+use crate::stall::{self, StallAction, StallMonitor, StallPolicy};
 
 #[derive(Clone, Debug)]
 pub struct RunConfig {
@@ -47,6 +51,34 @@ pub struct RunConfig {
     pub multi_checklist_mode: bool,
     /// Base path for scanning AGENTS.md files (used in multi_checklist_mode)
     pub gimme_base_path: Option<PathBuf>,
+    /// Whether worker turns continue the previous agent session
+    pub session_policy: SessionPolicy,
+    /// Iteration cap, deadline and active hours, shared by all instances
+    pub limits: Arc<RunLimits>,
+    /// When a worker without progress is escalated
+    pub stall_policy: StallPolicy,
+    /// Commit changes a turn leaves uncommitted
+    pub auto_commit: bool,
+    /// Other instances work in the same tree, so commits and changes can't
+    /// be attributed to a turn
+    pub shared_worktree: bool,
+    /// afkcode's logs and state, never a turn's changes
+    pub own_paths: Vec<PathBuf>,
+    /// Commits made by each turn, shared by all instances
+    pub commits: CommitLedger,
+    /// Command each worker turn must leave passing before the run can finish
+    pub gate: Option<Gate>,
+}
+
+/// When worker turns resume the previous agent session instead of starting
+/// a new one
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SessionPolicy {
+    pub enabled: bool,
+    /// Turns per session before starting fresh (0 for no limit)
+    pub max_turns: usize,
+    /// Start fresh once the context reaches this many tokens (0 for no limit)
+    pub max_context_tokens: u64,
 }
 
 struct WorkerLoopState {
@@ -54,6 +86,61 @@ struct WorkerLoopState {
     last_stdout: String,
     saw_stop_token: bool,
     audit_done: bool,
+    /// Session to continue on the next turn, and how many turns it has had
+    session: Option<AgentSession>,
+    session_turns: usize,
+    /// Progress watch, when stall detection is on
+    stall: Option<StallMonitor>,
+    /// Tell the worker it appears stuck on the next turn
+    stall_nudge: bool,
+    /// Gate result for the tree the last turn left
+    gate: GateStatus,
+}
+
+impl WorkerLoopState {
+    fn new(audit_done: bool) -> Self {
+        Self {
+            iteration: 1,
+            last_stdout: String::new(),
+            saw_stop_token: false,
+            audit_done,
+            session: None,
+            session_turns: 0,
+            stall: None,
+            stall_nudge: false,
+            gate: GateStatus::Unknown,
+        }
+    }
+
+    /// Start watching for stalls from the current state, if enabled
+    fn watch_for_stalls(&mut self, config: &RunConfig, work_items: &[ChecklistItem]) {
+        if config.stall_policy.enabled() {
+            self.stall = Some(StallMonitor::new(
+                config.stall_policy.clone(),
+                watched_markers(config, work_items),
+            ));
+        }
+    }
+
+    /// Notes to lead the next worker prompt with: the gate failure to fix
+    /// first, running the gate if this is the first turn, and the stall nudge
+    fn worker_notes(
+        &mut self,
+        config: &RunConfig,
+        logger: &mut Option<Logger>,
+        prefix: &str,
+    ) -> Vec<String> {
+        gate_passes(config, &mut self.gate, logger, prefix);
+        let mut notes: Vec<String> = gate_failure_note(config, &self.gate).into_iter().collect();
+        if std::mem::take(&mut self.stall_nudge) {
+            notes.push(fill_placeholders(
+                prompts::STALL_NUDGE_PROMPT,
+                &config.checklist_path_str,
+                &config.completion_token,
+            ));
+        }
+        notes
+    }
 }
 
 pub fn fill_placeholders(template: &str, checklist: &str, completion_token: &str) -> String {
@@ -117,34 +204,22 @@ fn contains_token(stdout: &str, completion_token: &str) -> bool {
         .contains(&completion_token.to_lowercase())
 }
 
-pub fn stream_outputs(label: &str, stdout: &str, stderr: &str, logger: &mut Option<Logger>) {
+/// Invoke the tool chain, streaming its output live between labelled
+/// header and footer lines. Returns the complete stdout/stderr.
+pub fn invoke_streamed(
+    label: &str,
+    prompt: &str,
+    tool_chain: &mut LlmToolChain,
+    logger: &mut Option<Logger>,
+) -> Result<(String, String)> {
     let header = format!("\n--- {} OUTPUT ---", label.to_uppercase());
     let footer = format!("--- END {} OUTPUT ---\n", label.to_uppercase());
 
-    println!("{}", header);
-    if let Some(log) = logger.as_mut() {
-        let _ = log.logln(&header);
-    }
+    log_message(logger, &header);
+    let result = tool_chain.stream_with_fallback(prompt, logger);
+    log_message(logger, &footer);
 
-    if !stdout.is_empty() {
-        print!("{}", stdout);
-        if let Some(log) = logger.as_mut() {
-            let _ = log.log(stdout);
-        }
-    }
-    if !stderr.is_empty() {
-        eprint!("{}", stderr);
-        if let Some(log) = logger.as_mut() {
-            let _ = log.log(stderr);
-        }
-    }
-
-    println!("{}", footer);
-    if let Some(log) = logger.as_mut() {
-        let _ = log.logln(&footer);
-    }
-
-    let _ = io::stdout().flush();
+    result
 }
 
 pub fn log_message(logger: &mut Option<Logger>, message: &str) {
@@ -163,6 +238,371 @@ pub fn log_warning(logger: &mut Option<Logger>, message: &str) {
     let _ = io::stderr().flush();
 }
 
+/// Log the tokens and cost of the turn that just finished, if the tool
+/// reported any
+fn log_turn_usage(tool_chain: &mut LlmToolChain, logger: &mut Option<Logger>, status: &str) {
+    if let Some(usage) = tool_chain.take_turn_usage() {
+        log_message(logger, &format!("{} usage: {}", status, usage));
+    }
+}
+
+/// Ask the chain to continue the session from the previous turn, if any
+fn begin_session_turn(config: &RunConfig, state: &WorkerLoopState, tool_chain: &mut LlmToolChain) {
+    if config.session_policy.enabled {
+        tool_chain.resume_session(state.session.clone());
+    }
+}
+
+/// Record the session the turn ran in, rolling over to a fresh session once
+/// it has had enough turns or its context is nearly full
+fn end_session_turn(
+    config: &RunConfig,
+    state: &mut WorkerLoopState,
+    tool_chain: &mut LlmToolChain,
+    logger: &mut Option<Logger>,
+) {
+    let policy = config.session_policy;
+    if !policy.enabled {
+        return;
+    }
+    let Some(session) = tool_chain.take_turn_session() else {
+        state.session = None;
+        state.session_turns = 0;
+        return;
+    };
+
+    if state.session.as_ref().is_some_and(|previous| {
+        previous.tool == session.tool && previous.id == session.id
+    }) {
+        state.session_turns += 1;
+    } else {
+        state.session_turns = 1;
+    }
+
+    let rollover = if policy.max_turns > 0 && state.session_turns >= policy.max_turns {
+        Some(format!("reached {} turns", state.session_turns))
+    } else {
+        session
+            .context_tokens
+            .filter(|&tokens| policy.max_context_tokens > 0 && tokens >= policy.max_context_tokens)
+            .map(|tokens| format!("context is at {} tokens", tokens))
+    };
+
+    match rollover {
+        Some(reason) => {
+            log_message(
+                logger,
+                &format!(
+                    "{} session {} {}; the next turn starts a fresh session.",
+                    session.tool, session.id, reason
+                ),
+            );
+            state.session = None;
+            state.session_turns = 0;
+        }
+        None => state.session = Some(session),
+    }
+}
+
+/// Log and report whether the run's cost or token budget is used up
+fn budget_exhausted(tool_chain: &LlmToolChain, logger: &mut Option<Logger>) -> bool {
+    match tool_chain.budget_exceeded() {
+        Some(reason) => {
+            log_message(logger, &format!("Budget exhausted: {}. Stopping.", reason));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Log and report whether a run limit stops the loop
+fn run_limit_reached(config: &RunConfig, logger: &mut Option<Logger>) -> bool {
+    match config.limits.exceeded() {
+        Some(reason) => {
+            log_message(logger, &format!("Run limit reached: {}. Stopping.", reason));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Count the worker turn about to start against the iteration cap; it is
+/// finished once the claim is dropped. Logs and returns `None` if no more may
+/// start.
+fn claim_worker_turn<'a>(
+    config: &'a RunConfig,
+    logger: &mut Option<Logger>,
+) -> Option<IterationClaim<'a>> {
+    match config.limits.start_iteration() {
+        Ok(claim) => Some(claim),
+        Err(reason) => {
+            log_message(logger, &format!("Run limit reached: {}. Stopping.", reason));
+            None
+        }
+    }
+}
+
+/// Outside the active hours, wait for the next window to open. Wakes early
+/// on shutdown, at the deadline or once `stopping` holds; returns whether it
+/// waited, so the caller re-runs its stop checks before starting a turn.
+fn wait_for_active_hours(
+    config: &RunConfig,
+    logger: &mut Option<Logger>,
+    stopping: impl Fn() -> bool,
+) -> bool {
+    let (Some(opens), Some(hours)) = (
+        config.limits.paused_until(chrono::Local::now()),
+        config.limits.active_hours(),
+    ) else {
+        return false;
+    };
+    log_message(
+        logger,
+        &format!(
+            "Outside active hours ({}). Pausing until {}...",
+            hours,
+            opens.format("%Y-%m-%d %H:%M")
+        ),
+    );
+    while chrono::Local::now() < opens
+        && !config.shutdown_flag.load(Ordering::Relaxed)
+        && config.limits.exceeded().is_none()
+        && !stopping()
+    {
+        thread::sleep(Duration::from_secs(1));
+    }
+    true
+}
+
+/// After a turn, log the commits it made and any changes it left
+/// uncommitted, committing those if `auto_commit` is set, and record the
+/// commits for the run summary. `start` is `None` outside a git repository
+/// or when instances share the tree. Returns what the turn changed.
+fn record_turn_commits(
+    config: &RunConfig,
+    logger: &mut Option<Logger>,
+    start: Option<TurnStart>,
+    status: &str,
+    instance: Option<usize>,
+    iteration: usize,
+    work_items: &[ChecklistItem],
+) -> TurnChanges {
+    let Some(start) = start else {
+        return TurnChanges::default();
+    };
+    let mut commits = start.commits();
+    let mut left_behind = start.left_behind();
+    if !left_behind.is_empty() {
+        if config.auto_commit {
+            match start.commit(&auto_commit_message(iteration, work_items), &left_behind) {
+                Ok(commit) => {
+                    commits.push(commit);
+                    left_behind.clear();
+                }
+                Err(e) => log_warning(
+                    logger,
+                    &format!("Warning: Failed to commit leftover changes: {}", e),
+                ),
+            }
+        } else {
+            log_warning(
+                logger,
+                &format!(
+                    "Warning: {} left {} uncommitted change(s): {}",
+                    status,
+                    left_behind.len(),
+                    left_behind.keys().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            );
+        }
+    }
+
+    let summary = if commits.is_empty() {
+        "none".to_string()
+    } else {
+        git::format_commits(&commits)
+    };
+    log_message(logger, &format!("{} commits: {}", status, summary));
+    config.commits.record(instance, iteration, &commits);
+    TurnChanges {
+        commits,
+        left_behind,
+    }
+}
+
+/// HEAD and the uncommitted changes before a turn, unless its commits can't
+/// be attributed to it
+fn record_turn_start(config: &RunConfig) -> Option<TurnStart> {
+    if config.shared_worktree {
+        return None;
+    }
+    TurnStart::record(&config.own_paths)
+}
+
+/// Subject for committing a turn's leftovers, naming the assigned item
+fn auto_commit_message(iteration: usize, work_items: &[ChecklistItem]) -> String {
+    const MAX_ITEM_CHARS: usize = 60;
+    match work_items {
+        [] => format!("afkcode: changes left uncommitted by iteration {}", iteration),
+        [item, rest @ ..] => {
+            let mut content: String = item.content.chars().take(MAX_ITEM_CHARS).collect();
+            if item.content.chars().count() > MAX_ITEM_CHARS {
+                content.push_str("...");
+            }
+            if !rest.is_empty() {
+                content.push_str(&format!(" (+{} more)", rest.len()));
+            }
+            format!("afkcode: iteration {}: {}", iteration, content)
+        }
+    }
+}
+
+fn watched_markers(config: &RunConfig, work_items: &[ChecklistItem]) -> Vec<String> {
+    stall::watched_markers(work_items, &config.checklist, config.gimme_base_path.as_deref())
+}
+
+/// After a worker turn, check what it changed and escalate if the worker has
+/// stalled. `work_items` are the instance's checked-out items, if any; their
+/// markers alone count when there are some. Returns whether the loop should
+/// stop.
+fn handle_stall(
+    config: &RunConfig,
+    state: &mut WorkerLoopState,
+    tool_chain: &mut LlmToolChain,
+    logger: &mut Option<Logger>,
+    changes: &TurnChanges,
+    work_items: &[ChecklistItem],
+    prefix: &str,
+) -> Result<bool> {
+    let Some(monitor) = state.stall.as_mut() else {
+        return Ok(false);
+    };
+    let Some(action) = monitor.observe(changes, watched_markers(config, work_items)) else {
+        return Ok(false);
+    };
+    let idle_turns = monitor.idle_turns();
+    log_message(
+        logger,
+        &format!(
+            "{}No progress in {} iteration(s); escalating: {}",
+            prefix, idle_turns, action
+        ),
+    );
+
+    match action {
+        StallAction::SwitchTool => {
+            let message = match tool_chain.for_role(Role::Worker).rotate_primary_tool() {
+                Some(tool) => format!("{}Worker turns now start with {}", prefix, tool),
+                None => format!("{}Only one worker tool; nothing to switch to", prefix),
+            };
+            log_message(logger, &message);
+        }
+        StallAction::Nudge => state.stall_nudge = true,
+        StallAction::Groom => {
+            let chain = tool_chain.for_role(Role::Controller);
+            let prompt = build_prompt(
+                &config.checklist_path_str,
+                &config.controller_prompt,
+                &config.completion_token,
+            );
+            invoke_streamed("controller", &prompt, chain, logger)?;
+            log_turn_usage(chain, logger, &format!("{}mode=worker turn=groom", prefix));
+        }
+        StallAction::Block => {
+            if work_items.is_empty() {
+                log_message(
+                    logger,
+                    &format!("{}No checked-out work item to mark BLOCKED", prefix),
+                );
+            }
+            let reason = format!("no progress in {} iterations", idle_turns);
+            for item in work_items {
+                match gimme::marker::mark_blocked(item, &reason) {
+                    Ok(true) => log_message(
+                        logger,
+                        &format!("{}Marked BLOCKED: {}", prefix, item.content),
+                    ),
+                    Ok(false) => {}
+                    Err(e) => log_warning(
+                        logger,
+                        &format!("{}Warning: Failed to mark item BLOCKED: {}", prefix, e),
+                    ),
+                }
+            }
+            gimme::checkout::release_outstanding(work_items);
+        }
+        StallAction::Stop => {
+            log_message(logger, &format!("{}Worker stalled. Stopping.", prefix));
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Run the gate, if one is configured, and record and log its result
+fn run_gate(config: &RunConfig, status: &mut GateStatus, logger: &mut Option<Logger>, prefix: &str) {
+    let Some(gate) = config.gate.as_ref() else {
+        return;
+    };
+    log_message(logger, &format!("{}Running gate: {}", prefix, gate.command()));
+    *status = match gate.run() {
+        Ok(elapsed) => {
+            log_message(
+                logger,
+                &format!("{}Gate passed in {}s", prefix, elapsed.as_secs()),
+            );
+            GateStatus::Passed
+        }
+        Err(failure) => {
+            log_warning(
+                logger,
+                &format!("{}Gate {}:\n{}", prefix, failure.summary, failure.tail),
+            );
+            GateStatus::Failed(failure)
+        }
+    };
+}
+
+/// Whether the gate passes for the tree as the last turn left it, running it
+/// if it hasn't run since. Always true without a gate.
+fn gate_passes(
+    config: &RunConfig,
+    status: &mut GateStatus,
+    logger: &mut Option<Logger>,
+    prefix: &str,
+) -> bool {
+    if config.gate.is_none() {
+        return true;
+    }
+    if *status == GateStatus::Unknown {
+        run_gate(config, status, logger, prefix);
+    }
+    *status == GateStatus::Passed
+}
+
+/// The "fix this first" note for a failing gate
+fn gate_failure_note(config: &RunConfig, status: &GateStatus) -> Option<String> {
+    let (Some(gate), GateStatus::Failed(failure)) = (config.gate.as_ref(), status) else {
+        return None;
+    };
+    let note = fill_placeholders(
+        prompts::GATE_FAILURE_PROMPT,
+        &config.checklist_path_str,
+        &config.completion_token,
+    )
+    .replace("{gate_command}", gate.command())
+    .replace("{gate_summary}", &failure.summary)
+    .replace("{gate_output}", &failure.tail);
+    Some(note)
+}
+
+/// Sleep before the next turn, unless a run limit means there won't be one
+fn sleep_between_turns(config: &RunConfig, logger: &mut Option<Logger>) {
+    if config.limits.exceeded().is_none() {
+        sleep_with_log(config.sleep_seconds, logger);
+    }
+}
+
 fn sleep_with_log(seconds: u64, logger: &mut Option<Logger>) {
     let sleep_msg = format!("Sleeping {} seconds before next prompt...", seconds);
     log_message(logger, &sleep_msg);
@@ -171,23 +611,33 @@ fn sleep_with_log(seconds: u64, logger: &mut Option<Logger>) {
     }
 }
 
+/// The worker prompt, led by `notes` (see `WorkerLoopState::worker_notes`)
+fn worker_prompt(config: &RunConfig, notes: &[String]) -> String {
+    let prompt = build_prompt_with_mode(
+        &config.checklist_path_str,
+        &config.worker_prompt,
+        &config.completion_token,
+        config.multi_checklist_mode,
+    );
+    if notes.is_empty() {
+        return prompt;
+    }
+    format!("{}\n{}", notes.join("\n\n"), prompt)
+}
+
 fn run_worker_turn(
     config: &RunConfig,
     tool_chain: &mut LlmToolChain,
     logger: &mut Option<Logger>,
     iteration: usize,
+    notes: &[String],
 ) -> Result<String> {
     let status = format!("mode=worker iteration={} turn=normal", iteration);
     log_message(logger, &status);
 
-    let prompt = build_prompt_with_mode(
-        &config.checklist_path_str,
-        &config.worker_prompt,
-        &config.completion_token,
-        config.multi_checklist_mode,
-    );
-    let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt, logger)?;
-    stream_outputs("worker", &stdout, &stderr, logger);
+    let prompt = worker_prompt(config, notes);
+    let (stdout, _stderr) = invoke_streamed("worker", &prompt, tool_chain, logger)?;
+    log_turn_usage(tool_chain, logger, &status);
     Ok(stdout)
 }
 
@@ -212,8 +662,8 @@ fn run_stop_confirmation_turn(
         prompt.push('\n');
     }
 
-    let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt, logger)?;
-    stream_outputs("confirmation", &stdout, &stderr, logger);
+    let (stdout, _stderr) = invoke_streamed("confirmation", &prompt, tool_chain, logger)?;
+    log_turn_usage(tool_chain, logger, &status);
     Ok(stdout)
 }
 
@@ -222,12 +672,7 @@ pub fn run_worker_loop(
     tool_chain: &mut LlmToolChain,
     logger: &mut Option<Logger>,
 ) -> Result<()> {
-    let mut state = WorkerLoopState {
-        iteration: 1,
-        last_stdout: String::new(),
-        saw_stop_token: false,
-        audit_done: config.skip_audit,
-    };
+    let mut state = WorkerLoopState::new(config.skip_audit);
 
     if !config.skip_audit {
         let audit_config = AuditConfig {
@@ -236,9 +681,10 @@ pub fn run_worker_loop(
             audit_orders_path: &config.audit_orders_path,
             commit_audit: config.commit_audit,
         };
-        run_standing_orders_audit(&audit_config, tool_chain, logger)?;
+        run_standing_orders_audit(&audit_config, tool_chain.for_role(Role::Audit), logger)?;
         state.audit_done = true;
     }
+    state.watch_for_stalls(config, &[]);
 
     loop {
         if config.shutdown_flag.load(Ordering::Relaxed) {
@@ -246,16 +692,30 @@ pub fn run_worker_loop(
             break;
         }
 
+        if budget_exhausted(tool_chain, logger) {
+            break;
+        }
+
+        if wait_for_active_hours(config, logger, || false) {
+            continue;
+        }
+
         // In multi_checklist_mode, check scanner for completion instead of token-based
         if config.multi_checklist_mode {
             if let Some(ref base_path) = config.gimme_base_path {
                 match has_incomplete_items(base_path) {
                     Ok(false) => {
+                        if gate_passes(config, &mut state.gate, logger, "") {
+                            log_message(
+                                logger,
+                                "Scanner detected no incomplete items. All checklists complete.",
+                            );
+                            break;
+                        }
                         log_message(
                             logger,
-                            "Scanner detected no incomplete items. All checklists complete.",
+                            "Scanner detected no incomplete items, but the gate is failing. Continuing.",
                         );
-                        break;
                     }
                     Ok(true) => {
                         // Work remains, continue
@@ -270,42 +730,90 @@ pub fn run_worker_loop(
             }
         }
 
+        if run_limit_reached(config, logger) {
+            break;
+        }
+
         // Token-based completion (only in single-checklist mode)
         if !config.multi_checklist_mode && state.saw_stop_token {
+            let chain = tool_chain.for_role(Role::Confirmation);
+            begin_session_turn(config, &state, chain);
+            let turn_start = record_turn_start(config);
             let confirmation_stdout = run_stop_confirmation_turn(
                 config,
-                tool_chain,
+                chain,
                 logger,
                 state.iteration,
                 &state.last_stdout,
             )?;
+            end_session_turn(config, &mut state, chain, logger);
+            record_turn_commits(
+                config,
+                logger,
+                turn_start,
+                &format!("mode=worker iteration={} turn=confirmation", state.iteration),
+                None,
+                state.iteration,
+                &[],
+            );
+            run_gate(config, &mut state.gate, logger, "");
 
             let confirmed = contains_token(&confirmation_stdout, &config.completion_token);
             if confirmed {
-                log_message(logger, "Stop token confirmed; exiting.");
-                break;
+                if gate_passes(config, &mut state.gate, logger, "") {
+                    log_message(logger, "Stop token confirmed; exiting.");
+                    break;
+                }
+                log_message(logger, "Stop token confirmed, but the gate is failing. Continuing.");
             }
 
             state.saw_stop_token = false;
             state.last_stdout = confirmation_stdout;
-            sleep_with_log(config.sleep_seconds, logger);
+            sleep_between_turns(config, logger);
             continue;
         }
 
-        let stdout = run_worker_turn(config, tool_chain, logger, state.iteration)?;
+        let Some(claim) = claim_worker_turn(config, logger) else {
+            break;
+        };
+        let notes = state.worker_notes(config, logger, "");
+        let chain = tool_chain.for_role(Role::Worker);
+        begin_session_turn(config, &state, chain);
+        let turn_start = record_turn_start(config);
+        let stdout = run_worker_turn(config, chain, logger, state.iteration, &notes)?;
+        claim.finish();
+        end_session_turn(config, &mut state, chain, logger);
+        let changes = record_turn_commits(
+            config,
+            logger,
+            turn_start,
+            &format!("mode=worker iteration={} turn=normal", state.iteration),
+            None,
+            state.iteration,
+            &[],
+        );
+        run_gate(config, &mut state.gate, logger, "");
 
         // Only check for stop token in single-checklist mode
         if !config.multi_checklist_mode {
             state.saw_stop_token = contains_token(&stdout, &config.completion_token);
+            if state.saw_stop_token && !gate_passes(config, &mut state.gate, logger, "") {
+                log_message(logger, "Ignoring stop token: the gate is failing.");
+                state.saw_stop_token = false;
+            }
         }
         state.last_stdout = stdout;
         state.iteration += 1;
 
+        if handle_stall(config, &mut state, tool_chain, logger, &changes, &[], "")? {
+            break;
+        }
+
         if config.shutdown_flag.load(Ordering::Relaxed) {
             break;
         }
 
-        sleep_with_log(config.sleep_seconds, logger);
+        sleep_between_turns(config, logger);
     }
 
     let _ = state.audit_done;
@@ -319,11 +827,12 @@ pub fn run_controller_worker_loop(
     logger: &mut Option<Logger>,
 ) -> Result<()> {
     let prompts = [
-        ("controller", &config.controller_prompt),
-        ("worker", &config.worker_prompt),
+        ("controller", Role::Controller, &config.controller_prompt),
+        ("worker", Role::Worker, &config.worker_prompt),
     ];
 
     let mut iteration = 0;
+    let mut gate = GateStatus::Unknown;
 
     loop {
         if config.shutdown_flag.load(Ordering::Relaxed) {
@@ -331,23 +840,67 @@ pub fn run_controller_worker_loop(
             break;
         }
 
-        let (label, prompt_template) = prompts[iteration % prompts.len()];
-        let prompt = build_prompt(
+        if budget_exhausted(tool_chain, logger) {
+            break;
+        }
+
+        if wait_for_active_hours(config, logger, || false) {
+            continue;
+        }
+
+        if run_limit_reached(config, logger) {
+            break;
+        }
+
+        let (label, role, prompt_template) = prompts[iteration % prompts.len()];
+        let claim = match label {
+            "worker" => match claim_worker_turn(config, logger) {
+                Some(claim) => Some(claim),
+                None => break,
+            },
+            _ => None,
+        };
+        let mut prompt = build_prompt(
             &config.checklist_path_str,
             prompt_template,
             &config.completion_token,
         );
+        if label == "worker" {
+            gate_passes(config, &mut gate, logger, "");
+            if let Some(note) = gate_failure_note(config, &gate) {
+                prompt = format!("{}\n{}", note, prompt);
+            }
+        }
 
         let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
         let timestamp_msg = format!("\n[{}] Running {} prompt...", timestamp, label);
         log_message(logger, &timestamp_msg);
 
-        let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt, logger)?;
-        stream_outputs(label, &stdout, &stderr, logger);
+        let chain = tool_chain.for_role(role);
+        let turn_start = record_turn_start(config);
+        let (stdout, _stderr) = invoke_streamed(label, &prompt, chain, logger)?;
+        drop(claim);
+        let status = format!("mode=controller turn={}", label);
+        log_turn_usage(chain, logger, &status);
+        record_turn_commits(config, logger, turn_start, &status, None, iteration + 1, &[]);
+        // Any turn may change the tree; the gate runs after worker turns and
+        // before completion is accepted
+        gate = GateStatus::Unknown;
+        if label == "worker" {
+            run_gate(config, &mut gate, logger, "");
+        }
 
         if label == "controller" && completion_detected(&stdout, &config.completion_token) {
-            if verify_completion_intent(&stdout, &config.completion_token, tool_chain, logger)? {
-                break;
+            if gate_passes(config, &mut gate, logger, "") {
+                let chain = tool_chain.for_role(Role::IntentCheck);
+                if verify_completion_intent(&stdout, &config.completion_token, chain, logger)? {
+                    break;
+                }
+            } else {
+                log_message(
+                    logger,
+                    "Completion token detected, but the gate is failing. Continuing.",
+                );
             }
         }
 
@@ -357,7 +910,7 @@ pub fn run_controller_worker_loop(
              break;
         }
 
-        sleep_with_log(config.sleep_seconds, logger);
+        sleep_between_turns(config, logger);
     }
 
     Ok(())
@@ -391,7 +944,7 @@ Text to analyze:
         let _ = log.logln(&verify_msg);
     }
 
-    match tool_chain.invoke_with_fallback_without_thinking(&verification_prompt, logger) {
+    match tool_chain.invoke_with_fallback(&verification_prompt, logger) {
         Ok((verify_stdout, _)) => {
             let is_confirmed = verify_stdout.contains(completion_token);
 
@@ -437,12 +990,7 @@ pub fn run_worker_loop_parallel(
     subprocess_id: usize,
     work_items: &[ChecklistItem],
 ) -> Result<SubprocessResult> {
-    let mut state = WorkerLoopState {
-        iteration: 1,
-        last_stdout: String::new(),
-        saw_stop_token: false,
-        audit_done: config.skip_audit,
-    };
+    let mut state = WorkerLoopState::new(config.skip_audit);
 
     // Build effective prompt (with gimme items if present)
     let effective_worker_prompt = if !work_items.is_empty() {
@@ -460,6 +1008,8 @@ pub fn run_worker_loop_parallel(
 
     // Skip audit for parallel runs (should be done by main process)
     state.audit_done = true;
+    state.watch_for_stalls(config, work_items);
+    let prefix = format!("[Instance {}] ", subprocess_id);
 
     loop {
         // Check coordinator stop flag before starting iteration
@@ -480,24 +1030,71 @@ pub fn run_worker_loop_parallel(
             return Ok(SubprocessResult::Shutdown);
         }
 
+        // Budget is shared by all instances
+        if budget_exhausted(tool_chain, logger) {
+            return Ok(SubprocessResult::BudgetExceeded);
+        }
+
+        if wait_for_active_hours(config, logger, || coordinator.should_stop()) {
+            continue;
+        }
+
+        if run_limit_reached(config, logger) {
+            return Ok(SubprocessResult::LimitReached);
+        }
+        // Confirmation turns don't count against the cap
+        let claim = if state.saw_stop_token {
+            None
+        } else {
+            match claim_worker_turn(config, logger) {
+                Some(claim) => Some(claim),
+                None => return Ok(SubprocessResult::LimitReached),
+            }
+        };
+
         // Mark that we're starting an LLM call (not at a safe stopping point)
         coordinator.mark_iteration_start(subprocess_id);
 
         if state.saw_stop_token {
+            let chain = tool_chain.for_role(Role::Confirmation);
+            begin_session_turn(config, &state, chain);
+            let turn_start = record_turn_start(config);
             let confirmation_stdout = run_stop_confirmation_turn_parallel(
                 &effective_config,
-                tool_chain,
+                chain,
                 logger,
                 state.iteration,
                 &state.last_stdout,
                 subprocess_id,
             )?;
+            end_session_turn(config, &mut state, chain, logger);
+            record_turn_commits(
+                config,
+                logger,
+                turn_start,
+                &format!(
+                    "mode=worker instance={} iteration={} turn=confirmation",
+                    subprocess_id, state.iteration
+                ),
+                Some(subprocess_id),
+                state.iteration,
+                work_items,
+            );
+            run_gate(config, &mut state.gate, logger, &prefix);
 
             // Mark iteration complete - we're at a safe stopping point
             coordinator.mark_iteration_complete(subprocess_id);
 
             let confirmed = contains_token(&confirmation_stdout, &config.completion_token);
-            if confirmed {
+            if confirmed && !gate_passes(config, &mut state.gate, logger, &prefix) {
+                log_message(
+                    logger,
+                    &format!(
+                        "{}Stop token confirmed, but the gate is failing. Continuing.",
+                        prefix
+                    ),
+                );
+            } else if confirmed {
                 log_message(
                     logger,
                     &format!(
@@ -518,24 +1115,85 @@ pub fn run_worker_loop_parallel(
                 return Ok(SubprocessResult::Shutdown);
             }
 
-            sleep_with_log(config.sleep_seconds, logger);
+            sleep_between_turns(config, logger);
             continue;
         }
 
+        let notes = state.worker_notes(config, logger, &prefix);
+        let chain = tool_chain.for_role(Role::Worker);
+        begin_session_turn(config, &state, chain);
+        let turn_start = record_turn_start(config);
         let stdout = run_worker_turn_parallel(
             &effective_config,
-            tool_chain,
+            chain,
             logger,
             state.iteration,
             subprocess_id,
+            &notes,
+        )?;
+        drop(claim);
+        end_session_turn(config, &mut state, chain, logger);
+        let changes = record_turn_commits(
+            config,
+            logger,
+            turn_start,
+            &format!(
+                "mode=worker instance={} iteration={} turn=normal",
+                subprocess_id, state.iteration
+            ),
+            Some(subprocess_id),
+            state.iteration,
+            work_items,
+        );
+        run_gate(config, &mut state.gate, logger, &prefix);
+
+        let stalled = handle_stall(
+            config,
+            &mut state,
+            tool_chain,
+            logger,
+            &changes,
+            work_items,
+            &prefix,
         )?;
 
         // Mark iteration complete - we're at a safe stopping point
         coordinator.mark_iteration_complete(subprocess_id);
 
+        if stalled {
+            return Ok(SubprocessResult::Stalled);
+        }
+
+        // Workers launched to fix a failing gate on finished checklists stop
+        // once it passes
+        if config.multi_checklist_mode
+            && state.gate == GateStatus::Passed
+            && config
+                .gimme_base_path
+                .as_deref()
+                .is_some_and(|base_path| matches!(has_incomplete_items(base_path), Ok(false)))
+        {
+            log_message(
+                logger,
+                &format!(
+                    "{}All checklists complete and the gate passes; signaling coordinator.",
+                    prefix
+                ),
+            );
+            coordinator.signal_stop(subprocess_id);
+            return Ok(SubprocessResult::StopConfirmed);
+        }
+
         // In multi_checklist_mode, ignore stop token - completion is scanner-based
         state.saw_stop_token = !config.multi_checklist_mode
             && contains_token(&stdout, &config.completion_token);
+        if state.saw_stop_token && !gate_passes(config, &mut state.gate, logger, &prefix) {
+            log_message(
+                logger,
+                &format!("{}Ignoring stop token: the gate is failing.", prefix),
+            );
+            state.saw_stop_token = false;
+        }
         state.last_stdout = stdout;
         state.iteration += 1;
 
@@ -544,7 +1202,7 @@ pub fn run_worker_loop_parallel(
             return Ok(SubprocessResult::Shutdown);
         }
 
-        sleep_with_log(config.sleep_seconds, logger);
+        sleep_between_turns(config, logger);
     }
 }
 
@@ -554,6 +1212,7 @@ fn run_worker_turn_parallel(
     logger: &mut Option<Logger>,
     iteration: usize,
     subprocess_id: usize,
+    notes: &[String],
 ) -> Result<String> {
     let status = format!(
         "mode=worker instance={} iteration={} turn=normal",
@@ -561,14 +1220,10 @@ fn run_worker_turn_parallel(
     );
     log_message(logger, &status);
 
-    let prompt = build_prompt_with_mode(
-        &config.checklist_path_str,
-        &config.worker_prompt,
-        &config.completion_token,
-        config.multi_checklist_mode,
-    );
-    let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt, logger)?;
-    stream_outputs(&format!("worker-{}", subprocess_id), &stdout, &stderr, logger);
+    let prompt = worker_prompt(config, notes);
+    let label = format!("worker-{}", subprocess_id);
+    let (stdout, _stderr) = invoke_streamed(&label, &prompt, tool_chain, logger)?;
+    log_turn_usage(tool_chain, logger, &status);
     Ok(stdout)
 }
 
@@ -597,12 +1252,8 @@ fn run_stop_confirmation_turn_parallel(
         prompt.push('\n');
     }
 
-    let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt, logger)?;
-    stream_outputs(
-        &format!("confirmation-{}", subprocess_id),
-        &stdout,
-        &stderr,
-        logger,
-    );
+    let label = format!("confirmation-{}", subprocess_id);
+    let (stdout, _stderr) = invoke_streamed(&label, &prompt, tool_chain, logger)?;
+    log_turn_usage(tool_chain, logger, &status);
     Ok(stdout)
 }
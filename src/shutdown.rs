@@ -0,0 +1,71 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signal handling.
+//!
+//! SIGINT, SIGTERM and SIGHUP drive the same two-step shutdown. The first
+//! signal raises the shutdown flag, so the run stops once the current turn
+//! is done. The second forces an exit: running tools' process groups get
+//! SIGTERM, then SIGKILL after a grace period, and work items still checked
+//! out by this run are restored before afkcode exits.
+
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::constants::SHUTDOWN_GRACE_SECONDS;
+use crate::gimme;
+use crate::llm;
+
+/// Held for the whole of a forced exit
+static FORCING: Mutex<()> = Mutex::new(());
+
+/// Install the handler for SIGINT, SIGTERM and SIGHUP
+pub fn install(shutdown_flag: Arc<AtomicBool>) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if !shutdown_flag.swap(true, Ordering::SeqCst) {
+            println!(
+                "\nInterrupted. Finishing current turn... (interrupt again to stop running tools and exit)"
+            );
+            return;
+        }
+        force_exit();
+    })
+    .context("Error setting signal handler")
+}
+
+/// Take down running tools, hand back checked-out work items, and exit
+fn force_exit() -> ! {
+    let _forcing = FORCING.lock().unwrap_or_else(PoisonError::into_inner);
+    println!(
+        "\nInterrupted again. Stopping running tools (killed after {}s)...",
+        SHUTDOWN_GRACE_SECONDS
+    );
+    let stopped = llm::terminate_running_tools(Duration::from_secs(SHUTDOWN_GRACE_SECONDS));
+    if stopped > 0 {
+        println!("Stopped {} running tool process(es)", stopped);
+    }
+    let restored = gimme::checkout::restore_outstanding();
+    if restored > 0 {
+        println!("Restored {} checked-out work item(s)", restored);
+    }
+    std::process::exit(130);
+}
+
+/// Wait for a forced exit in progress, so the main thread can't return
+/// (ending the process) before it has finished cleaning up
+pub fn wait_for_forced_exit() {
+    drop(FORCING.lock());
+}
@@ -0,0 +1,284 @@
+// Copyright (c) 2025 Sean McNamara <smcnam@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stall detection.
+//!
+//! After each worker turn the loop checks what the turn itself did: the
+//! commits attributed to it, the files it changed and left uncommitted, and
+//! the markers of the checklist items the worker answers for. A turn that
+//! moves none of them made no progress. Once `threshold` turns in a row have
+//! made none, the [`StallMonitor`] escalates one rung up the ladder, and one
+//! more after every further `threshold`; any progress starts it over.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::gimme::{parser, ChecklistItem};
+use crate::git::TurnChanges;
+
+/// One rung of the escalation ladder
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StallAction {
+    /// Make the next worker tool the primary one
+    SwitchTool,
+    /// Tell the worker it appears stuck on its next turn
+    Nudge,
+    /// Run a controller turn to groom the checklist
+    Groom,
+    /// Mark the instance's checked-out items BLOCKED
+    Block,
+    /// End the loop
+    Stop,
+}
+
+impl fmt::Display for StallAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StallAction::SwitchTool => "switch-tool",
+            StallAction::Nudge => "nudge",
+            StallAction::Groom => "groom",
+            StallAction::Block => "block",
+            StallAction::Stop => "stop",
+        })
+    }
+}
+
+/// The full ladder, in order
+pub const DEFAULT_STALL_LADDER: [StallAction; 5] = [
+    StallAction::SwitchTool,
+    StallAction::Nudge,
+    StallAction::Groom,
+    StallAction::Block,
+    StallAction::Stop,
+];
+
+/// When a worker counts as stalled and what to do about it
+#[derive(Clone, Debug, Default)]
+pub struct StallPolicy {
+    /// Turns in a row without progress before each escalation (0 disables)
+    pub threshold: usize,
+    /// Actions taken in turn; the last repeats once the ladder runs out
+    pub ladder: Vec<StallAction>,
+}
+
+impl StallPolicy {
+    pub fn enabled(&self) -> bool {
+        self.threshold > 0 && !self.ladder.is_empty()
+    }
+}
+
+/// Markers of the checklist items a worker answers for: its checked-out
+/// items, found again by their content, with everything indented under them,
+/// or else every item in the checklist (the file itself, or every AGENTS.md
+/// under `checklist_dir`)
+pub fn watched_markers(
+    work_items: &[ChecklistItem],
+    checklist: &Path,
+    checklist_dir: Option<&Path>,
+) -> Vec<String> {
+    if work_items.is_empty() {
+        let items = match checklist_dir {
+            Some(dir) => parser::parse_all(dir),
+            None => parser::parse_file(checklist),
+        };
+        return items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| format!("{} {}", item.marker, item.content))
+            .collect();
+    }
+
+    let mut files: Vec<&Path> = work_items.iter().map(|item| item.file.as_path()).collect();
+    files.sort();
+    files.dedup();
+    let mut markers = Vec::new();
+    for file in files {
+        let content = fs::read_to_string(file).unwrap_or_default();
+        let lines: Vec<&str> = content.lines().collect();
+        for item in parser::parse_file(file).unwrap_or_default() {
+            if work_items.iter().any(|own| own.content == item.content) {
+                markers.push(item_block(&lines, item.line - 1));
+            }
+        }
+    }
+    markers
+}
+
+/// The item on line `start` and the lines indented under it
+fn item_block(lines: &[&str], start: usize) -> String {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let depth = indent(lines[start]);
+    let mut end = start + 1;
+    while end < lines.len() && (lines[end].trim().is_empty() || indent(lines[end]) > depth) {
+        end += 1;
+    }
+    lines[start..end].join("\n").trim_end().to_string()
+}
+
+/// Counts turns without progress and decides when to escalate
+#[derive(Debug)]
+pub struct StallMonitor {
+    policy: StallPolicy,
+    markers: Vec<String>,
+    idle_turns: usize,
+    rung: usize,
+}
+
+impl StallMonitor {
+    /// Start watching from `markers`, the watched items before the first turn
+    pub fn new(policy: StallPolicy, markers: Vec<String>) -> Self {
+        Self {
+            policy,
+            markers,
+            idle_turns: 0,
+            rung: 0,
+        }
+    }
+
+    /// Turns in a row without progress so far
+    pub fn idle_turns(&self) -> usize {
+        self.idle_turns
+    }
+
+    /// Record what a turn changed and the watched markers after it,
+    /// returning the action to take if the worker is stalled
+    pub fn observe(&mut self, changes: &TurnChanges, markers: Vec<String>) -> Option<StallAction> {
+        let progressed = !changes.commits.is_empty()
+            || !changes.left_behind.is_empty()
+            || markers != self.markers;
+        self.markers = markers;
+        if progressed {
+            self.idle_turns = 0;
+            self.rung = 0;
+            return None;
+        }
+        self.idle_turns += 1;
+        if !self.policy.enabled() || !self.idle_turns.is_multiple_of(self.policy.threshold) {
+            return None;
+        }
+        let ladder = &self.policy.ladder;
+        let action = ladder[self.rung.min(ladder.len() - 1)];
+        self.rung += 1;
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::Commit;
+    use tempfile::TempDir;
+
+    fn markers(markers: &[&str]) -> Vec<String> {
+        markers.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn test_escalates_every_threshold_turns_without_progress() {
+        let policy = StallPolicy {
+            threshold: 2,
+            ladder: vec![StallAction::Nudge, StallAction::Stop],
+        };
+        let idle = TurnChanges::default();
+        let mut monitor = StallMonitor::new(policy, markers(&["[ ] Task"]));
+
+        assert_eq!(monitor.observe(&idle, markers(&["[ ] Task"])), None);
+        assert_eq!(
+            monitor.observe(&idle, markers(&["[ ] Task"])),
+            Some(StallAction::Nudge)
+        );
+        assert_eq!(monitor.idle_turns(), 2);
+
+        // A checklist change is progress and starts the ladder over
+        let ticked = || markers(&["[x] Task"]);
+        assert_eq!(monitor.observe(&idle, ticked()), None);
+        assert_eq!(monitor.observe(&idle, ticked()), None);
+        assert_eq!(monitor.observe(&idle, ticked()), Some(StallAction::Nudge));
+        assert_eq!(monitor.observe(&idle, ticked()), None);
+        assert_eq!(monitor.observe(&idle, ticked()), Some(StallAction::Stop));
+        // The last rung repeats
+        monitor.observe(&idle, ticked());
+        assert_eq!(monitor.observe(&idle, ticked()), Some(StallAction::Stop));
+
+        // So are a commit and a changed file the turn left uncommitted
+        let mut monitor = StallMonitor::new(
+            StallPolicy {
+                threshold: 1,
+                ladder: DEFAULT_STALL_LADDER.to_vec(),
+            },
+            ticked(),
+        );
+        let committed = TurnChanges {
+            commits: vec![Commit {
+                sha: "a1b2c3d4".to_string(),
+                subject: "Add parser".to_string(),
+                auto: false,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(monitor.observe(&committed, ticked()), None);
+        let edited = TurnChanges {
+            left_behind: [("src/lib.rs".to_string(), Some("e5f6".to_string()))].into(),
+            ..Default::default()
+        };
+        assert_eq!(monitor.observe(&edited, ticked()), None);
+        assert_eq!(
+            monitor.observe(&idle, ticked()),
+            Some(StallAction::SwitchTool)
+        );
+    }
+
+    #[test]
+    fn test_disabled_policy_never_escalates() {
+        let mut monitor = StallMonitor::new(StallPolicy::default(), Vec::new());
+        for _ in 0..5 {
+            assert_eq!(monitor.observe(&TurnChanges::default(), Vec::new()), None);
+        }
+    }
+
+    #[test]
+    fn test_watched_markers_follow_own_items() {
+        let temp = TempDir::new().unwrap();
+        let file = temp.path().join("AGENTS.md");
+        fs::write(&file, "- [ip:a1b2] Mine\n- [ip:c3d4] Theirs\n").unwrap();
+        let items = parser::parse_file(&file).unwrap();
+        let own = &items[..1];
+
+        assert_eq!(
+            watched_markers(own, &file, None),
+            markers(&["- [ip:a1b2] Mine"])
+        );
+        assert_eq!(
+            watched_markers(&[], &file, None),
+            markers(&["[ip:a1b2] Mine", "[ip:c3d4] Theirs"])
+        );
+
+        // Another instance's item moving leaves this one's markers alone;
+        // a new sub-item under its own changes them
+        fs::write(&file, "- [ip:a1b2] Mine\n- [x] Theirs\n").unwrap();
+        assert_eq!(
+            watched_markers(own, &file, None),
+            markers(&["- [ip:a1b2] Mine"])
+        );
+        fs::write(&file, "- [ip:a1b2] Mine\n  - [x] Parse input\n\n- [x] Theirs\n").unwrap();
+        assert_eq!(
+            watched_markers(own, &file, None),
+            markers(&["- [ip:a1b2] Mine\n  - [x] Parse input"])
+        );
+    }
+}
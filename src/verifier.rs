@@ -26,7 +26,7 @@ use crate::checklist::scanner::scan_all_checklists;
 use crate::llm::LlmToolChain;
 use crate::logger::Logger;
 use crate::prompts::DEFAULT_VERIFIER_PROMPT;
-use crate::runner::{log_message, stream_outputs};
+use crate::runner::{log_message, invoke_streamed};
 
 /// Configuration for the verifier phase.
 #[derive(Debug, Clone)]
@@ -82,8 +82,7 @@ pub fn run_verifier(
 
     // Run the verifier LLM
     log_message(logger, "Running verifier LLM...");
-    let (stdout, stderr) = tool_chain.invoke_with_fallback(&prompt, logger)?;
-    stream_outputs("verifier", &stdout, &stderr, logger);
+    let (_stdout, _stderr) = invoke_streamed("verifier", &prompt, tool_chain, logger)?;
 
     // Count incomplete items after verification
     let after_scan = scan_all_checklists(&config.checklist_dir)?;
@@ -0,0 +1,295 @@
+use assert_cmd::Command;
+use predicates::str::contains;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tempfile::tempdir;
+
+const COMPLETION_TOKEN: &str = "__ALL_TASKS_COMPLETE__";
+
+/// A recorded request: request line plus body.
+type Recorded = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Serve the given (status, body) responses in order, one per connection,
+/// and return the base URL plus the recorded requests.
+fn start_stub_server(responses: Vec<(u16, String)>) -> (String, Recorded) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let recorded: Recorded = Arc::new(Mutex::new(Vec::new()));
+    let recorded_clone = recorded.clone();
+
+    thread::spawn(move || {
+        for (status, body) in responses {
+            let (stream, _) = match listener.accept() {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0usize;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                let lower = header.to_lowercase();
+                if let Some(value) = lower.strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut request_body = vec![0u8; content_length];
+            reader.read_exact(&mut request_body).unwrap();
+            recorded_clone.lock().unwrap().push((
+                request_line.trim().to_string(),
+                String::from_utf8_lossy(&request_body).to_string(),
+            ));
+
+            let response = format!(
+                "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let mut stream = reader.into_inner();
+            stream.write_all(response.as_bytes()).unwrap();
+            stream.flush().unwrap();
+        }
+    });
+
+    (format!("http://{}/v1", addr), recorded)
+}
+
+fn chat_completion(content: &str) -> String {
+    serde_json::json!({
+        "choices": [{ "message": { "role": "assistant", "content": content } }]
+    })
+    .to_string()
+}
+
+fn init_checklist(workdir: &Path, binary: &Path, name: &str) {
+    Command::new(binary)
+        .arg("init")
+        .arg(name)
+        .arg("--title")
+        .arg("Test Project")
+        .current_dir(workdir)
+        .assert()
+        .success();
+}
+
+#[test]
+fn openai_backend_drives_worker_loop() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let token_reply = format!("{}\n", COMPLETION_TOKEN);
+    let (base_url, recorded) = start_stub_server(vec![
+        (200, chat_completion(&token_reply)),
+        (200, chat_completion(&token_reply)),
+    ]);
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "openai_base_url = \"{}\"\nopenai_model = \"local-model\"\n",
+            base_url
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("openai")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(workdir.join("openai.log"))
+        .current_dir(workdir)
+        .env_remove("OPENAI_API_KEY")
+        .assert()
+        .success()
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let requests = recorded.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].0.starts_with("POST /v1/chat/completions"));
+    assert!(requests[0].1.contains("\"model\":\"local-model\""));
+}
+
+#[test]
+fn openai_intent_check_sends_no_unasked_reasoning_effort() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    // Controller turn, then the intent check of its stop token
+    let token_reply = format!("{}\n", COMPLETION_TOKEN);
+    let (base_url, recorded) = start_stub_server(vec![
+        (200, chat_completion(&token_reply)),
+        (200, chat_completion(&token_reply)),
+    ]);
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "openai_base_url = \"{}\"\nopenai_model = \"gpt-4o\"\n",
+            base_url
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--mode")
+        .arg("controller")
+        .arg("--tools")
+        .arg("openai")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(workdir.join("openai.log"))
+        .current_dir(workdir)
+        .env_remove("OPENAI_API_KEY")
+        .assert()
+        .success()
+        .stdout(contains("LLM confirmed intentional completion."));
+
+    let requests = recorded.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    for (_, body) in requests.iter() {
+        assert!(!body.contains("reasoning_effort"), "{}", body);
+    }
+}
+
+#[test]
+fn openai_backend_429_is_treated_as_rate_limit() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let (base_url, _recorded) = start_stub_server(vec![(
+        429,
+        r#"{"error":{"message":"slow down"}}"#.to_string(),
+    )]);
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "openai_base_url = \"{}\"\nopenai_model = \"local-model\"\n",
+            base_url
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("openai")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(workdir.join("openai.log"))
+        .current_dir(workdir)
+        .assert()
+        .failure()
+        .stdout(contains("Rate limit detected for openai"));
+}
+
+fn warp_config(base_url: &str) -> String {
+    format!(
+        "warp_api_base = \"{}\"\nwarp_api_key = \"test-key\"\nwarp_poll_seconds = 0\n",
+        base_url
+    )
+}
+
+#[test]
+fn warp_backend_returns_task_output() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let task_id = r#"{"task_id":"task-1","state":"QUEUED"}"#.to_string();
+    let done = serde_json::json!({
+        "state": "SUCCEEDED",
+        "session_link": "https://app.warp.dev/session/1",
+        "output": format!("All items done.\n{}", COMPLETION_TOKEN),
+    })
+    .to_string();
+    let (base_url, recorded) = start_stub_server(vec![
+        (200, task_id.clone()),
+        (200, r#"{"state":"INPROGRESS"}"#.to_string()),
+        (200, done.clone()),
+        (200, task_id),
+        (200, done),
+    ]);
+
+    fs::write(workdir.join("afkcode.toml"), warp_config(&base_url)).unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("warp")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(workdir.join("warp.log"))
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let requests = recorded.lock().unwrap();
+    assert_eq!(requests.len(), 5);
+    assert!(requests[0].0.starts_with("POST /v1/agent/run"));
+    assert!(requests[1].0.starts_with("GET /v1/agent/tasks/task-1"));
+}
+
+#[test]
+fn warp_backend_429_is_treated_as_rate_limit() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let (base_url, _recorded) = start_stub_server(vec![
+        (200, r#"{"task_id":"task-1"}"#.to_string()),
+        (429, r#"{"error":"too many requests"}"#.to_string()),
+    ]);
+
+    fs::write(workdir.join("afkcode.toml"), warp_config(&base_url)).unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("warp")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(workdir.join("warp.log"))
+        .current_dir(workdir)
+        .assert()
+        .failure()
+        .stdout(contains("Rate limit detected for warp"));
+}
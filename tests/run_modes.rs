@@ -291,3 +291,1042 @@ fn controller_mode_backwards_compatible() {
     let log_contents = fs::read_to_string(log_path).unwrap();
     assert!(log_contents.contains("Running controller prompt"));
 }
+
+#[test]
+fn custom_tool_from_config_drives_worker_loop() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let responses: Vec<String> = vec![
+        format!("{token}\n", token = COMPLETION_TOKEN),
+        format!("{token}\n", token = COMPLETION_TOKEN),
+    ];
+    let response_refs: Vec<&str> = responses.iter().map(|s| s.as_str()).collect();
+    let llm_dir = setup_fake_codex(workdir, &response_refs).unwrap();
+    let script = workdir.join("bin").join("codex");
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "[tool.mytool]\ncommand = \"{}\"\nargs = [\"--headless\"]\nprompt_mode = \"stdin\"\n",
+            script.display()
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let log_path = workdir.join("custom.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("mytool")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .env("FAKE_LLM_DIR", &llm_dir)
+        .assert()
+        .success()
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let counter = fs::read_to_string(llm_dir.join("counter")).unwrap();
+    assert_eq!(counter.trim(), "2");
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("Using LLM tool: mytool"));
+}
+
+#[test]
+fn worker_output_streams_between_header_and_footer() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let responses: Vec<String> = vec![
+        format!("first line of work\n{token}\n", token = COMPLETION_TOKEN),
+        format!("{token}\n", token = COMPLETION_TOKEN),
+    ];
+    let response_refs: Vec<&str> = responses.iter().map(|s| s.as_str()).collect();
+    let llm_dir = setup_fake_codex(workdir, &response_refs).unwrap();
+    let bin_dir = workdir.join("bin");
+    let fake_path = prepend_path(&bin_dir);
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let log_path = workdir.join("stream.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("codex")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .env("PATH", fake_path)
+        .env("FAKE_LLM_DIR", &llm_dir)
+        .assert()
+        .success()
+        .stdout(contains("first line of work"));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    let header = log_contents.find("--- WORKER OUTPUT ---").unwrap();
+    let line = log_contents.find("first line of work").unwrap();
+    let footer = log_contents.find("--- END WORKER OUTPUT ---").unwrap();
+    assert!(header < line && line < footer);
+}
+
+#[test]
+fn hung_tool_times_out_and_falls_back() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let responses: Vec<String> = vec![
+        format!("{token}\n", token = COMPLETION_TOKEN),
+        format!("{token}\n", token = COMPLETION_TOKEN),
+    ];
+    let response_refs: Vec<&str> = responses.iter().map(|s| s.as_str()).collect();
+    let llm_dir = setup_fake_codex(workdir, &response_refs).unwrap();
+    let bin_dir = workdir.join("bin");
+    let fake_path = prepend_path(&bin_dir);
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.hang]\ncommand = \"sh\"\nargs = [\"-c\", \"sleep 60\"]\nmax_retries = 1\nretry_delay_seconds = 0\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let log_path = workdir.join("timeout.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("hang,codex")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--timeout-seconds")
+        .arg("1")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .env("PATH", fake_path)
+        .env("FAKE_LLM_DIR", &llm_dir)
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .success()
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("Error invoking hang"));
+    assert!(log_contents.contains("wall-clock limit"));
+    assert!(log_contents.contains("Retrying hang in 0.0s (retry 1/1)."));
+    assert!(log_contents.contains("Switching to fallback tool: codex"));
+}
+
+#[test]
+fn second_signal_stops_running_tool_and_exits() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.sleeper]\ncommand = \"sh\"\nargs = [\"-c\", \"echo $$ > tool.pid; exec sleep 60\"]\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let mut afkcode = std::process::Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("sleeper")
+        .arg("--log-file")
+        .arg(workdir.join("signal.log"))
+        .current_dir(workdir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let pid_file = workdir.join("tool.pid");
+    let started = std::time::Instant::now();
+    let tool_pid = loop {
+        if let Some(pid) = fs::read_to_string(&pid_file)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<libc::pid_t>().ok())
+        {
+            break pid;
+        }
+        assert!(started.elapsed().as_secs() < 20, "tool never started");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+
+    // A supervisor's SIGTERM finishes the turn; a SIGHUP after it forces the exit
+    let afkcode_pid = afkcode.id() as libc::pid_t;
+    unsafe { libc::kill(afkcode_pid, libc::SIGTERM) };
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    unsafe { libc::kill(afkcode_pid, libc::SIGHUP) };
+
+    let started = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = afkcode.try_wait().unwrap() {
+            break status;
+        }
+        assert!(started.elapsed().as_secs() < 20, "afkcode did not exit");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    assert_eq!(status.code(), Some(130));
+
+    let mut stdout = String::new();
+    std::io::Read::read_to_string(&mut afkcode.stdout.take().unwrap(), &mut stdout).unwrap();
+    assert!(stdout.contains("Stopped 1 running tool process(es)"));
+
+    // The orphaned tool is reaped by init once killed
+    let started = std::time::Instant::now();
+    while unsafe { libc::kill(tool_pid, 0) } == 0 {
+        assert!(started.elapsed().as_secs() < 10, "tool process survived");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn rate_limit_squelch_persists_across_runs() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let responses: Vec<String> = vec![
+        format!("{token}\n", token = COMPLETION_TOKEN),
+        format!("{token}\n", token = COMPLETION_TOKEN),
+        format!("{token}\n", token = COMPLETION_TOKEN),
+        format!("{token}\n", token = COMPLETION_TOKEN),
+    ];
+    let response_refs: Vec<&str> = responses.iter().map(|s| s.as_str()).collect();
+    let llm_dir = setup_fake_codex(workdir, &response_refs).unwrap();
+    let bin_dir = workdir.join("bin");
+    let fake_path = prepend_path(&bin_dir);
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.limited]\ncommand = \"sh\"\nargs = [\"-c\", \"echo 'Error: quota exceeded, retry after 600s' >&2; exit 1\"]\nrate_limit_patterns = [\"quota exceeded\"]\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let run = |log_name: &str| {
+        let log_path = workdir.join(log_name);
+        Command::new(binary)
+            .arg("run")
+            .arg("checklist.md")
+            .arg("--tools")
+            .arg("limited,codex")
+            .arg("--sleep-seconds")
+            .arg("0")
+            .arg("--log-file")
+            .arg(&log_path)
+            .current_dir(workdir)
+            .env("PATH", &fake_path)
+            .env("FAKE_LLM_DIR", &llm_dir)
+            .assert()
+            .success();
+        fs::read_to_string(log_path).unwrap()
+    };
+
+    let first = run("first.log");
+    assert!(first.contains("Rate limit detected for limited"));
+    assert!(first.contains("(600s, from reset hint)"));
+    let state = fs::read_to_string(workdir.join(".afkcode").join("ratelimits.json")).unwrap();
+    assert!(state.contains("\"limited\""));
+
+    let second = run("second.log");
+    assert!(second.contains("limited is rate limited until"));
+    assert!(!second.contains("Using LLM tool: limited"));
+}
+
+#[test]
+fn cost_budget_stops_worker_loop() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    // Never emits the stop token; each turn reports $0.60
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.metered]\ncommand = \"sh\"\nargs = [\"-c\", \"cat > /dev/null; echo '{\\\"type\\\":\\\"result\\\",\\\"total_cost_usd\\\":0.6,\\\"usage\\\":{\\\"input_tokens\\\":100,\\\"output_tokens\\\":20}}'\"]\nprompt_mode = \"stdin\"\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let log_path = workdir.join("budget.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("metered")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--max-cost")
+        .arg("1.0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Usage: 240 tokens (in 200, out 40), $1.2000 over 2 invocation(s)"));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("mode=worker iteration=1 turn=normal usage: 120 tokens"));
+    assert!(log_contents.contains("Budget exhausted: cost $1.2000 reached max_cost $1.00"));
+    assert!(!log_contents.contains("iteration=3"));
+}
+
+#[test]
+fn run_limits_stop_controller_loop() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    // Never emits the stop token; counts its invocations
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.counter]\ncommand = \"sh\"\nargs = [\"-c\", \"cat > /dev/null; echo turn >> calls; echo working\"]\nprompt_mode = \"stdin\"\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--mode")
+        .arg("controller")
+        .arg("--tools")
+        .arg("counter")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--max-iterations")
+        .arg("2")
+        .arg("--log-file")
+        .arg(workdir.join("limits.log"))
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Running worker prompt..."))
+        .stdout(contains("Run limit reached: 2 iteration(s) completed. Stopping."))
+        .stdout(contains("Run: 2 iteration(s) in 0h00m"));
+
+    // Only worker turns count: controller, worker, controller, worker
+    let calls = fs::read_to_string(workdir.join("calls")).unwrap();
+    assert_eq!(calls.lines().count(), 4);
+
+    // A deadline that has already passed stops the run before any turn
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("counter")
+        .arg("--deadline")
+        .arg("0s")
+        .arg("--log-file")
+        .arg(workdir.join("limits.log"))
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Run limit reached: deadline"))
+        .stdout(contains("Run: 0 iteration(s)"));
+
+    let calls = fs::read_to_string(workdir.join("calls")).unwrap();
+    assert_eq!(calls.lines().count(), 4);
+}
+
+#[test]
+fn stalled_worker_is_nudged_then_stopped() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    // Saves each prompt and changes nothing
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.idle]\ncommand = \"sh\"\nargs = [\"-c\", \"n=$(ls prompts 2>/dev/null | wc -l); mkdir -p prompts; cat > prompts/$n; echo thinking\"]\nprompt_mode = \"stdin\"\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("idle")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--stall-threshold")
+        .arg("1")
+        .arg("--stall-ladder")
+        .arg("nudge,block,stop")
+        .arg("--log-file")
+        .arg(workdir.join("stall.log"))
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("No progress in 1 iteration(s); escalating: nudge"))
+        .stdout(contains("No checked-out work item to mark BLOCKED"))
+        .stdout(contains("No progress in 3 iteration(s); escalating: stop"))
+        .stdout(contains("Worker stalled. Stopping."));
+
+    let prompts = workdir.join("prompts");
+    assert_eq!(fs::read_dir(&prompts).unwrap().count(), 3);
+    let first = fs::read_to_string(prompts.join("0")).unwrap();
+    let second = fs::read_to_string(prompts.join("1")).unwrap();
+    let third = fs::read_to_string(prompts.join("2")).unwrap();
+    assert!(!first.contains("You appear to be stuck"));
+    assert!(second.starts_with("You appear to be stuck"));
+    assert!(!third.contains("You appear to be stuck"));
+}
+
+#[test]
+fn stalled_instance_escalates_while_another_progresses() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    // The instance assigned "Parse input" adds a sub-item every turn; the one
+    // assigned "Write docs" changes nothing
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.split]\ncommand = \"sh\"\nargs = [\"-c\", \"case $(cat) in *'- Parse input (from'*) sed -i '/Parse input/a\\\\  - [x] step' AGENTS.md;; esac; echo working\"]\nprompt_mode = \"stdin\"\n",
+    )
+    .unwrap();
+    fs::write(workdir.join("AGENTS.md"), "# Tasks\n\n- [ ] Parse input\n- [ ] Write docs\n").unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    let output = Command::new(binary)
+        .timeout(std::time::Duration::from_secs(30))
+        .arg("run")
+        .arg("--checklist-dir")
+        .arg(".")
+        .arg("--tools")
+        .arg("split")
+        .arg("--num-instances")
+        .arg("2")
+        .arg("--warmup-delay")
+        .arg("0")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--stall-threshold")
+        .arg("1")
+        .arg("--stall-ladder")
+        .arg("stop")
+        .arg("--max-iterations")
+        .arg("4")
+        .arg("--log-file")
+        .arg("stall.log")
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8_lossy(&output);
+
+    // The other instance's edits to the shared checklist don't count
+    let stalled: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.contains("No progress in 1 iteration(s); escalating: stop"))
+        .collect();
+    assert_eq!(stalled.len(), 1, "{}", stdout);
+    let instance = &stalled[0][..stalled[0].find(']').unwrap() + 1];
+    assert!(stdout.contains(&format!("{} Worker stalled. Stopping.", instance)));
+    assert!(stdout.contains("Run limit reached: 4 iteration(s) completed."));
+
+    let agents = fs::read_to_string(workdir.join("AGENTS.md")).unwrap();
+    assert_eq!(agents.matches("  - [x] step").count(), 3, "{}", agents);
+}
+
+#[test]
+fn failing_gate_is_fed_back_and_blocks_stop_token() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    // Saves each prompt and always claims completion; fixes the gate on its
+    // second turn
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "gate_command = \"test -f fixed.txt || {{ echo building; echo missing fixed.txt; exit 1; }}\"\n\n\
+             [tool.eager]\ncommand = \"sh\"\nargs = [\"-c\", \"n=$(ls prompts 2>/dev/null | wc -l); mkdir -p prompts; cat > prompts/$n; if [ $n -eq 1 ]; then touch fixed.txt; fi; echo {}\"]\nprompt_mode = \"stdin\"\n",
+            COMPLETION_TOKEN
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("eager")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--max-iterations")
+        .arg("5")
+        .arg("--log-file")
+        .arg(workdir.join("gate.log"))
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stderr(contains("Gate exited with status 1:\nbuilding\nmissing fixed.txt"))
+        .stdout(contains("Ignoring stop token: the gate is failing."))
+        .stdout(contains("Gate passed in"))
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    // The gate runs before the first turn too, so both worker turns are told
+    // to fix it; the confirmation turn isn't
+    let prompts = workdir.join("prompts");
+    assert_eq!(fs::read_dir(&prompts).unwrap().count(), 3);
+    for turn in ["0", "1"] {
+        let prompt = fs::read_to_string(prompts.join(turn)).unwrap();
+        assert!(prompt.starts_with("FIX THIS FIRST: the gate command `test -f fixed.txt"));
+        assert!(prompt.contains("exited with status 1 after the last turn"));
+        assert!(prompt.contains("```\nbuilding\nmissing fixed.txt\n```"));
+    }
+    let confirmation = fs::read_to_string(prompts.join("2")).unwrap();
+    assert!(!confirmation.contains("FIX THIS FIRST"));
+}
+
+#[test]
+fn worker_commits_are_recorded_and_leftovers_auto_committed() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    for args in [
+        &["init", "--quiet"][..],
+        &["config", "user.email", "test@example.com"],
+        &["config", "user.name", "Test User"],
+    ] {
+        Command::new("git").args(args).current_dir(workdir).assert().success();
+    }
+
+    // Commits on its first turn; leaves a file behind on its second
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.committer]\ncommand = \"sh\"\nargs = [\"-c\", \"cat > /dev/null; if [ -f a.txt ]; then echo $$ > b.txt; else echo a > a.txt && git add a.txt && git commit --quiet -m 'Add a'; fi; echo done\"]\nprompt_mode = \"stdin\"\n",
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+    Command::new("git").args(["add", "-A"]).current_dir(workdir).assert().success();
+    Command::new("git")
+        .args(["commit", "--quiet", "-m", "Initial"])
+        .current_dir(workdir)
+        .assert()
+        .success();
+    // Uncommitted work from before the run isn't the turns' to commit
+    fs::write(workdir.join("notes.txt"), "mine\n").unwrap();
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("committer")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--max-iterations")
+        .arg("2")
+        .arg("--auto-commit")
+        .arg("--log-file")
+        .arg("commits.log")
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(predicates::str::is_match(r"mode=worker iteration=1 turn=normal commits: [0-9a-f]{7} Add a\n").unwrap())
+        .stdout(
+            predicates::str::is_match(
+                r"mode=worker iteration=2 turn=normal commits: [0-9a-f]{7} afkcode: changes left uncommitted by iteration 2 \(auto\)",
+            )
+            .unwrap(),
+        )
+        .stdout(contains("Commits: 2 over 2 turn(s)"));
+
+    let log = std::process::Command::new("git")
+        .args(["log", "--format=%s"])
+        .current_dir(workdir)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&log.stdout),
+        "afkcode: changes left uncommitted by iteration 2\nAdd a\nInitial\n"
+    );
+    let auto_commit = std::process::Command::new("git")
+        .args(["show", "--name-only", "--format=", "HEAD"])
+        .current_dir(workdir)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&auto_commit.stdout), "b.txt\n");
+
+    // Without --auto-commit, leftovers are only reported: b.txt, dirty before
+    // the turn but rewritten by it, and not notes.txt. No sleep follows the
+    // last allowed turn.
+    fs::write(workdir.join("b.txt"), "mine\n").unwrap();
+    Command::new(binary)
+        .timeout(std::time::Duration::from_secs(10))
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("committer")
+        .arg("--max-iterations")
+        .arg("1")
+        .arg("--log-file")
+        .arg("commits.log")
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stderr(contains(
+            "Warning: mode=worker iteration=1 turn=normal left 1 uncommitted change(s): b.txt\n",
+        ))
+        .stdout(contains("mode=worker iteration=1 turn=normal commits: none"));
+}
+
+#[test]
+fn structured_output_checks_stop_token_in_final_message() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let noisy = format!(
+        concat!(
+            r#"{{"type":"system","subtype":"init","session_id":"sess-1"}}"#, "\n",
+            r#"{{"type":"assistant","message":{{"content":[{{"type":"tool_use","name":"Bash","input":{{"command":"grep -r {token} ."}}}}]}}}}"#, "\n",
+            r#"{{"type":"user","message":{{"content":[{{"type":"tool_result","content":"{token}"}}]}}}}"#, "\n",
+            r#"{{"type":"result","subtype":"success","is_error":false,"result":"Still working.","session_id":"sess-1"}}"#, "\n",
+        ),
+        token = COMPLETION_TOKEN
+    );
+    let done = format!(
+        r#"{{"type":"result","subtype":"success","is_error":false,"result":"All done.\n{token}","session_id":"sess-1"}}"#,
+        token = COMPLETION_TOKEN
+    );
+    let llm_dir = setup_fake_codex(workdir, &[&noisy, &done, &done]).unwrap();
+    let script = workdir.join("bin").join("codex");
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "[tool.agent]\ncommand = \"{}\"\nevent_format = \"claude\"\n",
+            script.display()
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let log_path = workdir.join("structured.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("agent")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .env("FAKE_LLM_DIR", &llm_dir)
+        .assert()
+        .success();
+
+    // The token in the first turn's tool output must not count
+    let counter = fs::read_to_string(llm_dir.join("counter")).unwrap();
+    assert_eq!(counter.trim(), "3");
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("-> Bash: {\"command\":\"grep -r"));
+    assert!(log_contents.contains("agent finished: 1 tool call(s), 0 error(s), session sess-1"));
+    assert!(!log_contents.contains("\"type\":\"system\""));
+}
+
+#[test]
+fn resumed_sessions_roll_over_after_max_turns() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let working = r#"{"type":"result","subtype":"success","is_error":false,"result":"Still working.","session_id":"s1"}"#;
+    let done = format!(
+        r#"{{"type":"result","subtype":"success","is_error":false,"result":"{token}","session_id":"s1"}}"#,
+        token = COMPLETION_TOKEN
+    );
+    let llm_dir = setup_fake_codex(workdir, &[working, working, &done, &done]).unwrap();
+    let script = workdir.join("bin").join("codex");
+
+    // Record each invocation's arguments, then answer like the fake codex
+    let wrapper = workdir.join("bin").join("agent");
+    fs::write(
+        &wrapper,
+        format!(
+            "#!/bin/bash\necho \"args:$*\" >> \"$FAKE_LLM_DIR/args\"\nexec \"{}\"\n",
+            script.display()
+        ),
+    )
+    .unwrap();
+    let mut perms = fs::metadata(&wrapper).unwrap().permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(&wrapper, perms).unwrap();
+
+    fs::write(
+        workdir.join("afkcode.toml"),
+        format!(
+            "[tool.agent]\ncommand = \"{}\"\nevent_format = \"claude\"\nresume_args = [\"--resume\", \"{{session_id}}\"]\n",
+            wrapper.display()
+        ),
+    )
+    .unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    let log_path = workdir.join("sessions.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("agent")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--resume-sessions")
+        .arg("--session-max-turns")
+        .arg("2")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .env("FAKE_LLM_DIR", &llm_dir)
+        .assert()
+        .success();
+
+    let args = fs::read_to_string(llm_dir.join("args")).unwrap();
+    let args: Vec<&str> = args.lines().collect();
+    assert_eq!(args, ["args:", "args:--resume s1", "args:", "args:--resume s1"]);
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("Resuming agent session s1"));
+    assert!(log_contents.contains("agent session s1 reached 2 turns; the next turn starts a fresh session."));
+}
+
+/// Scripted tools `flaky` (always rate limited) and `steady` (finishes the
+/// checklist's one item, then emits the stop token). Returns the checklist.
+fn setup_replay_tools(workdir: &Path, binary: &Path) -> PathBuf {
+    init_checklist(workdir, binary, "checklist.md");
+    let checklist = workdir.join("checklist.md");
+    let mut contents = fs::read_to_string(&checklist).unwrap();
+    contents.push_str("\n- [ ] Wire up the widget\n");
+    fs::write(&checklist, contents).unwrap();
+
+    fs::write(
+        workdir.join("flaky.toml"),
+        "[[step]]\nrate_limit = \"Rate limit reached, retry after 600s\"\n",
+    )
+    .unwrap();
+    fs::write(
+        workdir.join("steady.toml"),
+        format!(
+            r#"[[rule]]
+prompt = "previous response emitted the stop"
+stdout = "{token}"
+
+[[step]]
+stdout = "Implemented the widget."
+edits = [{{ path = "checklist.md", find = "- [ ] Wire up the widget\n", replace = "" }}]
+
+[[step]]
+stdout = "Checklist is empty.\n{token}"
+"#,
+            token = COMPLETION_TOKEN
+        ),
+    )
+    .unwrap();
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.flaky]\nreplay_script = \"flaky.toml\"\n\n[tool.steady]\nreplay_script = \"steady.toml\"\n",
+    )
+    .unwrap();
+    checklist
+}
+
+#[test]
+fn replay_scripts_drive_worker_loop_without_agents() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    let checklist = setup_replay_tools(workdir, binary);
+
+    let log_path = workdir.join("replay.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("flaky,steady")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Implemented the widget."))
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    assert!(!fs::read_to_string(&checklist).unwrap().contains("Wire up the widget"));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("Rate limit detected for flaky"));
+    assert!(log_contents.contains("mode=worker iteration=3 turn=confirmation"));
+}
+
+#[test]
+fn recorded_cassette_replays_without_tools() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    let checklist = setup_replay_tools(workdir, binary);
+    let original = fs::read_to_string(&checklist).unwrap();
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("flaky,steady")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--record-cassette")
+        .arg("cassettes/run.jsonl")
+        .arg("--log-file")
+        .arg(workdir.join("record.log"))
+        .current_dir(workdir)
+        .assert()
+        .success();
+
+    let cassette = fs::read_to_string(workdir.join("cassettes").join("run.jsonl")).unwrap();
+    let entries: Vec<serde_json::Value> = cassette
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let summary: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|e| (e["tool"].as_str().unwrap(), e["classification"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("flaky", "rate_limited"),
+            ("steady", "ok"),
+            ("steady", "ok"),
+            ("steady", "ok"),
+        ]
+    );
+    assert_eq!(entries[1]["stdout"], "Implemented the widget.");
+    assert!(entries[3]["prompt"].as_str().unwrap().contains("previous response emitted the stop"));
+
+    // Without the scripts or their config, only the cassette can answer
+    fs::remove_file(workdir.join("afkcode.toml")).unwrap();
+    fs::remove_file(workdir.join("flaky.toml")).unwrap();
+    fs::remove_file(workdir.join("steady.toml")).unwrap();
+    fs::write(&checklist, original).unwrap();
+
+    let log_path = workdir.join("replay.log");
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--replay-cassette")
+        .arg("cassettes/run.jsonl")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Replaying 4 exchange(s) from cassettes/run.jsonl with tools: flaky,steady"))
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    assert!(log_contents.contains("Rate limit detected for flaky"));
+    assert!(log_contents.contains("mode=worker iteration=3 turn=confirmation"));
+}
+
+#[test]
+fn round_robin_strategy_alternates_tools() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    fs::write(
+        workdir.join("first.toml"),
+        format!(
+            "[[rule]]\nprompt = \"previous response emitted the stop\"\nstdout = \"{token}\"\n\n[[step]]\nstdout = \"first tool turn\"\n",
+            token = COMPLETION_TOKEN
+        ),
+    )
+    .unwrap();
+    fs::write(
+        workdir.join("second.toml"),
+        format!("[[step]]\nstdout = \"{token}\"\n", token = COMPLETION_TOKEN),
+    )
+    .unwrap();
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "tool_strategy = \"round-robin\"\n\n[tool.first]\nreplay_script = \"first.toml\"\n\n[tool.second]\nreplay_script = \"second.toml\"\n",
+    )
+    .unwrap();
+
+    let log_path = workdir.join("round_robin.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("first,second")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    let used: Vec<&str> = log_contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("Using LLM tool: "))
+        .collect();
+    assert_eq!(used, ["first", "second", "first"]);
+}
+
+#[test]
+fn roles_route_confirmation_to_their_own_tool() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+    init_checklist(workdir, binary, "checklist.md");
+
+    fs::write(
+        workdir.join("worker.toml"),
+        format!(
+            "when_exhausted = \"repeat_last\"\n\n[[step]]\nstdout = \"Done.\\n{token}\"\n",
+            token = COMPLETION_TOKEN
+        ),
+    )
+    .unwrap();
+    fs::write(
+        workdir.join("checker.toml"),
+        format!("[[step]]\nstdout = \"{token}\"\n", token = COMPLETION_TOKEN),
+    )
+    .unwrap();
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.builder]\nreplay_script = \"worker.toml\"\n\n[tool.checker]\nreplay_script = \"checker.toml\"\n\n[roles.confirmation]\ntools = \"checker\"\n",
+    )
+    .unwrap();
+
+    let log_path = workdir.join("roles.log");
+
+    Command::new(binary)
+        .arg("run")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("builder")
+        .arg("--sleep-seconds")
+        .arg("0")
+        .arg("--log-file")
+        .arg(&log_path)
+        .current_dir(workdir)
+        .assert()
+        .success()
+        .stdout(contains("Stop token confirmed; exiting."));
+
+    let log_contents = fs::read_to_string(log_path).unwrap();
+    let used: Vec<&str> = log_contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("Using LLM tool: "))
+        .collect();
+    assert_eq!(used, ["builder", "checker"]);
+}
+
+#[test]
+fn doctor_reports_checks_and_fails_on_problems() {
+    let temp = tempdir().unwrap();
+    let workdir = temp.path();
+
+    for args in [
+        &["init"][..],
+        &["config", "user.email", "test@example.com"],
+        &["config", "user.name", "Test User"],
+    ] {
+        Command::new("git")
+            .args(args)
+            .current_dir(workdir)
+            .assert()
+            .success();
+    }
+
+    let bin_dir = workdir.join("bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+    let script = bin_dir.join("fake-agent");
+    fs::write(
+        &script,
+        "#!/bin/bash\nif [[ \"${1:-}\" == \"--version\" ]]; then echo \"fake-agent 1.2.3\"; exit 0; fi\ncat > /dev/null\necho OK\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        workdir.join("afkcode.toml"),
+        "[tool.mytool]\ncommand = \"fake-agent\"\n",
+    )
+    .unwrap();
+    fs::write(workdir.join("checklist.md"), "# Tasks\n- [ ] One\n- [x] Two\n").unwrap();
+
+    let binary = assert_cmd::cargo::cargo_bin!("afkcode");
+
+    Command::new(binary)
+        .arg("doctor")
+        .arg("checklist.md")
+        .arg("--tools")
+        .arg("mytool,warp")
+        .current_dir(workdir)
+        .env("PATH", prepend_path(&bin_dir))
+        .env_remove("WARP_API_KEY")
+        .assert()
+        .failure()
+        .stdout(contains("mytool version    PASS    fake-agent 1.2.3"))
+        .stdout(contains("mytool auth       PASS    answered a test prompt"))
+        .stdout(contains("warp api key      FAIL    missing"))
+        .stdout(contains("checklist parses  PASS    2 items, 1 incomplete"))
+        .stdout(contains("git clean         FAIL"))
+        .stderr(contains("doctor check(s) failed"));
+}